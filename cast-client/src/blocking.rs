@@ -0,0 +1,320 @@
+//! Blocking facade over [`Client`] for callers without a tokio runtime.
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::prelude::*;
+use futures::sync::oneshot;
+use futures::{future, IntoFuture};
+use tokio::runtime::Runtime;
+
+use crate::channel::DEFAULT_MEDIA_RECEIVER_APP_ID;
+use crate::{
+    media, receiver, Client, ClientBuilder, CommandError, Error, Media, MediaConnection,
+    ReceiverConnection, Response, Status, DEFAULT_REQUEST_TIMEOUT,
+};
+
+/// App session the client is connected to, as reported by the status
+/// stream.
+#[derive(Debug, Default)]
+struct State {
+    connect: Option<ReceiverConnection>,
+    closed: bool,
+}
+
+#[derive(Debug, Default)]
+struct Session {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Session {
+    fn update(&self, status: &Status) {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+        match *status {
+            Status::Connected(ref connect) => state.connect = Some((**connect).clone()),
+            Status::SessionLost { .. } => state.connect = None,
+            Status::Disconnected { .. } => state.closed = true,
+            _ => return,
+        }
+        self.changed.notify_all();
+    }
+
+    fn close(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.closed = true;
+        }
+        self.changed.notify_all();
+    }
+
+    /// Block until the client connects to the app session with id
+    /// `session`, or to any app session if `session` is `None`.
+    fn wait(&self, session: Option<&str>, timeout: Duration) -> Result<ReceiverConnection, Error> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().map_err(|_| Error::Closed)?;
+        loop {
+            if state.closed {
+                return Err(Error::Closed);
+            }
+            match state.connect {
+                Some(ref connect) if session.map_or(true, |id| id == connect.session) => {
+                    return Ok(connect.clone());
+                }
+                _ => {}
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::Command(CommandError::Timeout));
+            }
+            state = self
+                .changed
+                .wait_timeout(state, deadline - now)
+                .map_err(|_| Error::Closed)?
+                .0;
+        }
+    }
+}
+
+/// Blocking client for a Cast device.
+///
+/// `SyncClient` runs a [`Client`] on a tokio runtime it owns. Each command
+/// blocks the calling thread until the device acknowledges it or the request
+/// timeout elapses. Status updates are delivered to the `std::sync::mpsc`
+/// receiver returned by [`SyncClient::connect`].
+///
+/// Requires the `blocking` feature.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct SyncClient {
+    runtime: Runtime,
+    client: Arc<Client>,
+    session: Arc<Session>,
+    timeout: Duration,
+}
+
+impl SyncClient {
+    /// Connect to the device at `addr`. Blocks until the connection is
+    /// established.
+    pub fn connect(addr: SocketAddr) -> Result<(Self, Receiver<Status>), Error> {
+        Self::connect_with(ClientBuilder::default(), addr)
+    }
+
+    /// Connect to the device at `addr` with a client configured by `builder`.
+    pub fn connect_with(
+        builder: ClientBuilder,
+        addr: SocketAddr,
+    ) -> Result<(Self, Receiver<Status>), Error> {
+        let timeout = builder.request_timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT);
+        let mut runtime = Runtime::new().map_err(Error::Io)?;
+        let (client, status, init) = builder.connect(addr);
+        runtime.block_on(init)?;
+        let session = Arc::new(Session::default());
+        let (tx, rx) = mpsc::channel();
+        let forward = {
+            let session = Arc::clone(&session);
+            status.for_each(move |status| {
+                session.update(&status);
+                // The caller may not be interested in status updates.
+                let _ = tx.send(status);
+                Ok(())
+            })
+        };
+        let closed = Arc::clone(&session);
+        runtime.spawn(forward.then(move |_| {
+            closed.close();
+            Ok(())
+        }));
+        let client = Self {
+            runtime,
+            client: Arc::new(client),
+            session,
+            timeout,
+        };
+        Ok((client, rx))
+    }
+
+    /// Issue a command from within the runtime and block until it resolves.
+    fn command<F, R>(&self, command: F) -> Result<R::Item, Error>
+    where
+        F: FnOnce(&Client) -> R + Send + 'static,
+        R: IntoFuture<Error = CommandError>,
+        R::Future: Send + 'static,
+        R::Item: Send + 'static,
+    {
+        let client = Arc::clone(&self.client);
+        let command = future::lazy(move || command(&client));
+        oneshot::spawn(command, &self.runtime.executor())
+            .wait()
+            .map_err(Error::from)
+    }
+
+    /// Launch the default media receiver app and block until the client is
+    /// connected to its session.
+    ///
+    /// Returns immediately if the client is already connected to the session
+    /// of the app, e.g. when the app was running before the launch.
+    pub fn launch_app(&self) -> Result<ReceiverConnection, Error> {
+        let response = self.command(Client::launch_app)?;
+        let session = session_id(&response, DEFAULT_MEDIA_RECEIVER_APP_ID);
+        self.session.wait(session, self.timeout)
+    }
+
+    /// Join the session of the running receiver app with id `app_id` and
+    /// block until the client is connected to it.
+    pub fn join(&self, app_id: &str) -> Result<ReceiverConnection, Error> {
+        let id = app_id.to_owned();
+        let response = self.command(move |client| client.join(&id))?;
+        let session = session_id(&response, app_id);
+        self.session.wait(session, self.timeout)
+    }
+
+    /// Load `media` into the app session `connect`. Returns the media session
+    /// once the device acknowledges the load.
+    pub fn load(
+        &self,
+        connect: &ReceiverConnection,
+        media: Media,
+    ) -> Result<MediaConnection, Error> {
+        let receiver = connect.clone();
+        let connect = connect.clone();
+        let response = self.command(move |client| client.load(&connect, media))?;
+        match response {
+            Response::MediaStatus(ref status) if !status.is_empty() => Ok(MediaConnection {
+                receiver,
+                session: status[0].media_session_id,
+            }),
+            _ => Err(Error::Protocol(
+                "load acknowledged without media".to_owned(),
+            )),
+        }
+    }
+
+    /// Resume playback of the media session `connect`.
+    pub fn play(&self, connect: &MediaConnection) -> Result<Response, Error> {
+        let connect = connect.clone();
+        self.command(move |client| client.play(&connect))
+    }
+
+    /// Pause playback of the media session `connect`.
+    pub fn pause(&self, connect: &MediaConnection) -> Result<Response, Error> {
+        let connect = connect.clone();
+        self.command(move |client| client.pause(&connect))
+    }
+
+    /// Stop playback and end the media session `connect`.
+    pub fn stop(&self, connect: &MediaConnection) -> Result<Response, Error> {
+        let connect = connect.clone();
+        self.command(move |client| client.stop(&connect))
+    }
+
+    /// Seek to `time` seconds into the media of the media session `connect`.
+    pub fn seek(&self, connect: &MediaConnection, time: f64) -> Result<Response, Error> {
+        let connect = connect.clone();
+        self.command(move |client| client.seek(&connect, time))
+    }
+
+    /// Set the volume of the device. See [`Client::set_device_volume`].
+    pub fn set_device_volume(
+        &self,
+        level: Option<f32>,
+        muted: Option<bool>,
+    ) -> Result<Response, Error> {
+        self.command(move |client| client.set_device_volume(level, muted))
+    }
+
+    /// Status of the device, including the running apps and the device
+    /// volume.
+    pub fn status(&self) -> Result<receiver::Status, Error> {
+        self.command(Client::receiver_status)
+    }
+
+    /// Status of every media session of the app session `connect`.
+    pub fn media_status(
+        &self,
+        connect: &ReceiverConnection,
+    ) -> Result<Vec<media::MediaStatus>, Error> {
+        let connect = connect.clone();
+        self.command(move |client| client.media_status(&connect))
+    }
+
+    /// Gracefully shut down the connection to the device and the runtime.
+    /// See [`Client::shutdown`].
+    pub fn shutdown(self) {
+        self.disconnect(Client::shutdown);
+    }
+
+    /// Close the connection to the device and the runtime, leaving the
+    /// receiver app and its media playing. See [`Client::close`].
+    pub fn close(self) {
+        self.disconnect(Client::close);
+    }
+
+    fn disconnect<F, R>(self, disconnect: F)
+    where
+        F: FnOnce(&mut Client) -> R + Send + 'static,
+        R: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        let Self {
+            runtime, client, ..
+        } = self;
+        if let Ok(mut client) = Arc::try_unwrap(client) {
+            let disconnect = future::lazy(move || disconnect(&mut client));
+            let _ = oneshot::spawn(disconnect, &runtime.executor()).wait();
+        }
+        let _ = runtime.shutdown_now().wait();
+    }
+}
+
+/// Id of the session of the app with id `app_id` in a receiver status
+/// response.
+fn session_id<'a>(response: &'a Response, app_id: &str) -> Option<&'a str> {
+    match *response {
+        Response::ReceiverStatus(ref status) => status
+            .applications
+            .iter()
+            .find(|app| app.app_id == app_id)
+            .map(|app| app.session_id.as_str()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::SyncClient;
+    use crate::media::PlayerState;
+    use crate::mock::fixture;
+    use crate::Response;
+
+    #[test]
+    fn plays_media_without_runtime() {
+        // The runtime only serves the mock receiver.
+        let (_runtime, receiver) = fixture::serve();
+
+        let (client, status) = SyncClient::connect(receiver.addr()).unwrap();
+        let connect = client.launch_app().unwrap();
+        let media = client.load(&connect, fixture::media(1)).unwrap();
+        match client.pause(&media).unwrap() {
+            Response::MediaStatus(ref status) => {
+                assert_eq!(status[0].player_state, PlayerState::Paused)
+            }
+            response => panic!("unexpected response: {:?}", response),
+        }
+        let device = client.status().unwrap();
+        assert!(device
+            .applications
+            .iter()
+            .any(|app| app.session_id == connect.session));
+        assert!(status.recv_timeout(Duration::from_secs(1)).is_ok());
+        // Launching the running app keeps its session.
+        let relaunched = client.launch_app().unwrap();
+        assert_eq!(relaunched.session, connect.session);
+        client.shutdown();
+        receiver.shutdown();
+    }
+}
@@ -0,0 +1,342 @@
+//! Capture and replay of the frames exchanged with a device.
+//!
+//! A [`Recorder`] writes every frame the codec decodes or encodes as a line of
+//! JSON. A [`Capture`] reads such a file back and replays the frames received
+//! from the device through the same handlers a live connection uses, so a
+//! session recorded against a misbehaving device can be reproduced in a test.
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use floating_duration::TimeAsFloat;
+use futures::future;
+use futures::prelude::*;
+use futures::stream;
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures_locks::RwLock;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::channel::custom::Namespaces;
+use crate::channel::heartbeat::Liveness;
+use crate::channel::receiver::AppId;
+use crate::channel::{Context, Responder};
+use crate::codec::Counters;
+use crate::metrics::Tracker;
+use crate::pending::Pending;
+use crate::poll::Playback;
+use crate::proto::{CastMessage, CastMessage_PayloadType, CastMessage_ProtocolVersion};
+use crate::task;
+use crate::{ConnectState, Status};
+
+/// Whether a frame was received from or sent to the device.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// A frame exchanged with the device.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CapturedFrame {
+    pub direction: Direction,
+    /// Seconds since the Unix epoch at which the frame was decoded or
+    /// encoded.
+    pub timestamp: f64,
+    pub namespace: String,
+    pub source: String,
+    pub destination: String,
+    /// JSON payload of the frame.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+    /// Binary payload of the frame.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_binary: Option<Vec<u8>>,
+}
+
+impl CapturedFrame {
+    fn new(direction: Direction, message: &CastMessage) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_fractional_secs())
+            .unwrap_or_default();
+        let (payload, payload_binary) = match message.get_payload_type() {
+            CastMessage_PayloadType::STRING => (Some(message.get_payload_utf8().to_owned()), None),
+            CastMessage_PayloadType::BINARY => (None, Some(message.get_payload_binary().to_vec())),
+        };
+        Self {
+            direction,
+            timestamp,
+            namespace: message.get_namespace().to_owned(),
+            source: message.get_source_id().to_owned(),
+            destination: message.get_destination_id().to_owned(),
+            payload,
+            payload_binary,
+        }
+    }
+
+    fn into_message(self) -> CastMessage {
+        let mut message = CastMessage::new();
+        message.set_protocol_version(CastMessage_ProtocolVersion::CASTV2_1_0);
+        message.set_namespace(self.namespace);
+        message.set_source_id(self.source);
+        message.set_destination_id(self.destination);
+        if let Some(payload) = self.payload_binary {
+            message.set_payload_type(CastMessage_PayloadType::BINARY);
+            message.set_payload_binary(payload);
+        } else {
+            message.set_payload_type(CastMessage_PayloadType::STRING);
+            message.set_payload_utf8(self.payload.unwrap_or_default());
+        }
+        message
+    }
+}
+
+/// Writes the frames exchanged with a device, one JSON-encoded
+/// [`CapturedFrame`] per line.
+///
+/// Recording is opt-in with `ClientBuilder::capture`. Frames dropped by the
+/// codec are not recorded. Frames are encoded and written on a dedicated
+/// thread so a slow writer does not stall the connection. The thread exits
+/// once every clone of the recorder is dropped.
+#[derive(Clone)]
+pub struct Recorder(UnboundedSender<CapturedFrame>);
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Recorder").finish()
+    }
+}
+
+impl Recorder {
+    /// Record frames to `writer`.
+    pub fn new<W: Write + Send + 'static>(writer: W) -> io::Result<Self> {
+        let (tx, rx) = mpsc::unbounded();
+        thread::Builder::new()
+            .name("cast-capture".to_owned())
+            .spawn(move || write_frames(writer, rx))?;
+        Ok(Recorder(tx))
+    }
+
+    /// Record frames to the file at `path`, truncating it if it exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        File::create(path).and_then(Self::new)
+    }
+
+    pub fn record(&self, direction: Direction, message: &CastMessage) {
+        let frame = CapturedFrame::new(direction, message);
+        if self.0.unbounded_send(frame).is_err() {
+            warn!("capture writer exited, dropping captured frame");
+        }
+    }
+}
+
+fn write_frames<W: Write>(mut writer: W, frames: UnboundedReceiver<CapturedFrame>) {
+    for frame in frames.wait().filter_map(Result::ok) {
+        let line = match serde_json::to_string(&frame) {
+            Ok(line) => line,
+            Err(err) => {
+                warn!("failed to encode captured frame: {:?}", err);
+                continue;
+            }
+        };
+        if let Err(err) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+            warn!("failed to write captured frame: {:?}", err);
+        }
+    }
+}
+
+/// A session captured by a [`Recorder`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Capture {
+    pub frames: Vec<CapturedFrame>,
+}
+
+impl Capture {
+    /// Read the capture at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fs::read_to_string(path).and_then(|contents| Self::parse(&contents))
+    }
+
+    /// Parse a capture with one JSON-encoded [`CapturedFrame`] per line.
+    /// Blank lines are skipped.
+    pub fn parse(contents: &str) -> io::Result<Self> {
+        let frames = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(Self { frames })
+    }
+
+    /// Replay the frames received from the device through the channel
+    /// handlers of a fresh client that launched the default media receiver
+    /// app.
+    ///
+    /// Frames are handled one at a time in capture order. Each frame is
+    /// handled once the connection state updates of the previous frame have
+    /// been applied. Commands the handlers would send to the device are
+    /// discarded.
+    ///
+    /// Returns the stream of status updates the client would have emitted and
+    /// a future that drives the replay. The stream ends once the replay is
+    /// complete. Spawn the future on a current thread tokio runtime for the
+    /// replay to be deterministic.
+    pub fn replay(
+        self,
+    ) -> (
+        UnboundedReceiver<Status>,
+        impl Future<Item = (), Error = ()>,
+    ) {
+        let (command_tx, command_rx) = mpsc::unbounded();
+        let (status_tx, status_rx) = mpsc::unbounded();
+        let connect = RwLock::new(ConnectState::default());
+        let responder = Responder::new(&Context {
+            connect: connect.clone(),
+            command: command_tx,
+            status: status_tx.clone(),
+            pending: Pending::default(),
+            namespaces: Namespaces::default(),
+            app_id: AppId::default(),
+            playback: Playback::default(),
+            metrics: Tracker::default(),
+            counters: Counters::default(),
+        });
+        let messages = self
+            .frames
+            .into_iter()
+            .filter(|frame| frame.direction == Direction::Inbound)
+            .map(CapturedFrame::into_message)
+            .collect::<Vec<_>>();
+        let source = stream::iter_ok::<_, io::Error>(messages).and_then(move |message| {
+            // Handlers update the connection state on a spawned task. Yield
+            // so that task runs, then wait for the lock so the update of the
+            // previous frame is applied before the next frame is handled.
+            let connect = connect.clone();
+            yield_now()
+                .and_then(move |_| connect.read().map_err(|_| lock_poisoned()))
+                .map(move |_| message)
+        });
+        let replay = task::respond(source, responder, &Liveness::default(), &status_tx);
+        let replay = replay.then(move |_| {
+            drop(command_rx);
+            drop(status_tx);
+            Ok(())
+        });
+        (status_rx, replay)
+    }
+}
+
+/// Future that returns to the executor once before it resolves, so tasks
+/// that were spawned earlier are polled first.
+fn yield_now() -> impl Future<Item = (), Error = io::Error> {
+    let mut yielded = false;
+    future::poll_fn(move || {
+        if yielded {
+            return Ok(Async::Ready(()));
+        }
+        yielded = true;
+        futures::task::current().notify();
+        Ok(Async::NotReady)
+    })
+}
+
+fn lock_poisoned() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "connection state lock is unavailable")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use futures::prelude::*;
+    use tokio::runtime::current_thread::Runtime;
+
+    use super::{Capture, Direction, Recorder};
+    use crate::channel::heartbeat;
+    use crate::Status;
+
+    /// Writer that appends to a shared buffer.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn records_frames() {
+        let buffer = Buffer::default();
+        let recorder = Recorder::new(buffer.clone()).unwrap();
+        recorder.record(Direction::Outbound, &heartbeat::ping());
+        recorder.record(Direction::Inbound, &heartbeat::pong());
+        // Frames are written on the recorder thread.
+        let mut contents = String::new();
+        for _ in 0..100 {
+            contents = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+            if contents.lines().count() == 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let capture = Capture::parse(&contents).unwrap();
+        assert_eq!(capture.frames.len(), 2);
+        assert_eq!(capture.frames[0].direction, Direction::Outbound);
+        assert_eq!(
+            capture.frames[1].namespace,
+            "urn:x-cast:com.google.cast.tp.heartbeat"
+        );
+        assert_eq!(
+            capture.frames[1].payload.as_ref().map(String::as_str),
+            Some(r#"{"type":"PONG"}"#)
+        );
+    }
+
+    #[test]
+    fn replays_captured_session() {
+        let capture = Capture::parse(
+            r#"
+{"direction":"outbound","timestamp":1571240000.0,"namespace":"urn:x-cast:com.google.cast.receiver","source":"sender-0","destination":"receiver-0","payload":"{\"type\":\"LAUNCH\",\"requestId\":1,\"appId\":\"CC1AD845\"}"}
+{"direction":"inbound","timestamp":1571240000.5,"namespace":"urn:x-cast:com.google.cast.receiver","source":"receiver-0","destination":"sender-0","payload":"{\"type\":\"RECEIVER_STATUS\",\"requestId\":1,\"status\":{\"applications\":[{\"appId\":\"CC1AD845\",\"displayName\":\"Default Media Receiver\",\"namespaces\":[],\"sessionId\":\"7E2FF513\",\"statusText\":\"Ready To Cast\",\"transportId\":\"web-5\"}],\"volume\":{\"level\":0.5,\"muted\":false}}}"}
+{"direction":"inbound","timestamp":1571240001.0,"namespace":"urn:x-cast:com.google.cast.receiver","source":"receiver-0","destination":"*","payload":"{\"type\":\"RECEIVER_STATUS\",\"requestId\":0,\"status\":{\"applications\":[{\"appId\":\"233637DE\",\"displayName\":\"YouTube\",\"namespaces\":[],\"sessionId\":\"A1B2C3D4\",\"statusText\":\"\",\"transportId\":\"web-9\"}],\"volume\":{\"level\":0.5,\"muted\":false}}}"}
+"#,
+        )
+        .unwrap();
+        let mut runtime = Runtime::new().unwrap();
+        let (status, replay) = capture.replay();
+        runtime.spawn(replay);
+        let status = runtime.block_on(status.collect()).unwrap();
+        let mut status = status.into_iter().filter(|status| match *status {
+            Status::DeviceVolume(_) => false,
+            _ => true,
+        });
+        match status.next() {
+            Some(Status::Connected(connect)) => {
+                assert_eq!(connect.session, "7E2FF513");
+                assert_eq!(connect.transport, "web-5");
+            }
+            status => panic!("unexpected status: {:?}", status),
+        }
+        match status.next() {
+            Some(Status::SessionLost { by_app, .. }) => {
+                assert_eq!(by_app.as_ref().map(String::as_str), Some("233637DE"))
+            }
+            status => panic!("unexpected status: {:?}", status),
+        }
+        assert!(status.next().is_none());
+    }
+}
@@ -4,7 +4,7 @@ use crate::channel::{self, Error, MessageBuilder, DEFAULT_SENDER_ID};
 use crate::proto::CastMessage;
 
 const CHANNEL: &str = "connection";
-const NAMESPACE: &str = "urn:x-cast:com.google.cast.tp.connection";
+pub const NAMESPACE: &str = "urn:x-cast:com.google.cast.tp.connection";
 const USER_AGENT: &str = "punchtop/cast-client";
 
 #[derive(Debug)]
@@ -31,7 +31,10 @@ impl channel::Handler for Handler {
 #[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum Request {
     #[serde(rename_all = "camelCase")]
-    Connect { user_agent: String },
+    Connect {
+        user_agent: String,
+    },
+    Close,
 }
 
 #[derive(Deserialize, Debug)]
@@ -51,3 +54,12 @@ pub fn connect(destination: &str) -> CastMessage {
         .payload(&payload)
         .into_message()
 }
+
+pub fn close(destination: &str) -> CastMessage {
+    MessageBuilder::default()
+        .namespace(NAMESPACE)
+        .source(DEFAULT_SENDER_ID)
+        .destination(destination)
+        .payload(&Request::Close)
+        .into_message()
+}
@@ -0,0 +1,114 @@
+//! Application-defined namespaces.
+//!
+//! Messages on namespaces that are not handled by the client are routed to
+//! the stream registered for their namespace with `Client::subscribe`.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::channel::{Error, MessageBuilder, DEFAULT_SENDER_ID};
+use crate::proto::{CastMessage, CastMessage_PayloadType};
+use crate::{CustomMessage, Payload};
+
+/// Registry of streams subscribed to application-defined namespaces.
+#[derive(Clone, Debug, Default)]
+pub struct Namespaces(Arc<Mutex<HashMap<String, UnboundedSender<CustomMessage>>>>);
+
+impl Namespaces {
+    /// Subscribe to messages on `namespace`. Replaces any existing
+    /// subscription, which terminates the previously returned stream.
+    pub fn subscribe(&self, namespace: &str) -> UnboundedReceiver<CustomMessage> {
+        let (tx, rx) = mpsc::unbounded();
+        if let Ok(mut namespaces) = self.0.lock() {
+            namespaces.insert(namespace.to_owned(), tx);
+        }
+        rx
+    }
+
+    pub fn try_handle(&self, message: &CastMessage) -> Result<Option<()>, Error> {
+        let namespace = message.get_namespace();
+        let mut namespaces = match self.0.lock() {
+            Ok(namespaces) => namespaces,
+            Err(_) => return Ok(None),
+        };
+        let subscriber = match namespaces.get(namespace) {
+            Some(subscriber) => subscriber,
+            None => return Ok(None),
+        };
+        trace!("found message for custom namespace {}", namespace);
+        let payload = match message.get_payload_type() {
+            CastMessage_PayloadType::STRING => serde_json::from_str(message.get_payload_utf8())
+                .map(Payload::Json)
+                .map_err(|_| Error::Parse)?,
+            CastMessage_PayloadType::BINARY => {
+                Payload::Binary(Bytes::from(message.get_payload_binary()))
+            }
+        };
+        let custom = CustomMessage {
+            namespace: namespace.to_owned(),
+            source: message.get_source_id().to_owned(),
+            destination: message.get_destination_id().to_owned(),
+            payload,
+        };
+        if subscriber.unbounded_send(custom).is_err() {
+            debug!("subscriber for custom namespace {} dropped", namespace);
+            namespaces.remove(namespace);
+        }
+        Ok(Some(()))
+    }
+}
+
+pub fn message(custom: &CustomMessage) -> CastMessage {
+    let builder = MessageBuilder::default()
+        .namespace(&custom.namespace)
+        .source(DEFAULT_SENDER_ID)
+        .destination(&custom.destination);
+    match custom.payload {
+        Payload::Json(ref payload) => builder.payload(payload),
+        Payload::Binary(ref payload) => builder.binary_payload(payload.clone()),
+    }
+    .into_message()
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures::{Future, Stream};
+    use serde_json::json;
+
+    use super::{message, Namespaces};
+    use crate::{CustomMessage, Payload};
+
+    #[test]
+    fn routes_subscribed_namespace() {
+        let namespaces = Namespaces::default();
+        let rx = namespaces.subscribe("urn:x-cast:com.example.game");
+        let custom = CustomMessage {
+            namespace: "urn:x-cast:com.example.game".to_owned(),
+            source: "sender-0".to_owned(),
+            destination: "web-1".to_owned(),
+            payload: Payload::Json(json!({ "type": "SCORE", "points": 3 })),
+        };
+        let frame = message(&custom);
+        assert_eq!(namespaces.try_handle(&frame).unwrap(), Some(()));
+        drop(namespaces);
+        let received = rx.collect().wait().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].payload, custom.payload);
+        assert_eq!(received[0].destination, "web-1");
+    }
+
+    #[test]
+    fn ignores_unsubscribed_namespace() {
+        let namespaces = Namespaces::default();
+        let custom = CustomMessage {
+            namespace: "urn:x-cast:com.example.other".to_owned(),
+            source: "sender-0".to_owned(),
+            destination: "web-1".to_owned(),
+            payload: Payload::Binary(Bytes::from_static(&[1, 2, 3])),
+        };
+        assert_eq!(namespaces.try_handle(&message(&custom)).unwrap(), None);
+    }
+}
@@ -0,0 +1,290 @@
+//! Device authentication handshake.
+//!
+//! The sender challenges the device with a random nonce. The device responds
+//! with a certificate chain and a signature over the nonce and the TLS
+//! certificate it presented on the connection. The chain is verified against
+//! the Cast root certificate authorities supplied by the caller.
+use std::io;
+use std::time::{Duration, SystemTime};
+
+use futures::future::{self, Loop};
+use futures::prelude::*;
+use protobuf::Message;
+use rand::{self, RngCore};
+use tokio_timer::Timeout;
+use webpki::trust_anchor_util;
+
+use crate::channel::{MessageBuilder, DEFAULT_DESTINATION_ID, DEFAULT_SENDER_ID};
+use crate::proto::{self, CastMessage};
+use crate::{Command, DeviceAuthError};
+
+const NAMESPACE: &str = "urn:x-cast:com.google.cast.tp.deviceauth";
+/// Length of the random nonce sent in an `AuthChallenge`.
+const NONCE_LENGTH: usize = 16;
+/// Timeout for the device to respond to an `AuthChallenge`.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Signature algorithms accepted for certificates in the device chain.
+static CHAIN_SIGNATURE_ALGORITHMS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+];
+
+/// Configuration for the device authentication handshake.
+#[derive(Clone, Debug)]
+pub struct DeviceAuth {
+    /// DER-encoded Cast root certificate authorities.
+    pub roots: Vec<Vec<u8>>,
+    /// Fail the connection if authentication fails.
+    pub strict: bool,
+}
+
+/// Challenge the device on `transport` and verify its response against the
+/// TLS certificate it presented, `peer_cert`.
+///
+/// Resolves with the transport and the result of verification. Errors if the
+/// handshake cannot be completed, e.g. the device does not respond before the
+/// timeout or the connection is closed.
+pub fn authenticate<T>(
+    transport: T,
+    peer_cert: Vec<u8>,
+    auth: DeviceAuth,
+) -> impl Future<Item = (T, Result<(), DeviceAuthError>), Error = DeviceAuthError>
+where
+    T: Stream<Item = CastMessage, Error = io::Error>
+        + Sink<SinkItem = Command, SinkError = io::Error>,
+{
+    let mut nonce = vec![0; NONCE_LENGTH];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let handshake = transport
+        .send(Command::AuthChallenge {
+            nonce: nonce.clone(),
+        })
+        .map_err(DeviceAuthError::Io)
+        .and_then(|transport| {
+            future::loop_fn(transport, |transport| {
+                transport
+                    .into_future()
+                    .map_err(|(err, _)| DeviceAuthError::Io(err))
+                    .and_then(|(message, transport)| match message {
+                        Some(ref message) if message.get_namespace() == NAMESPACE => {
+                            Ok(Loop::Break((transport, parse(message))))
+                        }
+                        Some(message) => {
+                            trace!(
+                                "dropping message in namespace {} during device auth",
+                                message.get_namespace()
+                            );
+                            Ok(Loop::Continue(transport))
+                        }
+                        None => Err(DeviceAuthError::Closed),
+                    })
+            })
+        });
+    Timeout::new(handshake, AUTH_TIMEOUT)
+        .map_err(|err| {
+            if err.is_elapsed() {
+                DeviceAuthError::Timeout
+            } else {
+                err.into_inner().unwrap_or(DeviceAuthError::Timeout)
+            }
+        })
+        .map(move |(transport, response)| {
+            let verified =
+                response.and_then(|response| verify(&response, &nonce, &peer_cert, &auth.roots));
+            (transport, verified)
+        })
+}
+
+/// Build an `AuthChallenge` requesting a SHA-256 signature over `nonce`.
+pub fn challenge(nonce: &[u8]) -> CastMessage {
+    let mut challenge = proto::AuthChallenge::new();
+    challenge.set_signature_algorithm(proto::SignatureAlgorithm::RSASSA_PKCS1v15);
+    challenge.set_hash_algorithm(proto::HashAlgorithm::SHA256);
+    challenge.set_sender_nonce(nonce.to_vec());
+    let mut auth = proto::DeviceAuthMessage::new();
+    auth.set_challenge(challenge);
+
+    let builder = MessageBuilder::default()
+        .namespace(NAMESPACE)
+        .source(DEFAULT_SENDER_ID)
+        .destination(DEFAULT_DESTINATION_ID);
+    match auth.write_to_bytes() {
+        Ok(payload) => builder.binary_payload(payload),
+        Err(_) => builder,
+    }
+    .into_message()
+}
+
+fn parse(message: &CastMessage) -> Result<proto::AuthResponse, DeviceAuthError> {
+    let mut auth =
+        protobuf::parse_from_bytes::<proto::DeviceAuthMessage>(message.get_payload_binary())
+            .map_err(|_| DeviceAuthError::Malformed)?;
+    if auth.has_error() {
+        let error = auth.get_error().get_error_type();
+        return Err(DeviceAuthError::Device(format!("{:?}", error)));
+    }
+    if !auth.has_response() {
+        return Err(DeviceAuthError::Malformed);
+    }
+    Ok(auth.take_response())
+}
+
+/// Verify the device certificate chain and the signature over the sender
+/// nonce and the TLS certificate presented by the device.
+fn verify(
+    response: &proto::AuthResponse,
+    nonce: &[u8],
+    peer_cert: &[u8],
+    roots: &[Vec<u8>],
+) -> Result<(), DeviceAuthError> {
+    if response.has_sender_nonce() && response.get_sender_nonce() != nonce {
+        return Err(DeviceAuthError::NonceMismatch);
+    }
+    let anchors = roots
+        .iter()
+        .map(|root| {
+            trust_anchor_util::cert_der_as_trust_anchor(untrusted::Input::from(root.as_slice()))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| DeviceAuthError::InvalidCertificateChain(format!("{:?}", err)))?;
+    let intermediates = response
+        .get_intermediate_certificate()
+        .iter()
+        .map(|cert| untrusted::Input::from(cert.as_slice()))
+        .collect::<Vec<_>>();
+    let time = webpki::Time::try_from(SystemTime::now())
+        .map_err(|_| DeviceAuthError::InvalidCertificateChain("system time".to_owned()))?;
+
+    let device = untrusted::Input::from(response.get_client_auth_certificate());
+    let device = webpki::EndEntityCert::from(device)
+        .map_err(|err| DeviceAuthError::InvalidCertificateChain(format!("{:?}", err)))?;
+    device
+        .verify_is_valid_tls_client_cert(
+            CHAIN_SIGNATURE_ALGORITHMS,
+            &webpki::TLSClientTrustAnchors(&anchors),
+            &intermediates,
+            time,
+        )
+        .map_err(|err| DeviceAuthError::InvalidCertificateChain(format!("{:?}", err)))?;
+
+    // The device signs the sender nonce followed by the DER-encoded TLS
+    // certificate it presented on this connection.
+    let mut signed = Vec::with_capacity(nonce.len() + peer_cert.len());
+    if response.has_sender_nonce() {
+        signed.extend_from_slice(nonce);
+    }
+    signed.extend_from_slice(peer_cert);
+    let algorithm = match response.get_hash_algorithm() {
+        proto::HashAlgorithm::SHA256 => &webpki::RSA_PKCS1_2048_8192_SHA256,
+        proto::HashAlgorithm::SHA1 => return Err(DeviceAuthError::InvalidSignature),
+    };
+    device
+        .verify_signature(
+            algorithm,
+            untrusted::Input::from(signed.as_slice()),
+            untrusted::Input::from(response.get_signature()),
+        )
+        .map_err(|_| DeviceAuthError::InvalidSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use protobuf::Message;
+
+    use super::{parse, verify, NAMESPACE};
+    use crate::channel::{MessageBuilder, DEFAULT_DESTINATION_ID, DEFAULT_SENDER_ID};
+    use crate::proto::{self, CastMessage};
+    use crate::DeviceAuthError;
+
+    const ROOT: &[u8] = include_bytes!("testdata/root.der");
+    const UNTRUSTED_ROOT: &[u8] = include_bytes!("testdata/untrusted-root.der");
+    /// Device certificate issued by `ROOT` for client auth.
+    const DEVICE: &[u8] = include_bytes!("testdata/device.der");
+    /// Signature by the device key over `NONCE` followed by `DEVICE`, which
+    /// stands in for the TLS certificate the device presented.
+    const SIGNATURE: &[u8] = include_bytes!("testdata/signature.bin");
+    const NONCE: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+    fn response(signature: &[u8]) -> proto::AuthResponse {
+        let mut response = proto::AuthResponse::new();
+        response.set_signature(signature.to_vec());
+        response.set_client_auth_certificate(DEVICE.to_vec());
+        response.set_sender_nonce(NONCE.to_vec());
+        response.set_hash_algorithm(proto::HashAlgorithm::SHA256);
+        response
+    }
+
+    fn message(auth: &proto::DeviceAuthMessage) -> CastMessage {
+        MessageBuilder::default()
+            .namespace(NAMESPACE)
+            .source(DEFAULT_DESTINATION_ID)
+            .destination(DEFAULT_SENDER_ID)
+            .binary_payload(auth.write_to_bytes().unwrap())
+            .into_message()
+    }
+
+    #[test]
+    fn verifies_chain_and_signature() {
+        let roots = [ROOT.to_vec()];
+        assert!(verify(&response(SIGNATURE), &NONCE, DEVICE, &roots).is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_signature() {
+        let mut signature = SIGNATURE.to_vec();
+        signature[0] ^= 0xff;
+        let roots = [ROOT.to_vec()];
+        match verify(&response(&signature), &NONCE, DEVICE, &roots) {
+            Err(DeviceAuthError::InvalidSignature) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+        // The signature does not cover a different TLS certificate.
+        match verify(&response(SIGNATURE), &NONCE, ROOT, &roots) {
+            Err(DeviceAuthError::InvalidSignature) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn rejects_untrusted_root() {
+        let roots = [UNTRUSTED_ROOT.to_vec()];
+        match verify(&response(SIGNATURE), &NONCE, DEVICE, &roots) {
+            Err(DeviceAuthError::InvalidCertificateChain(_)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn rejects_nonce_mismatch() {
+        let roots = [ROOT.to_vec()];
+        match verify(&response(SIGNATURE), &[0; 16], DEVICE, &roots) {
+            Err(DeviceAuthError::NonceMismatch) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn parses_response_and_device_error() {
+        let mut auth = proto::DeviceAuthMessage::new();
+        auth.set_response(response(SIGNATURE));
+        let parsed = parse(&message(&auth)).unwrap();
+        assert_eq!(parsed.get_signature(), SIGNATURE);
+
+        let mut error = proto::AuthError::new();
+        error.set_error_type(proto::AuthError_ErrorType::NO_TLS);
+        let mut auth = proto::DeviceAuthMessage::new();
+        auth.set_error(error);
+        match parse(&message(&auth)) {
+            Err(DeviceAuthError::Device(ref error)) if error == "NO_TLS" => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        let empty = proto::DeviceAuthMessage::new();
+        match parse(&message(&empty)) {
+            Err(DeviceAuthError::Malformed) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
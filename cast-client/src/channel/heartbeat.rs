@@ -1,21 +1,74 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
 use futures::sync::mpsc::UnboundedSender;
 use serde_derive::{Deserialize, Serialize};
 
 use crate::channel::{self, Error, MessageBuilder, DEFAULT_DESTINATION_ID, DEFAULT_SENDER_ID};
+use crate::metrics::Tracker;
 use crate::proto::CastMessage;
 use crate::Command;
 
 const CHANNEL: &str = "heartbeat";
-const NAMESPACE: &str = "urn:x-cast:com.google.cast.tp.heartbeat";
+pub const NAMESPACE: &str = "urn:x-cast:com.google.cast.tp.heartbeat";
+
+/// Heartbeat parameters for detecting a dead connection.
+#[derive(Clone, Copy, Debug)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub max_missed: u32,
+}
+
+impl Heartbeat {
+    /// Whether a dead connection can be detected with these parameters: the
+    /// interval is non-zero and at least one interval may be missed.
+    pub fn is_valid(&self) -> bool {
+        self.interval > Duration::default() && self.max_missed > 0
+    }
+}
+
+/// Tracks when the last frame was received from the device.
+///
+/// Any inbound frame, not just a `PONG`, is evidence that the device is alive.
+/// While the client is reconnecting there is no connection to monitor and the
+/// liveness is suspended.
+#[derive(Clone, Debug, Default)]
+pub struct Liveness(Arc<Mutex<Option<Instant>>>);
+
+impl Liveness {
+    /// Record that a frame was received from the device.
+    pub fn touch(&self) {
+        if let Ok(mut last) = self.0.lock() {
+            *last = Some(Instant::now());
+        }
+    }
+
+    /// Stop monitoring liveness until the next call to `touch`.
+    pub fn suspend(&self) {
+        if let Ok(mut last) = self.0.lock() {
+            *last = None;
+        }
+    }
+
+    /// Time since the last frame was received from the device, or `None` if
+    /// liveness is suspended.
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.0
+            .lock()
+            .ok()
+            .and_then(|last| last.map(|last| last.elapsed()))
+    }
+}
 
 #[derive(Debug)]
 pub struct Handler {
     command: UnboundedSender<Command>,
+    metrics: Tracker,
 }
 
 impl Handler {
-    pub fn new(command: UnboundedSender<Command>) -> Self {
-        Self { command }
+    pub fn new(command: UnboundedSender<Command>, metrics: Tracker) -> Self {
+        Self { command, metrics }
     }
 }
 
@@ -37,7 +90,10 @@ impl channel::Handler for Handler {
                 .command
                 .unbounded_send(Command::Pong)
                 .map_err(|_| Error::CommandSend),
-            Response::Pong => Ok(()),
+            Response::Pong => {
+                self.metrics.pong_received();
+                Ok(())
+            }
         }
     }
 }
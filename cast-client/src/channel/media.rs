@@ -1,24 +1,32 @@
+use bitflags::bitflags;
 use futures::sync::mpsc::UnboundedSender;
 use futures::Future;
 use futures_locks::RwLock;
 use serde;
 use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::channel::{self, Error, MessageBuilder, DEFAULT_SENDER_ID};
+use crate::metrics::Tracker;
+use crate::pending::Pending;
+use crate::poll::Playback;
 use crate::proto::CastMessage;
 use crate::provider::{Media, MediaConnection, ReceiverConnection};
 use crate::session;
-use crate::{Command, ConnectState, Status};
+use crate::{Command, CommandError, ConnectState, Status};
 
 const CHANNEL: &str = "media";
 const METADATA_TYPE_MUSIC_TRACK: u32 = 3;
-const NAMESPACE: &str = "urn:x-cast:com.google.cast.media";
+pub const NAMESPACE: &str = "urn:x-cast:com.google.cast.media";
 
 #[derive(Debug)]
 pub struct Handler {
     connect: RwLock<ConnectState>,
     command: UnboundedSender<Command>,
     status: UnboundedSender<Status>,
+    pending: Pending,
+    playback: Playback,
+    metrics: Tracker,
 }
 
 impl Handler {
@@ -26,11 +34,17 @@ impl Handler {
         connect: RwLock<ConnectState>,
         command: UnboundedSender<Command>,
         status: UnboundedSender<Status>,
+        pending: Pending,
+        playback: Playback,
+        metrics: Tracker,
     ) -> Self {
         Self {
             connect,
             command,
             status,
+            pending,
+            playback,
+            metrics,
         }
     }
 }
@@ -48,8 +62,16 @@ impl channel::Handler for Handler {
 
     fn handle(&self, payload: Self::Payload) -> Result<(), Error> {
         match payload {
-            Response::MediaStatus { status, .. } => {
+            Response::MediaStatus { request_id, status } => {
+                // Record playback before resolving the load it may answer.
+                let playing = status
+                    .first()
+                    .map_or(false, |status| status.player_state == PlayerState::Playing);
+                self.metrics.media_status(request_id, playing);
+                self.pending
+                    .resolve(request_id, Ok(crate::Response::MediaStatus(status.clone())));
                 let status = status.into_iter().next();
+                self.playback.update(status.as_ref());
                 let session = status.as_ref().map(|status| status.media_session_id);
                 if let Some(session) = session {
                     let tx = self.status.clone();
@@ -72,7 +94,74 @@ impl channel::Handler for Handler {
                 }
                 Ok(())
             }
-            _ => Err(Error::UnknownPayload),
+            Response::LoadCancelled { request_id } => {
+                warn!("load cancelled for request {}", request_id);
+                self.pending
+                    .resolve(request_id, Err(CommandError::LoadCancelled));
+                self.status
+                    .unbounded_send(Status::LoadCancelled { request_id })
+                    .map_err(|_| Error::StatusSend)
+            }
+            Response::LoadFailed { request_id } => {
+                warn!("load failed for request {}", request_id);
+                self.pending
+                    .resolve(request_id, Err(CommandError::LoadFailed));
+                self.status
+                    .unbounded_send(Status::LoadFailed { request_id })
+                    .map_err(|_| Error::StatusSend)
+            }
+            Response::InvalidPlayerState { request_id } => {
+                warn!("invalid player state for request {}", request_id);
+                self.pending
+                    .resolve(request_id, Err(CommandError::InvalidPlayerState));
+                self.status
+                    .unbounded_send(Status::InvalidPlayerState { request_id })
+                    .map_err(|_| Error::StatusSend)
+            }
+            Response::InvalidRequest { request_id, reason } => {
+                warn!("invalid request {}: {:?}", request_id, reason);
+                self.pending.resolve(
+                    request_id,
+                    Err(CommandError::InvalidRequest(reason.clone())),
+                );
+                self.status
+                    .unbounded_send(Status::InvalidRequest { request_id, reason })
+                    .map_err(|_| Error::StatusSend)
+            }
+            Response::QueueChange {
+                change_type,
+                item_ids,
+                insert_before,
+                ..
+            } => {
+                debug!(
+                    "queue change {:?} of items {:?} before {:?}",
+                    change_type, item_ids, insert_before
+                );
+                Ok(())
+            }
+            Response::QueueItemIds { item_ids, .. } => {
+                debug!("queue items {:?}", item_ids);
+                Ok(())
+            }
+            Response::Error {
+                request_id,
+                detailed_error_code,
+                reason,
+            } => {
+                warn!(
+                    "request {} failed with {:?}: {:?}",
+                    request_id, detailed_error_code, reason
+                );
+                self.pending.resolve(
+                    request_id,
+                    Err(CommandError::InvalidRequest(reason.clone())),
+                );
+                self.status
+                    .unbounded_send(Status::InvalidRequest { request_id, reason })
+                    .map_err(|_| Error::StatusSend)
+            }
+            Response::Unknown => Ok(()),
         }
     }
 }
@@ -101,7 +190,6 @@ pub enum Request<CustomData: serde::Serialize> {
         custom_data: Option<CustomData>,
     },
     #[serde(rename_all = "camelCase")]
-    #[allow(dead_code)]
     Seek {
         media_session_id: i64,
         request_id: i64,
@@ -135,9 +223,66 @@ pub enum Request<CustomData: serde::Serialize> {
         custom_data: Option<CustomData>,
     },
     #[serde(rename_all = "camelCase")]
-    #[allow(dead_code)]
+    QueueLoad {
+        request_id: i64,
+        session_id: String,
+        items: Vec<QueueItem>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        start_index: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        repeat_mode: Option<RepeatMode>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        custom_data: Option<CustomData>,
+    },
+    #[serde(rename_all = "camelCase")]
+    QueueInsert {
+        media_session_id: i64,
+        request_id: i64,
+        items: Vec<QueueItem>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        insert_before: Option<i64>, // appends to the end of the queue if not set
+        #[serde(skip_serializing_if = "Option::is_none")]
+        custom_data: Option<CustomData>,
+    },
+    #[serde(rename_all = "camelCase")]
+    QueueRemove {
+        media_session_id: i64,
+        request_id: i64,
+        item_ids: Vec<i64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        custom_data: Option<CustomData>,
+    },
+    #[serde(rename_all = "camelCase")]
+    QueueUpdate {
+        media_session_id: i64,
+        request_id: i64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        current_item_id: Option<i64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        jump: Option<i64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        repeat_mode: Option<RepeatMode>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        custom_data: Option<CustomData>,
+    },
+    #[serde(rename_all = "camelCase")]
+    QueueNext {
+        media_session_id: i64,
+        request_id: i64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        custom_data: Option<CustomData>,
+    },
+    #[serde(rename_all = "camelCase")]
+    QueuePrev {
+        media_session_id: i64,
+        request_id: i64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        custom_data: Option<CustomData>,
+    },
+    #[serde(rename_all = "camelCase")]
     // Media stream volume (distinct from device volume)
     Volume {
+        #[serde(skip_serializing_if = "Option::is_none")]
         media_session_id: Option<i64>,
         request_id: i64,
         volume: Volume,
@@ -166,6 +311,40 @@ pub enum Response {
         request_id: i64,
         reason: Option<String>,
     },
+    /// Broadcast when items are inserted, removed or updated in the queue.
+    /// The resulting queue is reported by the media status that follows.
+    #[serde(rename_all = "camelCase")]
+    QueueChange {
+        #[serde(default)]
+        request_id: i64,
+        #[serde(default)]
+        change_type: Option<String>,
+        #[serde(default)]
+        item_ids: Vec<i64>,
+        #[serde(default)]
+        insert_before: Option<i64>,
+    },
+    #[serde(rename_all = "camelCase")]
+    QueueItemIds {
+        #[serde(default)]
+        request_id: i64,
+        #[serde(default)]
+        item_ids: Vec<i64>,
+    },
+    /// A request failed, e.g. a queue command named an item that is not in
+    /// the queue.
+    #[serde(rename_all = "camelCase")]
+    Error {
+        #[serde(default)]
+        request_id: i64,
+        #[serde(default)]
+        detailed_error_code: Option<i64>,
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    /// Messages the client does not handle.
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
@@ -175,7 +354,7 @@ pub enum ResumeState {
     PlaybackPause,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 #[allow(clippy::module_name_repetitions)]
 pub struct MediaInformation {
@@ -186,9 +365,72 @@ pub struct MediaInformation {
     pub metadata: Option<Metadata>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub duration: Option<f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tracks: Vec<Track>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_data: Option<Value>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+/// Text, audio or video track of a media item.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Track {
+    pub track_id: i64,
+    #[serde(rename = "type")]
+    pub track_type: TrackType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_content_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// RFC 5646 language tag.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Kind of text track, e.g. `SUBTITLES` or `CAPTIONS`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtype: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_data: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TrackType {
+    Text,
+    Audio,
+    Video,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueItem {
+    /// Assigned by the receiver. Must not be set when adding items to the
+    /// queue.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub item_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media: Option<MediaInformation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub autoplay: Option<bool>, // defaults to true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<f64>,
+    /// Seconds before the end of the previous item to start loading this
+    /// item.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preload_time: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RepeatMode {
+    RepeatOff,
+    RepeatAll,
+    RepeatSingle,
+    RepeatAllAndShuffle,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum StreamType {
     None,
@@ -196,7 +438,7 @@ pub enum StreamType {
     Live,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct Metadata {
     pub metadata_type: u32,
@@ -214,6 +456,7 @@ pub struct Metadata {
     pub artist: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub composer: Option<String>,
+    #[serde(default)]
     pub images: Vec<Image>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub release_date: Option<String>,
@@ -252,7 +495,7 @@ impl Metadata {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Image {
     pub url: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -261,7 +504,7 @@ pub struct Image {
     pub height: Option<u32>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Volume {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub level: Option<f64>,
@@ -269,7 +512,7 @@ pub struct Volume {
     pub muted: Option<bool>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 #[allow(clippy::module_name_repetitions)]
 pub struct MediaStatus {
@@ -278,12 +521,84 @@ pub struct MediaStatus {
     pub media: Option<MediaInformation>,
     pub playback_rate: f64,
     pub player_state: PlayerState,
+    /// Set if `player_state` is `Idle`.
+    #[serde(default)]
     pub idle_reason: Option<IdleReason>,
     pub current_time: f64,
-    pub supported_media_commands: u32,
+    #[serde(deserialize_with = "supported_media_commands")]
+    pub supported_media_commands: SupportedMediaCommands,
+    #[serde(default)]
+    pub volume: Option<Volume>,
+    #[serde(default)]
+    pub active_track_ids: Vec<i64>,
+    #[serde(default)]
+    pub current_item_id: Option<i64>,
+    #[serde(default)]
+    pub loading_item_id: Option<i64>,
+    #[serde(default)]
+    pub preloaded_item_id: Option<i64>,
+    #[serde(default)]
+    pub repeat_mode: Option<RepeatMode>,
+    #[serde(default)]
+    pub items: Vec<QueueItem>,
+    #[serde(default)]
+    pub custom_data: Option<Value>,
+    /// Status of media that is loading while `player_state` reflects the
+    /// previous media.
+    #[serde(default)]
+    pub extended_status: Option<ExtendedStatus>,
 }
 
-#[derive(Deserialize, Debug, Eq, PartialEq)]
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtendedStatus {
+    pub player_state: ExtendedPlayerState,
+    #[serde(default)]
+    pub media: Option<MediaInformation>,
+    #[serde(default)]
+    pub media_session_id: Option<i64>,
+}
+
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ExtendedPlayerState {
+    Loading,
+    /// States added to the protocol after this client.
+    #[serde(other)]
+    Unknown,
+}
+
+bitflags! {
+    /// Media commands supported by the receiver app for the media session.
+    pub struct SupportedMediaCommands: u32 {
+        const PAUSE = 1;
+        const SEEK = 2;
+        const STREAM_VOLUME = 4;
+        const STREAM_MUTE = 8;
+        const SKIP_FORWARD = 16;
+        const SKIP_BACKWARD = 32;
+        const QUEUE_NEXT = 64;
+        const QUEUE_PREV = 128;
+        const QUEUE_SHUFFLE = 256;
+        const SKIP_AD = 512;
+        const QUEUE_REPEAT_ALL = 1024;
+        const QUEUE_REPEAT_ONE = 2048;
+        const EDIT_TRACKS = 4096;
+        const PLAYBACK_RATE = 8192;
+    }
+}
+
+/// Decode the `supportedMediaCommands` bitmask. Bits for commands added to
+/// the protocol after this client are ignored.
+fn supported_media_commands<'de, D>(deserializer: D) -> Result<SupportedMediaCommands, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    <u32 as serde::Deserialize>::deserialize(deserializer)
+        .map(SupportedMediaCommands::from_bits_truncate)
+}
+
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum PlayerState {
     Idle,
@@ -292,7 +607,7 @@ pub enum PlayerState {
     Paused,
 }
 
-#[derive(Deserialize, Debug, Eq, PartialEq)]
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum IdleReason {
     Cancelled,
@@ -301,7 +616,7 @@ pub enum IdleReason {
     Error,
 }
 
-pub fn load(request_id: i64, connect: &ReceiverConnection, media: Media) -> CastMessage {
+fn media_information(media: Media) -> MediaInformation {
     let mut images = Vec::with_capacity(1);
     if let Some(image) = media.cover {
         images.push(Image {
@@ -317,17 +632,35 @@ pub fn load(request_id: i64, connect: &ReceiverConnection, media: Media) -> Cast
         images,
         ..Metadata::music_default()
     };
-    let media = MediaInformation {
+    MediaInformation {
         content_id: media.url.to_string(),
         stream_type: StreamType::None, // let the device decide whether to buffer
         content_type: media.content_type,
         metadata: Some(metadata),
         duration: media.duration,
-    };
+        tracks: vec![],
+        custom_data: None,
+    }
+}
+
+fn queue_items(media: Vec<Media>) -> Vec<QueueItem> {
+    media
+        .into_iter()
+        .map(|media| QueueItem {
+            item_id: None,
+            media: Some(media_information(media)),
+            autoplay: None,
+            start_time: None,
+            preload_time: None,
+        })
+        .collect()
+}
+
+pub fn load(request_id: i64, connect: &ReceiverConnection, media: Media) -> CastMessage {
     let payload: Request<()> = Request::Load {
         request_id,
         session_id: connect.session.to_owned(),
-        media,
+        media: media_information(media),
         current_time: None,
         custom_data: None,
         autoplay: None,
@@ -368,6 +701,22 @@ pub fn play(request_id: i64, connect: &MediaConnection) -> CastMessage {
         .into_message()
 }
 
+pub fn seek(request_id: i64, connect: &MediaConnection, current_time: f64) -> CastMessage {
+    let payload: Request<()> = Request::Seek {
+        media_session_id: connect.session,
+        request_id,
+        resume_state: None,
+        current_time: Some(current_time),
+        custom_data: None,
+    };
+    MessageBuilder::default()
+        .namespace(NAMESPACE)
+        .source(DEFAULT_SENDER_ID)
+        .destination(&connect.receiver.transport)
+        .payload(&payload)
+        .into_message()
+}
+
 pub fn status(request_id: i64, connect: &MediaConnection) -> CastMessage {
     let payload: Request<()> = Request::GetStatus {
         media_session_id: Some(connect.session),
@@ -382,6 +731,21 @@ pub fn status(request_id: i64, connect: &MediaConnection) -> CastMessage {
         .into_message()
 }
 
+/// Request the status of every media session of the app on the transport.
+pub fn sessions(request_id: i64, connect: &ReceiverConnection) -> CastMessage {
+    let payload: Request<()> = Request::GetStatus {
+        media_session_id: None,
+        request_id,
+        custom_data: None,
+    };
+    MessageBuilder::default()
+        .namespace(NAMESPACE)
+        .source(DEFAULT_SENDER_ID)
+        .destination(&connect.transport)
+        .payload(&payload)
+        .into_message()
+}
+
 pub fn stop(request_id: i64, connect: &MediaConnection) -> CastMessage {
     let payload: Request<()> = Request::Stop {
         media_session_id: connect.session,
@@ -395,3 +759,349 @@ pub fn stop(request_id: i64, connect: &MediaConnection) -> CastMessage {
         .payload(&payload)
         .into_message()
 }
+
+pub fn volume(request_id: i64, connect: &MediaConnection, volume: Volume) -> CastMessage {
+    let payload: Request<()> = Request::Volume {
+        media_session_id: Some(connect.session),
+        request_id,
+        volume,
+        custom_data: None,
+    };
+    MessageBuilder::default()
+        .namespace(NAMESPACE)
+        .source(DEFAULT_SENDER_ID)
+        .destination(&connect.receiver.transport)
+        .payload(&payload)
+        .into_message()
+}
+
+pub fn queue_load(
+    request_id: i64,
+    connect: &ReceiverConnection,
+    media: Vec<Media>,
+    start_index: u32,
+) -> CastMessage {
+    let payload: Request<()> = Request::QueueLoad {
+        request_id,
+        session_id: connect.session.to_owned(),
+        items: queue_items(media),
+        start_index: Some(start_index),
+        repeat_mode: None,
+        custom_data: None,
+    };
+    MessageBuilder::default()
+        .namespace(NAMESPACE)
+        .source(DEFAULT_SENDER_ID)
+        .destination(&connect.transport)
+        .payload(&payload)
+        .into_message()
+}
+
+pub fn queue_insert(
+    request_id: i64,
+    connect: &MediaConnection,
+    media: Vec<Media>,
+    insert_before: Option<i64>,
+) -> CastMessage {
+    let payload: Request<()> = Request::QueueInsert {
+        media_session_id: connect.session,
+        request_id,
+        items: queue_items(media),
+        insert_before,
+        custom_data: None,
+    };
+    MessageBuilder::default()
+        .namespace(NAMESPACE)
+        .source(DEFAULT_SENDER_ID)
+        .destination(&connect.receiver.transport)
+        .payload(&payload)
+        .into_message()
+}
+
+pub fn queue_remove(request_id: i64, connect: &MediaConnection, item_ids: Vec<i64>) -> CastMessage {
+    let payload: Request<()> = Request::QueueRemove {
+        media_session_id: connect.session,
+        request_id,
+        item_ids,
+        custom_data: None,
+    };
+    MessageBuilder::default()
+        .namespace(NAMESPACE)
+        .source(DEFAULT_SENDER_ID)
+        .destination(&connect.receiver.transport)
+        .payload(&payload)
+        .into_message()
+}
+
+pub fn queue_update(
+    request_id: i64,
+    connect: &MediaConnection,
+    current_item_id: i64,
+) -> CastMessage {
+    let payload: Request<()> = Request::QueueUpdate {
+        media_session_id: connect.session,
+        request_id,
+        current_item_id: Some(current_item_id),
+        jump: None,
+        repeat_mode: None,
+        custom_data: None,
+    };
+    MessageBuilder::default()
+        .namespace(NAMESPACE)
+        .source(DEFAULT_SENDER_ID)
+        .destination(&connect.receiver.transport)
+        .payload(&payload)
+        .into_message()
+}
+
+pub fn queue_next(request_id: i64, connect: &MediaConnection) -> CastMessage {
+    let payload: Request<()> = Request::QueueNext {
+        media_session_id: connect.session,
+        request_id,
+        custom_data: None,
+    };
+    MessageBuilder::default()
+        .namespace(NAMESPACE)
+        .source(DEFAULT_SENDER_ID)
+        .destination(&connect.receiver.transport)
+        .payload(&payload)
+        .into_message()
+}
+
+pub fn queue_prev(request_id: i64, connect: &MediaConnection) -> CastMessage {
+    let payload: Request<()> = Request::QueuePrev {
+        media_session_id: connect.session,
+        request_id,
+        custom_data: None,
+    };
+    MessageBuilder::default()
+        .namespace(NAMESPACE)
+        .source(DEFAULT_SENDER_ID)
+        .destination(&connect.receiver.transport)
+        .payload(&payload)
+        .into_message()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use url::Url;
+
+    use super::{ExtendedPlayerState, ExtendedStatus, Response, SupportedMediaCommands, TrackType};
+    use crate::proto::CastMessage;
+    use crate::provider::{Media, MediaConnection, ReceiverConnection};
+
+    fn receiver() -> ReceiverConnection {
+        ReceiverConnection {
+            session: "session-1".to_owned(),
+            transport: "web-1".to_owned(),
+        }
+    }
+
+    fn round(index: u32) -> Media {
+        Media {
+            title: Some(format!("Round {}", index)),
+            artist: None,
+            album: None,
+            url: Url::parse(&format!("http://127.0.0.1:8000/round-{}.mp3", index)).unwrap(),
+            cover: None,
+            content_type: "audio/mpeg".to_owned(),
+            duration: Some(60.0),
+        }
+    }
+
+    fn payload(message: &CastMessage) -> Value {
+        assert_eq!(message.get_namespace(), super::NAMESPACE);
+        serde_json::from_str(message.get_payload_utf8()).unwrap()
+    }
+
+    #[test]
+    fn encodes_queue_load() {
+        let message = super::queue_load(7, &receiver(), vec![round(1), round(2)], 1);
+        assert_eq!(message.get_destination_id(), "web-1");
+        let payload = payload(&message);
+        assert_eq!(payload["type"], "QUEUE_LOAD");
+        assert_eq!(payload["requestId"], 7);
+        assert_eq!(payload["sessionId"], "session-1");
+        assert_eq!(payload["startIndex"], 1);
+        let items = payload["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        // Item ids are assigned by the receiver.
+        assert!(items[0].get("itemId").is_none());
+        assert_eq!(
+            items[1]["media"]["contentId"],
+            "http://127.0.0.1:8000/round-2.mp3"
+        );
+    }
+
+    #[test]
+    fn encodes_queue_insert() {
+        let connect = MediaConnection {
+            receiver: receiver(),
+            session: 3,
+        };
+        let append = payload(&super::queue_insert(8, &connect, vec![round(3)], None));
+        assert_eq!(append["type"], "QUEUE_INSERT");
+        assert_eq!(append["mediaSessionId"], 3);
+        assert!(append.get("insertBefore").is_none());
+        assert_eq!(append["items"].as_array().unwrap().len(), 1);
+        let insert = payload(&super::queue_insert(9, &connect, vec![round(3)], Some(2)));
+        assert_eq!(insert["insertBefore"], 2);
+    }
+
+    #[test]
+    fn encodes_queue_update() {
+        let connect = MediaConnection {
+            receiver: receiver(),
+            session: 3,
+        };
+        let message = super::queue_update(10, &connect, 4);
+        assert_eq!(message.get_destination_id(), "web-1");
+        let payload = payload(&message);
+        assert_eq!(payload["type"], "QUEUE_UPDATE");
+        assert_eq!(payload["requestId"], 10);
+        assert_eq!(payload["mediaSessionId"], 3);
+        assert_eq!(payload["currentItemId"], 4);
+        assert!(payload.get("jump").is_none());
+    }
+
+    #[test]
+    fn parses_queue_messages_and_errors() {
+        let change = r#"{
+            "type": "QUEUE_CHANGE",
+            "changeType": "INSERT",
+            "itemIds": [4],
+            "insertBefore": 2,
+            "sequenceNumber": 1
+        }"#;
+        match serde_json::from_str(change).unwrap() {
+            Response::QueueChange {
+                change_type,
+                item_ids,
+                insert_before,
+                ..
+            } => {
+                assert_eq!(change_type.as_ref().map(String::as_str), Some("INSERT"));
+                assert_eq!(item_ids, vec![4]);
+                assert_eq!(insert_before, Some(2));
+            }
+            response => panic!("unexpected response: {:?}", response),
+        }
+        let ids = r#"{ "type": "QUEUE_ITEM_IDS", "requestId": 5, "itemIds": [1, 2] }"#;
+        match serde_json::from_str(ids).unwrap() {
+            Response::QueueItemIds {
+                request_id,
+                item_ids,
+            } => {
+                assert_eq!(request_id, 5);
+                assert_eq!(item_ids, vec![1, 2]);
+            }
+            response => panic!("unexpected response: {:?}", response),
+        }
+        let error = r#"{
+            "type": "ERROR",
+            "requestId": 6,
+            "detailedErrorCode": 999,
+            "reason": "INVALID_PARAMS"
+        }"#;
+        match serde_json::from_str(error).unwrap() {
+            Response::Error {
+                request_id,
+                detailed_error_code,
+                reason,
+            } => {
+                assert_eq!(request_id, 6);
+                assert_eq!(detailed_error_code, Some(999));
+                assert_eq!(reason.as_ref().map(String::as_str), Some("INVALID_PARAMS"));
+            }
+            response => panic!("unexpected response: {:?}", response),
+        }
+        let unknown = r#"{ "type": "QUEUE_ITEMS", "requestId": 7, "items": [] }"#;
+        match serde_json::from_str(unknown).unwrap() {
+            Response::Unknown => {}
+            response => panic!("unexpected response: {:?}", response),
+        }
+    }
+
+    #[test]
+    fn parses_queue_item_ids_from_status() {
+        let payload = r#"{
+            "type": "MEDIA_STATUS",
+            "requestId": 4,
+            "status": [{
+                "mediaSessionId": 1,
+                "playbackRate": 1,
+                "playerState": "PLAYING",
+                "currentTime": 12.5,
+                "supportedMediaCommands": 15,
+                "currentItemId": 2,
+                "preloadedItemId": 3,
+                "repeatMode": "REPEAT_OFF",
+                "items": [{ "itemId": 2 }, { "itemId": 3, "autoplay": true }]
+            }]
+        }"#;
+        match serde_json::from_str(payload).unwrap() {
+            Response::MediaStatus { request_id, status } => {
+                assert_eq!(request_id, 4);
+                assert_eq!(status[0].current_item_id, Some(2));
+                assert_eq!(status[0].preloaded_item_id, Some(3));
+                assert_eq!(status[0].loading_item_id, None);
+                let items = status[0]
+                    .items
+                    .iter()
+                    .map(|item| item.item_id)
+                    .collect::<Vec<_>>();
+                assert_eq!(items, vec![Some(2), Some(3)]);
+            }
+            response => panic!("unexpected response: {:?}", response),
+        }
+    }
+
+    #[test]
+    fn parses_full_status() {
+        let payload = r#"{
+            "type": "MEDIA_STATUS",
+            "requestId": 0,
+            "status": [{
+                "mediaSessionId": 1,
+                "media": {
+                    "contentId": "http://127.0.0.1/round.mp3",
+                    "streamType": "BUFFERED",
+                    "contentType": "audio/mpeg",
+                    "tracks": [{ "trackId": 1, "type": "AUDIO", "language": "en" }]
+                },
+                "playbackRate": 1,
+                "playerState": "PAUSED",
+                "currentTime": 3,
+                "supportedMediaCommands": 1048591,
+                "volume": { "level": 0.5, "muted": false },
+                "activeTrackIds": [1],
+                "customData": { "round": 2 },
+                "extendedStatus": { "playerState": "LOADING", "mediaSessionId": 2 }
+            }]
+        }"#;
+        match serde_json::from_str(payload).unwrap() {
+            Response::MediaStatus { status, .. } => {
+                let status = &status[0];
+                assert_eq!(
+                    status.supported_media_commands,
+                    SupportedMediaCommands::PAUSE
+                        | SupportedMediaCommands::SEEK
+                        | SupportedMediaCommands::STREAM_VOLUME
+                        | SupportedMediaCommands::STREAM_MUTE
+                );
+                assert_eq!(status.active_track_ids, vec![1]);
+                assert_eq!(status.custom_data.as_ref().unwrap()["round"], 2);
+                let tracks = &status.media.as_ref().unwrap().tracks;
+                assert_eq!(tracks[0].track_type, TrackType::Audio);
+                let extended = status.extended_status.as_ref().unwrap();
+                assert_eq!(extended.player_state, ExtendedPlayerState::Loading);
+                assert_eq!(extended.media_session_id, Some(2));
+            }
+            response => panic!("unexpected response: {:?}", response),
+        }
+        let extended = r#"{ "playerState": "SKIPPING_AD" }"#;
+        let extended = serde_json::from_str::<ExtendedStatus>(extended).unwrap();
+        assert_eq!(extended.player_state, ExtendedPlayerState::Unknown);
+    }
+}
@@ -1,17 +1,25 @@
 use std::error;
 use std::fmt;
 
+use bytes::Bytes;
 use futures::sync::mpsc::UnboundedSender;
 use futures_locks::RwLock;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
+use crate::codec::Counters;
+use crate::metrics::Tracker;
+use crate::pending::Pending;
+use crate::poll::Playback;
 use crate::proto::{CastMessage, CastMessage_PayloadType, CastMessage_ProtocolVersion};
 use crate::{Command, ConnectState, Status};
 
 pub mod connection;
+pub mod custom;
+pub mod deviceauth;
 pub mod heartbeat;
 pub mod media;
+pub mod multizone;
 pub mod receiver;
 
 pub const DEFAULT_DESTINATION_ID: &str = "receiver-0";
@@ -43,44 +51,110 @@ pub trait Handler {
 
     fn handle(&self, payload: Self::Payload) -> Result<(), Error>;
 
+    /// Handle a message with a binary payload. Channels that only exchange
+    /// JSON reject binary messages.
+    fn handle_binary(&self, _payload: Bytes) -> Result<(), Error> {
+        Err(Error::UnknownPayload)
+    }
+
     fn try_handle(&self, message: &CastMessage) -> Result<Option<()>, Error> {
         if message.get_namespace() != self.namespace() {
             return Ok(None);
         }
         trace!("found message for {} channel", self.channel());
-        let payload = serde_json::from_str(message.get_payload_utf8()).map_err(|_| Error::Parse)?;
-        self.handle(payload).map(Some)
+        match message.get_payload_type() {
+            CastMessage_PayloadType::STRING => {
+                let payload =
+                    serde_json::from_str(message.get_payload_utf8()).map_err(|_| Error::Parse)?;
+                self.handle(payload).map(Some)
+            }
+            CastMessage_PayloadType::BINARY => self
+                .handle_binary(Bytes::from(message.get_payload_binary()))
+                .map(Some),
+        }
     }
 }
 
+/// Handles shared by the channel handlers of a `Responder`.
+#[derive(Clone, Debug)]
+pub struct Context {
+    pub connect: RwLock<ConnectState>,
+    pub command: UnboundedSender<Command>,
+    pub status: UnboundedSender<Status>,
+    pub pending: Pending,
+    pub namespaces: custom::Namespaces,
+    pub app_id: receiver::AppId,
+    pub playback: Playback,
+    pub metrics: Tracker,
+    pub counters: Counters,
+}
+
 #[derive(Debug)]
 pub struct Responder {
     connection: connection::Handler,
+    custom: custom::Namespaces,
     heartbeat: heartbeat::Handler,
     media: media::Handler,
+    multizone: multizone::Handler,
     receiver: receiver::Handler,
+    status: UnboundedSender<Status>,
+    counters: Counters,
 }
 
 impl Responder {
-    pub fn new(
-        connect: &RwLock<ConnectState>,
-        command: &UnboundedSender<Command>,
-        status: &UnboundedSender<Status>,
-    ) -> Self {
+    pub fn new(context: &Context) -> Self {
         Self {
             connection: connection::Handler,
-            heartbeat: heartbeat::Handler::new(command.clone()),
-            media: media::Handler::new(connect.clone(), command.clone(), status.clone()),
-            receiver: receiver::Handler::new(connect.clone(), command.clone(), status.clone()),
+            custom: context.namespaces.clone(),
+            heartbeat: heartbeat::Handler::new(context.command.clone(), context.metrics.clone()),
+            media: media::Handler::new(
+                context.connect.clone(),
+                context.command.clone(),
+                context.status.clone(),
+                context.pending.clone(),
+                context.playback.clone(),
+                context.metrics.clone(),
+            ),
+            multizone: multizone::Handler::new(context.status.clone(), context.pending.clone()),
+            receiver: receiver::Handler::new(
+                context.connect.clone(),
+                context.command.clone(),
+                context.status.clone(),
+                context.pending.clone(),
+                context.app_id.clone(),
+            ),
+            status: context.status.clone(),
+            counters: context.counters.clone(),
         }
     }
 
+    /// Dispatch `message` to the handler of its channel. Frames that cannot be
+    /// parsed or belong to no channel are dropped and reported as
+    /// `Status::Error`, so a single bad frame does not end the connection.
     pub fn handle(&self, message: &CastMessage) -> Result<(), Error> {
+        match self.dispatch(message) {
+            Err(err @ Error::Parse) | Err(err @ Error::UnknownPayload) => {
+                let namespace = message.get_namespace();
+                warn!("dropped {:?} frame on {}", err, namespace);
+                self.counters.dropped_inbound();
+                let protocol = crate::Error::Protocol(format!("{} on {}", err, namespace));
+                let _ = self
+                    .status
+                    .unbounded_send(Status::Error(Box::new(protocol)));
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn dispatch(&self, message: &CastMessage) -> Result<(), Error> {
         // Try handlers in order of receive frequency
         if self.media.try_handle(message)?.is_none()
             && self.receiver.try_handle(message)?.is_none()
             && self.heartbeat.try_handle(message)?.is_none()
             && self.connection.try_handle(message)?.is_none()
+            && self.multizone.try_handle(message)?.is_none()
+            && self.custom.try_handle(message)?.is_none()
         {
             warn!("message on unknown channel {}", message.get_namespace());
             return Err(Error::UnknownPayload);
@@ -89,12 +163,18 @@ impl Responder {
     }
 }
 
+#[derive(Debug)]
+enum Payload {
+    Utf8(String),
+    Binary(Bytes),
+}
+
 #[derive(Debug, Default)]
 pub struct MessageBuilder<'a> {
     namespace: Option<&'a str>,
     source: Option<&'a str>,
     destination: Option<&'a str>,
-    payload: Option<String>,
+    payload: Option<Payload>,
 }
 
 impl<'a> MessageBuilder<'a> {
@@ -115,11 +195,16 @@ impl<'a> MessageBuilder<'a> {
 
     pub fn payload<T: Serialize>(mut self, payload: &T) -> Self {
         if let Ok(payload) = serde_json::to_string(payload) {
-            self.payload = Some(payload);
+            self.payload = Some(Payload::Utf8(payload));
         }
         self
     }
 
+    pub fn binary_payload<T: Into<Bytes>>(mut self, payload: T) -> Self {
+        self.payload = Some(Payload::Binary(payload.into()));
+        self
+    }
+
     pub fn into_message(mut self) -> CastMessage {
         let mut message = CastMessage::new();
         message.set_protocol_version(CastMessage_ProtocolVersion::CASTV2_1_0);
@@ -132,10 +217,36 @@ impl<'a> MessageBuilder<'a> {
         if let Some(namespace) = self.namespace.take() {
             message.set_namespace(namespace.to_owned());
         }
-        if let Some(payload) = self.payload.take() {
-            message.set_payload_type(CastMessage_PayloadType::STRING);
-            message.set_payload_utf8(payload);
+        match self.payload.take() {
+            Some(Payload::Utf8(payload)) => {
+                message.set_payload_type(CastMessage_PayloadType::STRING);
+                message.set_payload_utf8(payload);
+            }
+            Some(Payload::Binary(payload)) => {
+                message.set_payload_type(CastMessage_PayloadType::BINARY);
+                message.set_payload_binary(payload.to_vec());
+            }
+            None => {}
         }
         message
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::MessageBuilder;
+    use crate::proto::CastMessage_PayloadType;
+
+    #[test]
+    fn builds_binary_payload() {
+        let message = MessageBuilder::default()
+            .namespace("urn:x-cast:com.example.binary")
+            .binary_payload(Bytes::from_static(&[0xca, 0x57]))
+            .into_message();
+        assert_eq!(message.get_payload_type(), CastMessage_PayloadType::BINARY);
+        assert_eq!(message.get_payload_binary(), &[0xca, 0x57]);
+        assert!(message.get_payload_utf8().is_empty());
+    }
+}
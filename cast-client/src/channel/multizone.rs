@@ -0,0 +1,209 @@
+//! Speaker group membership.
+//!
+//! A Cast group reports its member devices on the multizone namespace. The
+//! group broadcasts a message whenever a member joins, leaves or changes its
+//! volume.
+use futures::sync::mpsc::UnboundedSender;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::channel::receiver::Volume;
+use crate::channel::{self, Error, MessageBuilder, DEFAULT_DESTINATION_ID, DEFAULT_SENDER_ID};
+use crate::pending::Pending;
+use crate::proto::CastMessage;
+use crate::Status;
+
+const CHANNEL: &str = "multizone";
+pub const NAMESPACE: &str = "urn:x-cast:com.google.cast.multizone";
+
+#[derive(Debug)]
+pub struct Handler {
+    status: UnboundedSender<Status>,
+    pending: Pending,
+}
+
+impl Handler {
+    pub fn new(status: UnboundedSender<Status>, pending: Pending) -> Self {
+        Self { status, pending }
+    }
+}
+
+impl channel::Handler for Handler {
+    type Payload = Response;
+
+    fn channel(&self) -> &str {
+        CHANNEL
+    }
+
+    fn namespace(&self) -> &str {
+        NAMESPACE
+    }
+
+    fn handle(&self, payload: Self::Payload) -> Result<(), Error> {
+        let status = match payload {
+            Response::MultizoneStatus { request_id, status } => {
+                self.pending.resolve(
+                    request_id,
+                    Ok(crate::Response::GroupStatus(status.devices.clone())),
+                );
+                Status::GroupStatus(status.devices)
+            }
+            Response::DeviceAdded { device } => Status::GroupMemberUpdated(device),
+            Response::DeviceUpdated { request_id, device } => {
+                // Acknowledges a `SET_DEVICE_VOLUME` if `request_id` is set.
+                self.pending.resolve(
+                    request_id,
+                    Ok(crate::Response::GroupStatus(vec![device.clone()])),
+                );
+                Status::GroupMemberUpdated(device)
+            }
+            Response::DeviceRemoved { device_id } => Status::GroupMemberRemoved { device_id },
+            Response::Unknown => return Ok(()),
+        };
+        self.status
+            .unbounded_send(status)
+            .map_err(|_| Error::StatusSend)
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Request {
+    #[serde(rename_all = "camelCase")]
+    GetStatus { request_id: i64 },
+    #[serde(rename_all = "camelCase")]
+    SetDeviceVolume {
+        request_id: i64,
+        device_id: String,
+        volume: Volume,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Response {
+    #[serde(rename_all = "camelCase")]
+    MultizoneStatus {
+        #[serde(default)]
+        request_id: i64,
+        status: GroupStatus,
+    },
+    #[serde(rename_all = "camelCase")]
+    DeviceAdded { device: Member },
+    #[serde(rename_all = "camelCase")]
+    DeviceUpdated {
+        #[serde(default)]
+        request_id: i64,
+        device: Member,
+    },
+    #[serde(rename_all = "camelCase")]
+    DeviceRemoved { device_id: String },
+    /// Messages about casting sessions on the group, which the client does
+    /// not track.
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupStatus {
+    #[serde(default)]
+    pub devices: Vec<Member>,
+    #[serde(default)]
+    pub is_multichannel: bool,
+}
+
+/// Device that is a member of a Cast group.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Member {
+    pub device_id: String,
+    /// Friendly name of the member device.
+    pub name: String,
+    #[serde(default)]
+    pub capabilities: u32,
+    pub volume: Volume,
+}
+
+pub fn status(request_id: i64) -> CastMessage {
+    let payload = Request::GetStatus { request_id };
+    MessageBuilder::default()
+        .namespace(NAMESPACE)
+        .source(DEFAULT_SENDER_ID)
+        .destination(DEFAULT_DESTINATION_ID)
+        .payload(&payload)
+        .into_message()
+}
+
+pub fn set_volume(request_id: i64, device_id: &str, volume: Volume) -> CastMessage {
+    let payload = Request::SetDeviceVolume {
+        request_id,
+        device_id: device_id.to_owned(),
+        volume,
+    };
+    MessageBuilder::default()
+        .namespace(NAMESPACE)
+        .source(DEFAULT_SENDER_ID)
+        .destination(DEFAULT_DESTINATION_ID)
+        .payload(&payload)
+        .into_message()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Response;
+
+    #[test]
+    fn parses_group_members() {
+        let payload = r#"{
+            "type": "MULTIZONE_STATUS",
+            "requestId": 5,
+            "status": {
+                "devices": [
+                    {
+                        "deviceId": "4d2b1c9a-kitchen",
+                        "name": "Kitchen Speaker",
+                        "capabilities": 198660,
+                        "volume": { "level": 0.4, "muted": false }
+                    },
+                    {
+                        "deviceId": "8f01e3d2-den",
+                        "name": "Den Speaker",
+                        "capabilities": 198660,
+                        "volume": { "level": 0.7, "muted": true }
+                    }
+                ],
+                "isMultichannel": false
+            }
+        }"#;
+        match serde_json::from_str(payload).unwrap() {
+            Response::MultizoneStatus { request_id, status } => {
+                assert_eq!(request_id, 5);
+                assert_eq!(status.devices.len(), 2);
+                assert_eq!(status.devices[0].name, "Kitchen Speaker");
+                assert_eq!(status.devices[1].volume.muted, Some(true));
+            }
+            response => panic!("unexpected response: {:?}", response),
+        }
+        let payload = r#"{
+            "type": "DEVICE_UPDATED",
+            "requestId": 6,
+            "device": {
+                "deviceId": "8f01e3d2-den",
+                "name": "Den Speaker",
+                "volume": { "level": 0.5, "muted": false }
+            }
+        }"#;
+        match serde_json::from_str(payload).unwrap() {
+            Response::DeviceUpdated { request_id, device } => {
+                assert_eq!(request_id, 6);
+                assert_eq!(device.name, "Den Speaker");
+            }
+            response => panic!("unexpected response: {:?}", response),
+        }
+        let payload = r#"{ "type": "PLAYBACK_SESSION_UPDATED", "groupId": "whole-house" }"#;
+        match serde_json::from_str(payload).unwrap() {
+            Response::Unknown => {}
+            response => panic!("unexpected response: {:?}", response),
+        }
+    }
+}
@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
 use futures::sync::mpsc::UnboundedSender;
 use futures_locks::RwLock;
 use serde_derive::{Deserialize, Serialize};
@@ -6,17 +9,113 @@ use crate::channel::{
     self, Error, MessageBuilder, DEFAULT_DESTINATION_ID, DEFAULT_MEDIA_RECEIVER_APP_ID,
     DEFAULT_SENDER_ID,
 };
+use crate::pending::Pending;
 use crate::proto::CastMessage;
-use crate::{Command, ConnectState};
+use crate::{Command, CommandError, ConnectState, SessionLifecycle};
 
 const CHANNEL: &str = "receiver";
-const NAMESPACE: &str = "urn:x-cast:com.google.cast.receiver";
+pub const NAMESPACE: &str = "urn:x-cast:com.google.cast.receiver";
+const APP_AVAILABLE: &str = "APP_AVAILABLE";
+/// App the receiver runs when idle.
+const BACKDROP_APP_ID: &str = "E8C28D3C";
+
+/// Id of the receiver app launched or joined by the client. The receiver
+/// channel tracks the session of this app until it is released.
+#[derive(Clone, Debug)]
+pub struct AppId(Arc<Mutex<LaunchedApp>>);
+
+#[derive(Debug)]
+struct LaunchedApp {
+    app_id: String,
+    /// Whether the client launched the app and stops it on shutdown.
+    owned: bool,
+    /// Whether receiver status updates follow the session of the app.
+    tracked: bool,
+    launching: bool,
+}
+
+impl Default for AppId {
+    fn default() -> Self {
+        AppId(Arc::new(Mutex::new(LaunchedApp {
+            app_id: DEFAULT_MEDIA_RECEIVER_APP_ID.to_owned(),
+            owned: true,
+            tracked: true,
+            launching: true,
+        })))
+    }
+}
+
+impl AppId {
+    pub fn get(&self) -> String {
+        self.0
+            .lock()
+            .map(|app| app.app_id.clone())
+            .unwrap_or_else(|_| DEFAULT_MEDIA_RECEIVER_APP_ID.to_owned())
+    }
+
+    /// Track the session of `app_id` once it is launched. The next session
+    /// of the app replaces the current one without being reported as lost.
+    pub fn set(&self, app_id: &str) {
+        if let Ok(mut app) = self.0.lock() {
+            app.app_id = app_id.to_owned();
+            app.owned = true;
+            app.tracked = true;
+            app.launching = true;
+        }
+    }
+
+    /// Track the session of `app_id`, which was launched by another sender.
+    /// The client does not own the app, so it is not stopped on shutdown.
+    pub fn join(&self, app_id: &str) {
+        if let Ok(mut app) = self.0.lock() {
+            app.app_id = app_id.to_owned();
+            app.owned = false;
+            app.tracked = true;
+            app.launching = true;
+        }
+    }
+
+    /// Whether the client launched the app and has not yet connected to its
+    /// session.
+    pub fn is_launching(&self) -> bool {
+        self.0.lock().map(|app| app.launching).unwrap_or_default()
+    }
+
+    /// Record that the client connected to the session of the launched app.
+    pub fn connected(&self) {
+        if let Ok(mut app) = self.0.lock() {
+            app.launching = false;
+        }
+    }
+
+    /// Whether the client launched the app and owns its session.
+    pub fn is_owned(&self) -> bool {
+        self.0.lock().map(|app| app.owned).unwrap_or_default()
+    }
+
+    /// Whether receiver status updates follow the session of the app.
+    pub fn is_tracked(&self) -> bool {
+        self.0.lock().map(|app| app.tracked).unwrap_or_default()
+    }
+
+    /// Stop tracking the app's session and give up ownership of it. Receiver
+    /// status updates no longer connect to the app until it is launched or
+    /// joined again.
+    pub fn release(&self) {
+        if let Ok(mut app) = self.0.lock() {
+            app.owned = false;
+            app.tracked = false;
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Handler {
     connect: RwLock<ConnectState>,
     command: UnboundedSender<Command>,
     status: UnboundedSender<crate::Status>,
+    pending: Pending,
+    app_id: AppId,
 }
 
 impl Handler {
@@ -24,17 +123,21 @@ impl Handler {
         connect: RwLock<ConnectState>,
         command: UnboundedSender<Command>,
         status: UnboundedSender<crate::Status>,
+        pending: Pending,
+        app_id: AppId,
     ) -> Self {
         Self {
             connect,
             command,
             status,
+            pending,
+            app_id,
         }
     }
 }
 
 impl channel::Handler for Handler {
-    type Payload = Response;
+    type Payload = Message;
 
     fn channel(&self) -> &str {
         CHANNEL
@@ -45,23 +148,169 @@ impl channel::Handler for Handler {
     }
 
     fn handle(&self, payload: Self::Payload) -> Result<(), Error> {
-        let Response::ReceiverStatus { status, .. } = payload;
-        let app = status
-            .applications
-            .iter()
-            .find(|app| app.app_id == DEFAULT_MEDIA_RECEIVER_APP_ID);
+        let payload = match payload {
+            Message::Response(payload) => payload,
+            Message::AppAvailability(AppAvailability {
+                request_id,
+                availability,
+            }) => {
+                let availability = availability
+                    .into_iter()
+                    .map(|(app_id, availability)| (app_id, availability == APP_AVAILABLE))
+                    .collect();
+                self.pending.resolve(
+                    request_id,
+                    Ok(crate::Response::AppAvailability(availability)),
+                );
+                return Ok(());
+            }
+        };
+        let status = match payload {
+            Response::ReceiverStatus { request_id, status } => {
+                self.pending.resolve(
+                    request_id,
+                    Ok(crate::Response::ReceiverStatus(Box::new(status.clone()))),
+                );
+                status
+            }
+            Response::LaunchError { request_id, reason } => {
+                warn!("launch failed for request {}: {:?}", request_id, reason);
+                self.pending
+                    .resolve(request_id, Err(CommandError::LaunchFailed(reason.clone())));
+                let err = crate::Error::LaunchFailed(reason);
+                self.status
+                    .unbounded_send(crate::Status::Error(Box::new(err)))
+                    .map_err(|_| Error::StatusSend)?;
+                return Ok(());
+            }
+            Response::InvalidRequest { request_id, reason } => {
+                warn!("invalid request {}: {:?}", request_id, reason);
+                self.pending
+                    .resolve(request_id, Err(CommandError::InvalidRequest(reason)));
+                return Ok(());
+            }
+            Response::Unknown => return Ok(()),
+        };
+        let app_id = self.app_id.get();
+        let app = status.applications.iter().find(|app| app.app_id == app_id);
         let session = app.map(|app| app.session_id.to_owned());
         let transport = app.map(|app| app.transport_id.to_owned());
+        // The app that replaced the launched app, if any. The backdrop is
+        // shown when the app was stopped without launching another.
+        let running = status
+            .applications
+            .iter()
+            .find(|app| !app.is_idle_screen && app.app_id != BACKDROP_APP_ID);
+        let by_app = running.map(|app| app.app_id.to_owned());
+        let display_name = running.map(|app| app.display_name.to_owned());
+        let launched = self.app_id.clone();
+        let input = (status.is_active_input, status.is_stand_by);
+        let volume = status.volume;
         let status = self.status.clone();
         let command = self.command.clone();
-        let connect = self.connect.with_write(move |mut state| {
+        let connect = self.connect.write().and_then(move |mut state| {
             trace!("acquired connect state lock in receiver channel");
+            if state.set_device_volume(Some(volume.clone())) {
+                debug!("device volume changed: {:?}", volume);
+                status
+                    .unbounded_send(crate::Status::DeviceVolume(volume))
+                    .map_err(|_| ())?;
+            }
+            if state.set_input(input) {
+                debug!("device input changed: {:?}", input);
+                let (is_active_input, is_stand_by) = input;
+                status
+                    .unbounded_send(crate::Status::Input {
+                        is_active_input,
+                        is_stand_by,
+                    })
+                    .map_err(|_| ())?;
+            }
+            if state.reconnect {
+                state.reconnect = false;
+                match state.receiver_connection() {
+                    Some(ref connect) if session.deref() == Some(connect.session.as_str()) => {
+                        // The launched app survived the dropped connection.
+                        // Rejoin its transport and keep the media session.
+                        // Commands on the transport are accepted once the
+                        // `CONNECT` is sent, so report the session again.
+                        state.set_transport(transport.deref());
+                        if let Some(connect) = state.receiver_connection() {
+                            debug!("rejoining transport {}", connect.transport);
+                            command
+                                .unbounded_send(Command::Connect(connect.clone()))
+                                .map_err(|_| ())?;
+                            status
+                                .unbounded_send(crate::Status::Connected(Box::new(connect)))
+                                .map_err(|_| ())?;
+                        }
+                        return Ok(());
+                    }
+                    Some(_) => {
+                        // The app session did not survive the dropped
+                        // connection; the media session is lost.
+                        state.set_session(None);
+                        state.set_transport(None);
+                        state.set_media_session(None);
+                        state.lifecycle = SessionLifecycle::NoMediaSession;
+                        if by_app.is_some() {
+                            // Another sender launched an app while the
+                            // connection was down. Report it rather than take
+                            // the device back.
+                            debug!("app session taken over while reconnecting");
+                            launched.release();
+                            status
+                                .unbounded_send(crate::Status::SessionLost {
+                                    by_app,
+                                    display_name,
+                                })
+                                .map_err(|_| ())?;
+                        } else {
+                            debug!("app session lost while reconnecting, relaunching");
+                            command
+                                .unbounded_send(Command::Launch { app_id })
+                                .map_err(|_| ())?;
+                        }
+                        return Ok(());
+                    }
+                    // No app session was established before the connection
+                    // dropped. There is nothing to restore.
+                    None => {}
+                }
+            }
+            if !launched.is_tracked() {
+                // The session belongs to another sender or was stopped by
+                // the client. Wait for the app to be launched again.
+                return Ok(());
+            }
+            let lost = state
+                .receiver_connection()
+                .filter(|connect| session.deref() != Some(connect.session.as_str()))
+                .filter(|_| !launched.is_launching());
+            if let Some(connect) = lost {
+                // Another sender stopped the launched app or replaced its
+                // session.
+                debug!("app session {} lost", connect.session);
+                launched.release();
+                state.set_session(None);
+                state.set_transport(None);
+                state.set_media_session(None);
+                state.lifecycle = SessionLifecycle::NoMediaSession;
+                status
+                    .unbounded_send(crate::Status::SessionLost {
+                        by_app,
+                        display_name,
+                    })
+                    .map_err(|_| ())?;
+                return Ok(());
+            }
             if !state.set_session(session.deref()) || !state.set_transport(transport.deref()) {
                 // Connection did not change
                 return Ok(());
             }
             if let Some(ref connect) = state.receiver_connection() {
                 debug!("connecting to transport {}", connect.transport);
+                launched.connected();
                 status
                     .unbounded_send(crate::Status::Connected(Box::new(connect.clone())))
                     .map_err(|_| ())?;
@@ -73,7 +322,7 @@ impl channel::Handler for Handler {
             }
             Ok(())
         });
-        tokio_executor::spawn(connect.expect("lock spawn"));
+        tokio_executor::spawn(connect);
         Ok(())
     }
 }
@@ -86,54 +335,123 @@ pub enum Request {
     #[serde(rename_all = "camelCase")]
     GetStatus { request_id: i64 },
     #[serde(rename_all = "camelCase")]
-    #[allow(dead_code)]
     GetAppAvailability {
         request_id: i64,
         app_id: Vec<String>,
     },
-    #[allow(dead_code)]
-    SetVolume { volume: Volume },
+    #[serde(rename_all = "camelCase")]
+    SetVolume { request_id: i64, volume: Volume },
+    #[serde(rename_all = "camelCase")]
+    Stop { request_id: i64, session_id: String },
+}
+
+/// Messages on the receiver channel. Responses to `GET_APP_AVAILABILITY` are
+/// tagged by `responseType` rather than `type`.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Message {
+    Response(Response),
+    AppAvailability(AppAvailability),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AppAvailability {
+    pub request_id: i64,
+    pub availability: HashMap<String, String>,
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum Response {
     #[serde(rename_all = "camelCase")]
-    ReceiverStatus { request_id: i64, status: Status },
+    ReceiverStatus {
+        #[serde(default)]
+        request_id: i64,
+        status: Status,
+    },
+    #[serde(rename_all = "camelCase")]
+    LaunchError {
+        request_id: i64,
+        reason: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    InvalidRequest {
+        request_id: i64,
+        reason: Option<String>,
+    },
+    /// Messages the client does not handle.
+    #[serde(other)]
+    Unknown,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Status {
     #[serde(default)]
     pub applications: Vec<Applications>,
+    /// Whether the device is the active input of the TV it is attached to.
+    /// Only reported by devices attached over HDMI-CEC.
+    #[serde(default)]
+    pub is_active_input: Option<bool>,
+    /// Whether the TV the device is attached to is in standby. Only reported
+    /// by devices attached over HDMI-CEC.
     #[serde(default)]
-    pub is_active_input: bool,
+    pub is_stand_by: Option<bool>,
     pub volume: Volume,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Applications {
     pub app_id: String,
     pub display_name: String,
+    #[serde(default)]
+    pub icon_url: Option<String>,
+    /// Set for the backdrop app shown while the device is idle.
+    #[serde(default)]
+    pub is_idle_screen: bool,
+    #[serde(default)]
+    pub launched_from_cloud: bool,
+    #[serde(default)]
     pub namespaces: Vec<Namespace>,
     pub session_id: String,
+    #[serde(default)]
     pub status_text: String,
     pub transport_id: String,
+    #[serde(default)]
+    pub universal_app_id: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct Namespace {
     pub name: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
 pub struct Volume {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub level: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub muted: Option<bool>,
+    /// Reported by the device. Must not be set when changing the volume.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub control_type: Option<VolumeControlType>,
+    /// Reported by the device. Must not be set when changing the volume.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step_interval: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum VolumeControlType {
+    /// The volume can be changed.
+    Attenuation,
+    /// The volume is fixed, e.g. for a device attached to a receiver.
+    Fixed,
+    /// The volume is controlled by the system.
+    Master,
 }
 
 pub fn launch(request_id: i64, app_id: &str) -> CastMessage {
@@ -158,3 +476,70 @@ pub fn status(request_id: i64) -> CastMessage {
         .payload(&payload)
         .into_message()
 }
+
+pub fn set_volume(request_id: i64, volume: Volume) -> CastMessage {
+    let payload = Request::SetVolume { request_id, volume };
+    MessageBuilder::default()
+        .namespace(NAMESPACE)
+        .source(DEFAULT_SENDER_ID)
+        .destination(DEFAULT_DESTINATION_ID)
+        .payload(&payload)
+        .into_message()
+}
+
+pub fn stop(request_id: i64, session: &str) -> CastMessage {
+    let payload = Request::Stop {
+        request_id,
+        session_id: session.to_owned(),
+    };
+    MessageBuilder::default()
+        .namespace(NAMESPACE)
+        .source(DEFAULT_SENDER_ID)
+        .destination(DEFAULT_DESTINATION_ID)
+        .payload(&payload)
+        .into_message()
+}
+
+pub fn app_availability(request_id: i64, app_ids: Vec<String>) -> CastMessage {
+    let payload = Request::GetAppAvailability {
+        request_id,
+        app_id: app_ids,
+    };
+    MessageBuilder::default()
+        .namespace(NAMESPACE)
+        .source(DEFAULT_SENDER_ID)
+        .destination(DEFAULT_DESTINATION_ID)
+        .payload(&payload)
+        .into_message()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Message, Response};
+
+    #[test]
+    fn parses_app_availability() {
+        let payload = r#"{
+            "requestId": 3,
+            "responseType": "GET_APP_AVAILABILITY",
+            "availability": { "CC1AD845": "APP_AVAILABLE", "0F5096E8": "APP_UNAVAILABLE" }
+        }"#;
+        match serde_json::from_str(payload).unwrap() {
+            Message::AppAvailability(availability) => {
+                assert_eq!(availability.request_id, 3);
+                assert_eq!(availability.availability["CC1AD845"], "APP_AVAILABLE");
+                assert_eq!(availability.availability["0F5096E8"], "APP_UNAVAILABLE");
+            }
+            message => panic!("unexpected message: {:?}", message),
+        }
+    }
+
+    #[test]
+    fn parses_unknown_response() {
+        let payload = r#"{ "type": "DEVICE_UPDATED", "requestId": 0, "device": {} }"#;
+        match serde_json::from_str(payload).unwrap() {
+            Message::Response(Response::Unknown) => {}
+            message => panic!("unexpected message: {:?}", message),
+        }
+    }
+}
@@ -1,14 +1,20 @@
+use std::cmp;
 use std::convert::TryInto;
 use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use byteorder::{BigEndian, ByteOrder};
 use bytes::{Buf, BufMut, BytesMut, IntoBuf};
 use protobuf::{CodedOutputStream, Message};
 use tokio_codec::{Decoder, Encoder};
 
+use crate::capture::{Direction, Recorder};
 use crate::channel;
+use crate::metrics::{self, Tracker};
+use crate::pending::Pending;
 use crate::proto;
-use crate::provider::Command;
+use crate::provider::{CodecError, Command, CommandError, DroppedFrames};
 
 /// Protobuf header is a big endian u32.
 const CAST_MESSAGE_HEADER_LENGTH: usize = 4;
@@ -24,6 +30,8 @@ enum DecodeState {
     Header,
     /// Reading a protobuf with a given length.
     Payload(usize),
+    /// Discarding the remaining bytes of a dropped frame.
+    Skip(usize),
 }
 
 impl Default for DecodeState {
@@ -32,12 +40,117 @@ impl Default for DecodeState {
     }
 }
 
+/// Count of frames dropped by the codec, shared with the `Client`.
+#[derive(Clone, Debug, Default)]
+pub struct Counters {
+    inbound: Arc<AtomicUsize>,
+    outbound: Arc<AtomicUsize>,
+}
+
+impl Counters {
+    /// Count an inbound frame that was decoded but could not be handled.
+    pub fn dropped_inbound(&self) {
+        self.inbound.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dropped_frames(&self) -> DroppedFrames {
+        DroppedFrames {
+            inbound: self.inbound.load(Ordering::Relaxed),
+            outbound: self.outbound.load(Ordering::Relaxed),
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct CastMessage {
     state: DecodeState,
     request_id: i64,
     decoded_frames: i64,
     encoded_frames: i64,
+    pending: Pending,
+    counters: Counters,
+    metrics: Tracker,
+    recorder: Option<Recorder>,
+}
+
+/// Build the `CastMessage` protobuf for `command` with the given request id.
+fn into_message(request_id: i64, command: Command) -> proto::CastMessage {
+    match command {
+        Command::AuthChallenge { nonce } => channel::deviceauth::challenge(&nonce),
+        Command::Close(connect) => channel::connection::close(&connect.transport),
+        Command::Connect(connect) => channel::connection::connect(&connect.transport),
+        Command::Custom(custom) => channel::custom::message(&custom),
+        Command::GetAppAvailability { app_ids } => {
+            channel::receiver::app_availability(request_id, app_ids)
+        }
+        Command::GetGroupStatus => channel::multizone::status(request_id),
+        Command::GetMediaStatus(connect) => channel::media::sessions(request_id, &connect),
+        Command::Launch { app_id } => channel::receiver::launch(request_id, &app_id),
+        Command::Load { connect, media } => channel::media::load(request_id, &connect, *media),
+        Command::MediaStatus(connect) => channel::media::status(request_id, &connect),
+        Command::Pause(connect) => channel::media::pause(request_id, &connect),
+        Command::Ping => channel::heartbeat::ping(),
+        Command::Play(connect) => channel::media::play(request_id, &connect),
+        Command::Pong => channel::heartbeat::pong(),
+        Command::QueueInsert {
+            connect,
+            media,
+            insert_before,
+        } => channel::media::queue_insert(request_id, &connect, media, insert_before),
+        Command::QueueLoad {
+            connect,
+            media,
+            start_index,
+        } => channel::media::queue_load(request_id, &connect, media, start_index),
+        Command::QueueNext(connect) => channel::media::queue_next(request_id, &connect),
+        Command::QueuePrev(connect) => channel::media::queue_prev(request_id, &connect),
+        Command::QueueRemove(connect, item_ids) => {
+            channel::media::queue_remove(request_id, &connect, item_ids)
+        }
+        Command::QueueUpdate(connect, item_id) => {
+            channel::media::queue_update(request_id, &connect, item_id)
+        }
+        Command::ReceiverStatus => channel::receiver::status(request_id),
+        Command::Request { command, .. } => into_message(request_id, *command),
+        Command::SetDeviceVolume { level, muted } => {
+            let volume = channel::receiver::Volume {
+                level: level.map(f64::from),
+                muted,
+                ..channel::receiver::Volume::default()
+            };
+            channel::receiver::set_volume(request_id, volume)
+        }
+        Command::SetMemberVolume {
+            device_id,
+            level,
+            muted,
+        } => {
+            let volume = channel::receiver::Volume {
+                level: level.map(f64::from),
+                muted,
+                ..channel::receiver::Volume::default()
+            };
+            channel::multizone::set_volume(request_id, &device_id, volume)
+        }
+        Command::Seek(connect, time) => channel::media::seek(request_id, &connect, time),
+        Command::Shutdown => channel::connection::close(channel::DEFAULT_DESTINATION_ID),
+        Command::Stop(connect) => channel::media::stop(request_id, &connect),
+        Command::StopApp(connect) => channel::receiver::stop(request_id, &connect.session),
+        Command::VolumeLevel(connect, level) => {
+            let volume = channel::media::Volume {
+                level: Some(f64::from(level)),
+                muted: None,
+            };
+            channel::media::volume(request_id, &connect, volume)
+        }
+        Command::VolumeMute(connect, muted) => {
+            let volume = channel::media::Volume {
+                level: None,
+                muted: Some(muted),
+            };
+            channel::media::volume(request_id, &connect, volume)
+        }
+    }
 }
 
 impl Encoder for CastMessage {
@@ -54,21 +167,12 @@ impl Encoder for CastMessage {
             self.encoded_frames,
             item
         );
-        let message = match item {
-            Command::Connect(connect) => channel::connection::connect(&connect.transport),
-            Command::Launch { app_id } => channel::receiver::launch(self.request_id, &app_id),
-            Command::Load { connect, media } => {
-                channel::media::load(self.request_id, &connect, *media)
-            }
-            Command::MediaStatus(connect) => channel::media::status(self.request_id, &connect),
-            Command::Pause(connect) => channel::media::pause(self.request_id, &connect),
-            Command::Ping => channel::heartbeat::ping(),
-            Command::Play(connect) => channel::media::play(self.request_id, &connect),
-            Command::Pong => channel::heartbeat::pong(),
-            Command::ReceiverStatus => channel::receiver::status(self.request_id),
-            Command::Stop(connect) => channel::media::stop(self.request_id, &connect),
-            _ => unimplemented!(), // TODO: implement all commands
+        let (item, reply) = match item {
+            Command::Request { command, reply } => (*command, Some(reply)),
+            item => (item, None),
         };
+        let kind = metrics::kind(&item);
+        let message = into_message(self.request_id, item);
 
         let mut buf = Vec::new();
         let mut output = CodedOutputStream::new(&mut buf);
@@ -78,7 +182,26 @@ impl Encoder for CastMessage {
             .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
 
         if buf.len() > CAST_MESSAGE_PROTOBUF_MAX_LENGTH {
-            panic!("CastMessageCodec encoder generated message of length {}, which is larger than the max message length of {}", buf.len(), CAST_MESSAGE_PROTOBUF_MAX_LENGTH);
+            // Drop the frame rather than sending it. The device would close
+            // the connection.
+            let err = CodecError::FrameTooLarge(buf.len());
+            warn!("codec dropped outbound frame: {}", err);
+            self.counters.outbound.fetch_add(1, Ordering::Relaxed);
+            if let Some(reply) = reply {
+                let _ = reply.send(Err(CommandError::Codec(err)));
+            }
+            return Ok(());
+        }
+        if let Some(reply) = reply {
+            self.pending.register(self.request_id, kind, reply);
+        }
+        match kind {
+            "PING" => self.metrics.ping_sent(),
+            "LOAD" | "QUEUE_LOAD" => self.metrics.load_sent(self.request_id),
+            _ => {}
+        }
+        if let Some(ref recorder) = self.recorder {
+            recorder.record(Direction::Outbound, &message);
         }
 
         // Cast wire protocol is a 4-byte big endian length-prefixed protobuf.
@@ -97,6 +220,25 @@ impl Encoder for CastMessage {
 }
 
 impl CastMessage {
+    /// Construct a codec that registers the request ids of tracked commands
+    /// in `pending`, counts dropped frames in `counters` and records when
+    /// `PING`s and loads are sent in `metrics`. If `recorder` is set, every
+    /// frame that is decoded or encoded is recorded.
+    pub fn new(
+        pending: Pending,
+        counters: Counters,
+        metrics: Tracker,
+        recorder: Option<Recorder>,
+    ) -> Self {
+        Self {
+            pending,
+            counters,
+            metrics,
+            recorder,
+            ..Self::default()
+        }
+    }
+
     /// Cast wire protocol is a 4-byte big endian length-prefixed protobuf. At
     /// least 4 bytes are required to decode the next frame. Read the length of
     /// the following protobuf and reserve that much capacity in the `BytesMut`.
@@ -112,13 +254,26 @@ impl CastMessage {
             }
             usize::from_be_bytes(bytes)
         };
-        if length > CAST_MESSAGE_PROTOBUF_MAX_LENGTH {
-            panic!("CastMessageCodec decoder received message of length {}, which is larger than the max message length of {}", length, CAST_MESSAGE_PROTOBUF_MAX_LENGTH);
+        if length <= CAST_MESSAGE_PROTOBUF_MAX_LENGTH {
+            src.reserve(length);
         }
-        src.reserve(length);
         Some(length)
     }
 
+    /// Discard the remaining bytes of a dropped frame. Returns `true` once the
+    /// whole frame has been discarded.
+    fn skip(&mut self, n: usize, src: &mut BytesMut) -> bool {
+        let available = cmp::min(n, src.len());
+        src.advance(available);
+        if available < n {
+            self.state = DecodeState::Skip(n - available);
+            false
+        } else {
+            self.state = DecodeState::Header;
+            true
+        }
+    }
+
     fn decode_payload(&self, n: usize, src: &mut BytesMut) -> Option<BytesMut> {
         if src.len() < n {
             return None;
@@ -126,26 +281,41 @@ impl CastMessage {
         Some(src.split_to(n))
     }
 
-    fn try_decode(&mut self, src: &mut BytesMut) -> Result<Option<proto::CastMessage>, io::Error> {
+    /// Decode the next frame. Errors if the frame is oversized or malformed.
+    /// The length of the bad frame is known from its header, so the decoder
+    /// skips the frame and remains synchronized with the stream.
+    fn try_decode(&mut self, src: &mut BytesMut) -> Result<Option<proto::CastMessage>, CodecError> {
+        if let DecodeState::Skip(n) = self.state {
+            if !self.skip(n, src) {
+                return Ok(None);
+            }
+        }
         let n = match self.state {
             DecodeState::Header => match self.decode_header(src) {
+                Some(n) if n > CAST_MESSAGE_PROTOBUF_MAX_LENGTH => {
+                    self.state = DecodeState::Skip(n);
+                    return Err(CodecError::FrameTooLarge(n));
+                }
                 Some(n) => n,
                 None => return Ok(None),
             },
-            DecodeState::Payload(n) => n,
+            DecodeState::Payload(n) | DecodeState::Skip(n) => n,
         };
         self.state = DecodeState::Payload(n);
         if let Some(mut src) = self.decode_payload(n, src) {
             self.state = DecodeState::Header;
             src.reserve(CAST_MESSAGE_HEADER_LENGTH);
             let message = protobuf::parse_from_bytes::<proto::CastMessage>(&src)
-                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+                .map_err(|err| CodecError::Malformed(err.to_string()))?;
             self.decoded_frames += 1;
             trace!(
                 "codec decoded frame {} for message in namespace {}",
                 self.decoded_frames,
                 message.get_namespace()
             );
+            if let Some(ref recorder) = self.recorder {
+                recorder.record(Direction::Inbound, &message);
+            }
             Ok(Some(message))
         } else {
             Ok(None)
@@ -158,10 +328,62 @@ impl Decoder for CastMessage {
     type Error = io::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let item = self.try_decode(src);
-        if item.is_err() {
-            warn!("Error in decoder: {:?}", item);
+        loop {
+            match self.try_decode(src) {
+                Ok(item) => return Ok(item),
+                Err(err) => {
+                    warn!("codec dropped inbound frame: {}", err);
+                    self.counters.inbound.fetch_add(1, Ordering::Relaxed);
+                }
+            }
         }
-        item
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use byteorder::{BigEndian, ByteOrder};
+    use bytes::{BufMut, BytesMut};
+    use tokio_codec::{Decoder, Encoder};
+
+    use super::{CastMessage, CAST_MESSAGE_PROTOBUF_MAX_LENGTH};
+    use crate::Command;
+
+    fn frame(payload: &[u8]) -> BytesMut {
+        let mut header = [0; 4];
+        BigEndian::write_u32(&mut header, payload.len().try_into().unwrap());
+        let mut frame = BytesMut::new();
+        frame.put_slice(&header);
+        frame.put_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn skips_oversized_frame() {
+        let mut codec = CastMessage::default();
+        let oversized = vec![0; CAST_MESSAGE_PROTOBUF_MAX_LENGTH + 1];
+        let mut src = frame(&oversized);
+        codec.encode(Command::Ping, &mut src).unwrap();
+        // Deliver the oversized frame in two chunks.
+        let mut rest = src.split_off(1024);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        let message = codec.decode(&mut rest).unwrap().unwrap();
+        assert_eq!(
+            message.get_namespace(),
+            "urn:x-cast:com.google.cast.tp.heartbeat"
+        );
+        assert_eq!(codec.counters.dropped_frames().inbound, 1);
+    }
+
+    #[test]
+    fn skips_malformed_frame() {
+        let mut codec = CastMessage::default();
+        let mut src = frame(&[0xff; 16]);
+        codec.encode(Command::Ping, &mut src).unwrap();
+        assert!(codec.decode(&mut src).unwrap().is_some());
+        assert!(src.is_empty());
+        assert_eq!(codec.counters.dropped_frames().inbound, 1);
     }
 }
@@ -5,109 +5,782 @@
 #[macro_use]
 extern crate log;
 
-use std::io;
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::time::Duration;
 
+use futures::future::Either;
 use futures::prelude::*;
 use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::sync::oneshot;
 use futures::{future, Future, Stream};
 use futures_locks::RwLock;
-use stream_util::{self, Drainable, Trigger};
-use tokio_codec::Framed;
+use stream_util::{self, Drainable};
 use tokio_tcp::TcpStream;
+use tokio_timer::Timeout;
 use tokio_tls::{TlsConnector, TlsStream};
 
+#[cfg(feature = "blocking")]
+mod blocking;
+mod capture;
 mod channel;
 mod codec;
+mod metrics;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+mod pending;
+mod pinning;
+mod poll;
 #[allow(clippy::all, clippy::pedantic)]
 #[allow(warnings)]
 mod proto;
 mod provider;
 mod session;
+#[cfg(feature = "std-future")]
+pub mod std_future;
+mod supervisor;
 mod task;
 
+/// Models of the media channel messages.
+pub mod media {
+    pub use crate::channel::media::{
+        ExtendedPlayerState, ExtendedStatus, IdleReason, Image, MediaInformation, MediaStatus,
+        Metadata, PlayerState, QueueItem, RepeatMode, StreamType, SupportedMediaCommands, Track,
+        TrackType, Volume,
+    };
+}
+
+/// Models of the multizone channel messages.
+pub mod multizone {
+    pub use crate::channel::multizone::Member;
+}
+
+/// Models of the receiver channel messages.
+pub mod receiver {
+    pub use crate::channel::receiver::{
+        Applications, Namespace, Status, Volume, VolumeControlType,
+    };
+}
+
+#[cfg(feature = "blocking")]
+pub use self::blocking::SyncClient;
+pub use self::capture::{Capture, CapturedFrame, Direction, Recorder};
+pub use self::pinning::PinStore;
+pub use self::poll::Polling;
 pub use self::provider::*;
 
+use self::channel::custom::Namespaces;
+use self::channel::deviceauth::DeviceAuth;
+use self::channel::heartbeat::{Heartbeat, Liveness};
+use self::channel::receiver::AppId;
+use self::metrics::Tracker;
+use self::pending::Pending;
+use self::pinning::Pinning;
+use self::poll::Playback;
+use self::supervisor::{Backoff, Shutdown, Supervisor};
+
+/// Default timeout for establishing the TCP connection and TLS handshake.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default timeout for the device to acknowledge a command.
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default delay before the first attempt to reconnect a dropped connection.
+const DEFAULT_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+/// Default upper bound on the delay between reconnect attempts.
+const DEFAULT_RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+/// Default interval between `PING`s sent to the device.
+const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// Default number of heartbeat intervals without a frame from the device
+/// before the connection is declared dead.
+const DEFAULT_HEARTBEAT_MAX_MISSED: u32 = 3;
+
 #[derive(Debug)]
 pub struct Client {
     command: UnboundedSender<Command>,
-    shutdown: Option<Trigger>,
+    shutdown: Shutdown,
     status: UnboundedSender<Status>,
     connect: RwLock<ConnectState>,
+    namespaces: Namespaces,
+    app_id: AppId,
+    counters: codec::Counters,
+    metrics: Tracker,
+    playback: Playback,
+    closed: Option<oneshot::Receiver<()>>,
+    request_timeout: Duration,
 }
 
 impl Client {
-    pub fn launch_app(&self) {
+    /// Send `command` to the device and track its request id.
+    ///
+    /// The command is sent eagerly. The returned future resolves with the
+    /// response that acknowledges the command and may be dropped if the caller
+    /// is not interested in the response.
+    fn request(&self, command: Command) -> impl Future<Item = Response, Error = CommandError> {
+        request(&self.command, command, self.request_timeout)
+    }
+
+    /// Launch the default media receiver app.
+    pub fn launch_app(&self) -> impl Future<Item = Response, Error = CommandError> {
+        self.launch(channel::DEFAULT_MEDIA_RECEIVER_APP_ID)
+    }
+
+    /// Launch the receiver app with id `app_id` and connect to its session
+    /// once it is running. Replaces any app launched previously.
+    pub fn launch(&self, app_id: &str) -> impl Future<Item = Response, Error = CommandError> {
+        self.app_id.set(app_id);
         let launch = Command::Launch {
-            app_id: channel::DEFAULT_MEDIA_RECEIVER_APP_ID.to_owned(),
+            app_id: app_id.to_owned(),
         };
+        self.connect_receiver();
+        self.request(launch)
+    }
+
+    /// Open the virtual connection to the receiver.
+    fn connect_receiver(&self) {
         let _ = self
             .command
             .unbounded_send(Command::Connect(ReceiverConnection {
                 session: channel::DEFAULT_DESTINATION_ID.to_owned(),
                 transport: channel::DEFAULT_DESTINATION_ID.to_owned(),
-            }))
-            .and_then(|_| self.command.unbounded_send(launch));
+            }));
     }
 
-    pub fn load(&self, connect: &ReceiverConnection, media: Media) {
+    /// Query whether the receiver apps with the given ids can be launched on
+    /// the device. Resolves with the availability of each app id.
+    pub fn app_availability(
+        &self,
+        app_ids: &[&str],
+    ) -> impl Future<Item = HashMap<String, bool>, Error = CommandError> {
+        let app_ids = app_ids.iter().map(|&app_id| app_id.to_owned()).collect();
+        self.request(Command::GetAppAvailability { app_ids })
+            .and_then(|response| match response {
+                Response::AppAvailability(availability) => Ok(availability),
+                _ => Err(CommandError::InvalidRequest(None)),
+            })
+    }
+
+    /// Join the session of the receiver app with id `app_id` if it is
+    /// already running, e.g. an app launched by another sender, without
+    /// relaunching it. The client emits [`Status::Connected`] once it
+    /// connects to the session. A joined app is not stopped by
+    /// [`Client::shutdown`].
+    pub fn join(&self, app_id: &str) -> impl Future<Item = Response, Error = CommandError> {
+        self.app_id.join(app_id);
+        self.receiver_status_request()
+    }
+
+    /// Request the status of the device, including the running apps and the
+    /// device volume.
+    pub fn receiver_status(&self) -> impl Future<Item = receiver::Status, Error = CommandError> {
+        self.receiver_status_request()
+            .and_then(|response| match response {
+                Response::ReceiverStatus(status) => Ok(*status),
+                _ => Err(CommandError::InvalidRequest(None)),
+            })
+    }
+
+    fn receiver_status_request(&self) -> impl Future<Item = Response, Error = CommandError> {
+        self.connect_receiver();
+        self.request(Command::ReceiverStatus)
+    }
+
+    /// Request the status of every media session of the app session
+    /// `connect`. The client tracks the first media session, so commands can
+    /// control media loaded by another sender once this resolves.
+    pub fn media_status(
+        &self,
+        connect: &ReceiverConnection,
+    ) -> impl Future<Item = Vec<media::MediaStatus>, Error = CommandError> {
+        self.request(Command::GetMediaStatus(connect.clone()))
+            .and_then(|response| match response {
+                Response::MediaStatus(status) => Ok(status),
+                _ => Err(CommandError::InvalidRequest(None)),
+            })
+    }
+
+    pub fn load(
+        &self,
+        connect: &ReceiverConnection,
+        media: Media,
+    ) -> impl Future<Item = Response, Error = CommandError> {
+        let load = Command::Load {
+            connect: connect.clone(),
+            media: Box::new(media),
+        };
+        let (load, response) = pending::request(load, self.request_timeout);
         let command = self.command.clone();
-        let connect = connect.clone();
         let task = session::invalidate(&self.connect);
-        let task = task.and_then(move |_| {
-            command
-                .unbounded_send(Command::Load {
-                    connect,
-                    media: Box::new(media),
-                })
-                .map_err(|_| ())
-        });
+        let task = task.and_then(move |_| command.unbounded_send(load).map_err(|_| ()));
         tokio_executor::spawn(task);
+        response
+    }
+
+    pub fn pause(
+        &self,
+        connect: &MediaConnection,
+    ) -> impl Future<Item = Response, Error = CommandError> {
+        self.request(Command::Pause(connect.clone()))
+    }
+
+    pub fn play(
+        &self,
+        connect: &MediaConnection,
+    ) -> impl Future<Item = Response, Error = CommandError> {
+        self.request(Command::Play(connect.clone()))
+    }
+
+    /// Load `media` into the queue of the receiver app and start playing the
+    /// item at `start_index`. Replaces the current queue.
+    ///
+    /// Items after the current item are preloaded by the device before the
+    /// current item finishes, so advancing the queue does not incur the
+    /// buffering delay of a `load`.
+    pub fn queue_load(
+        &self,
+        connect: &ReceiverConnection,
+        media: Vec<Media>,
+        start_index: u32,
+    ) -> impl Future<Item = Response, Error = CommandError> {
+        let load = Command::QueueLoad {
+            connect: connect.clone(),
+            media,
+            start_index,
+        };
+        let (load, response) = pending::request(load, self.request_timeout);
+        let command = self.command.clone();
+        let task = session::invalidate(&self.connect);
+        let task = task.and_then(move |_| command.unbounded_send(load).map_err(|_| ()));
+        tokio_executor::spawn(task);
+        response
+    }
+
+    /// Insert `media` into the queue before the item with id `insert_before`,
+    /// or at the end of the queue if `None`.
+    pub fn queue_insert(
+        &self,
+        connect: &MediaConnection,
+        media: Vec<Media>,
+        insert_before: Option<i64>,
+    ) -> impl Future<Item = Response, Error = CommandError> {
+        self.request(Command::QueueInsert {
+            connect: connect.clone(),
+            media,
+            insert_before,
+        })
+    }
+
+    /// Remove the items with the given ids from the queue.
+    pub fn queue_remove(
+        &self,
+        connect: &MediaConnection,
+        item_ids: Vec<i64>,
+    ) -> impl Future<Item = Response, Error = CommandError> {
+        self.request(Command::QueueRemove(connect.clone(), item_ids))
+    }
+
+    /// Jump to the queue item with id `item_id`.
+    pub fn queue_update(
+        &self,
+        connect: &MediaConnection,
+        item_id: i64,
+    ) -> impl Future<Item = Response, Error = CommandError> {
+        self.request(Command::QueueUpdate(connect.clone(), item_id))
+    }
+
+    pub fn queue_next(
+        &self,
+        connect: &MediaConnection,
+    ) -> impl Future<Item = Response, Error = CommandError> {
+        self.request(Command::QueueNext(connect.clone()))
+    }
+
+    pub fn queue_prev(
+        &self,
+        connect: &MediaConnection,
+    ) -> impl Future<Item = Response, Error = CommandError> {
+        self.request(Command::QueuePrev(connect.clone()))
+    }
+
+    pub fn seek(
+        &self,
+        connect: &MediaConnection,
+        time: f64,
+    ) -> impl Future<Item = Response, Error = CommandError> {
+        self.request(Command::Seek(connect.clone(), time))
+    }
+
+    /// Set the volume of the media stream. `level` is in the range `[0, 1]`.
+    ///
+    /// Stream volume is distinct from device volume.
+    pub fn set_volume(
+        &self,
+        connect: &MediaConnection,
+        level: f32,
+    ) -> impl Future<Item = Response, Error = CommandError> {
+        self.request(Command::VolumeLevel(connect.clone(), level))
+    }
+
+    pub fn set_muted(
+        &self,
+        connect: &MediaConnection,
+        muted: bool,
+    ) -> impl Future<Item = Response, Error = CommandError> {
+        self.request(Command::VolumeMute(connect.clone(), muted))
+    }
+
+    /// Set the volume of the device. `level` is in the range `[0, 1]`.
+    ///
+    /// Device volume is distinct from media stream volume. Fields that are
+    /// `None` are left unmodified.
+    pub fn set_device_volume(
+        &self,
+        level: Option<f32>,
+        muted: Option<bool>,
+    ) -> impl Future<Item = Response, Error = CommandError> {
+        self.request(Command::SetDeviceVolume { level, muted })
+    }
+
+    pub fn stop(
+        &self,
+        connect: &MediaConnection,
+    ) -> impl Future<Item = Response, Error = CommandError> {
+        self.request(Command::Stop(connect.clone()))
+    }
+
+    /// Subscribe to messages on an application-defined `namespace`, e.g. the
+    /// namespace of a custom receiver app.
+    ///
+    /// Messages on the namespaces used by the client itself are never
+    /// delivered. Subscribing to a namespace again terminates the stream
+    /// returned by the previous subscription.
+    pub fn subscribe(&self, namespace: &str) -> UnboundedReceiver<CustomMessage> {
+        self.namespaces.subscribe(namespace)
+    }
+
+    /// Send `payload` on an application-defined `namespace` to `destination`,
+    /// which is usually the transport id of a receiver app session.
+    ///
+    /// Custom messages are not tracked by request id. Responses, if any, are
+    /// delivered to the stream returned by [`Client::subscribe`].
+    pub fn send_custom(
+        &self,
+        namespace: &str,
+        destination: &str,
+        payload: Payload,
+    ) -> Result<(), CommandError> {
+        let custom = CustomMessage {
+            namespace: namespace.to_owned(),
+            source: channel::DEFAULT_SENDER_ID.to_owned(),
+            destination: destination.to_owned(),
+            payload,
+        };
+        self.command
+            .unbounded_send(Command::Custom(Box::new(custom)))
+            .map_err(|_| CommandError::Closed)
     }
 
-    pub fn pause(&self, connect: &MediaConnection) {
-        let _ = self.command.unbounded_send(Command::Pause(connect.clone()));
+    /// Request the member devices of the Cast group. Errors with
+    /// [`CommandError::Timeout`] if the device is not a group.
+    pub fn group_status(&self) -> impl Future<Item = Vec<multizone::Member>, Error = CommandError> {
+        self.request(Command::GetGroupStatus)
+            .and_then(|response| match response {
+                Response::GroupStatus(members) => Ok(members),
+                _ => Err(CommandError::InvalidRequest(None)),
+            })
     }
 
-    pub fn play(&self, connect: &MediaConnection) {
-        let _ = self.command.unbounded_send(Command::Play(connect.clone()));
+    /// Set the volume of the member of the Cast group with id `device_id`.
+    /// `level` is in the range `[0, 1]`. Fields that are `None` are left
+    /// unmodified.
+    ///
+    /// Resolves with the updated member as [`Response::GroupStatus`] once the
+    /// group acknowledges the change. The change is also reported as
+    /// [`Status::GroupMemberUpdated`].
+    pub fn set_member_volume(
+        &self,
+        device_id: &str,
+        level: Option<f32>,
+        muted: Option<bool>,
+    ) -> impl Future<Item = Response, Error = CommandError> {
+        self.request(Command::SetMemberVolume {
+            device_id: device_id.to_owned(),
+            level,
+            muted,
+        })
     }
 
-    pub fn stop(&self, connect: &MediaConnection) {
-        let _ = self.command.unbounded_send(Command::Stop(connect.clone()));
+    /// Count of frames dropped because they were oversized, malformed, could
+    /// not be parsed or were on a namespace no channel handles.
+    pub fn dropped_frames(&self) -> DroppedFrames {
+        self.counters.dropped_frames()
     }
 
-    pub fn shutdown(&mut self) {
-        let trigger = self.shutdown.take();
-        if let Some(trigger) = trigger {
-            trigger.terminate();
+    /// Snapshot of the latency of the connection to the device: heartbeat
+    /// round-trip time, time from load to playback, and time for the device
+    /// to acknowledge each type of command.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.snapshot()
+    }
+
+    /// Playback position, in seconds, at which the caller needs an up-to-date
+    /// media status, e.g. the end of a round. The client polls the device
+    /// more often as playback approaches it. `None` clears the deadline.
+    pub fn set_deadline(&self, position: Option<f64>) {
+        self.playback.set_deadline(position);
+    }
+
+    /// Gracefully shut down the connection to the device.
+    ///
+    /// Queues the commands that stop the media session and the receiver app,
+    /// if the client launched it, and close the virtual connections to the
+    /// app transport and the receiver before returning, so the device is told
+    /// to stop even if the returned future is dropped. If a channel handler is
+    /// updating the connection state, the commands are queued once the update
+    /// is applied and the returned future must be polled for the shutdown to
+    /// proceed. The returned future resolves once the socket is closed.
+    pub fn shutdown(&mut self) -> impl Future<Item = (), Error = ()> {
+        // An app joined with `join` belongs to another sender.
+        let owned = self.app_id.is_owned();
+        self.disconnect(owned)
+    }
+
+    /// Close the virtual connections to the app transport and the receiver
+    /// and the socket, leaving the receiver app and its media playing. See
+    /// [`Client::shutdown`] for when the commands are queued.
+    pub fn close(&mut self) -> impl Future<Item = (), Error = ()> {
+        self.disconnect(false)
+    }
+
+    fn disconnect(&mut self, stop_app: bool) -> impl Future<Item = (), Error = ()> {
+        // Stopping the app below must not be reported as a lost session.
+        self.app_id.release();
+        let command = self.command.clone();
+        let shutdown = self.shutdown.clone();
+        let closed = self.closed.take();
+
+        let stopped = match self.connect.try_read() {
+            Ok(state) => {
+                stop(&command, &shutdown, &state, stop_app);
+                Either::A(future::ok(()))
+            }
+            Err(()) => Either::B(
+                self.connect
+                    .read()
+                    .map(move |state| stop(&command, &shutdown, &state, stop_app)),
+            ),
+        };
+        stopped.and_then(move |_| match closed {
+            Some(closed) => Either::A(closed.then(|_| Ok(()))),
+            None => Either::B(future::ok(())),
+        })
+    }
+}
+
+/// Queue the commands that close the connection to the device described by
+/// `state`. The media session and the receiver app are stopped first if
+/// `stop_app` is set.
+fn stop(
+    command: &UnboundedSender<Command>,
+    shutdown: &Shutdown,
+    state: &ConnectState,
+    stop_app: bool,
+) {
+    if stop_app {
+        if let Some(media) = state.media_connection() {
+            let _ = command.unbounded_send(Command::Stop(media));
         }
-        if !self.command.is_closed() {
-            let _ = self.command.close();
+    }
+    if let Some(receiver) = state.receiver_connection() {
+        if stop_app {
+            let _ = command.unbounded_send(Command::StopApp(receiver.clone()));
         }
+        let _ = command.unbounded_send(Command::Close(receiver));
     }
+    // Close the virtual connection to the receiver. The command channel is
+    // drained after the valve is triggered, so these messages are flushed to
+    // the socket before the connection is torn down.
+    let _ = command.unbounded_send(Command::Shutdown);
+    shutdown.terminate();
 }
 
-/// Asynchronously establish a TLS connection.
-fn tls_connect(addr: SocketAddr) -> impl Future<Item = TlsStream<TcpStream>, Error = io::Error> {
+fn request(
+    tx: &UnboundedSender<Command>,
+    command: Command,
+    timeout: Duration,
+) -> impl Future<Item = Response, Error = CommandError> {
+    let (command, response) = pending::request(command, timeout);
+    // If the send fails, the reply handle is dropped and the response future
+    // resolves with `CommandError::Closed`.
+    let _ = tx.unbounded_send(command);
+    response
+}
+
+/// Builder for configuring a [`Client`] before connecting to a device.
+#[derive(Debug, Default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ClientBuilder {
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    reconnect_delay: Option<Duration>,
+    reconnect_max_delay: Option<Duration>,
+    heartbeat_interval: Option<Duration>,
+    heartbeat_max_missed: Option<u32>,
+    device_auth: Option<DeviceAuth>,
+    pinning: Option<Pinning>,
+    polling: Option<Polling>,
+    metrics_interval: Option<Duration>,
+    recorder: Option<Recorder>,
+}
+
+impl ClientBuilder {
+    /// How long to wait for the TCP connection and TLS handshake with the
+    /// device, both initially and when reconnecting. If the timeout elapses
+    /// the attempt fails with [`Error::ConnectTimeout`]. Defaults to 10
+    /// seconds.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// How long to wait for the device to acknowledge a command before the
+    /// command future fails with [`CommandError::Timeout`]. Defaults to 10
+    /// seconds.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Exponential backoff for reconnecting when the connection to the device
+    /// drops. The first attempt is made after `initial` and the delay doubles
+    /// with each failed attempt up to `max`. Defaults to 500 milliseconds and
+    /// 30 seconds.
+    pub fn reconnect_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.reconnect_delay = Some(initial);
+        self.reconnect_max_delay = Some(max);
+        self
+    }
+
+    /// Send a `PING` to the device every `interval` and declare the
+    /// connection dead if no frame is received from the device for
+    /// `max_missed` intervals. Defaults to 5 seconds and 3 intervals.
+    ///
+    /// When the connection is declared dead, the client emits
+    /// [`Status::Disconnected`] and shuts down.
+    ///
+    /// The connect future fails with [`Error::InvalidHeartbeat`] if
+    /// `interval` is zero or `max_missed` is `0`.
+    pub fn heartbeat(mut self, interval: Duration, max_missed: u32) -> Self {
+        self.heartbeat_interval = Some(interval);
+        self.heartbeat_max_missed = Some(max_missed);
+        self
+    }
+
+    /// Authenticate the device with the `deviceauth` challenge after each
+    /// TLS connection is established. `roots` are the DER-encoded Cast root
+    /// certificate authorities the device certificate chain must chain to.
+    ///
+    /// If `strict` is set, a device that fails authentication is rejected
+    /// and the connection fails. Otherwise the failure is only logged.
+    /// Disabled by default.
+    pub fn device_auth(mut self, roots: Vec<Vec<u8>>, strict: bool) -> Self {
+        self.device_auth = Some(DeviceAuth { roots, strict });
+        self
+    }
+
+    /// Pin the TLS certificate of the device identified by `device_id` in
+    /// `store` on first use. Later connections to the device check its
+    /// certificate against the pinned fingerprint.
+    ///
+    /// If `strict` is set, a device presenting a different certificate is
+    /// rejected and the connection fails with [`Error::CertificateMismatch`].
+    /// Otherwise the mismatch is reported as [`Status::Error`]. Disabled by
+    /// default.
+    pub fn pin_certificate(mut self, store: PinStore, device_id: &str, strict: bool) -> Self {
+        self.pinning = Some(Pinning {
+            store,
+            device_id: device_id.to_owned(),
+            strict,
+        });
+        self
+    }
+
+    /// Cadence for polling the device for status. The device broadcasts
+    /// status changes, so polls only refresh the playback position. Defaults
+    /// to [`Polling::default`].
+    pub fn status_polling(mut self, polling: Polling) -> Self {
+        self.polling = Some(polling);
+        self
+    }
+
+    /// Emit a [`Status::Metrics`] snapshot of the connection latency every
+    /// `interval`. Disabled by default. The latest snapshot is always
+    /// available from [`Client::metrics`].
+    pub fn metrics_interval(mut self, interval: Duration) -> Self {
+        self.metrics_interval = Some(interval);
+        self
+    }
+
+    /// Record every frame exchanged with the device to `recorder`, including
+    /// frames on reconnected sockets. A recorded session can be replayed with
+    /// [`Capture::replay`]. Disabled by default.
+    pub fn capture(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Connect to the device at `addr`. Returns the client, a stream of
+    /// status updates, and a future that resolves once the connection is
+    /// established. The future must be spawned on a tokio runtime.
+    pub fn connect(
+        self,
+        addr: SocketAddr,
+    ) -> (
+        Client,
+        UnboundedReceiver<Status>,
+        impl Future<Item = (), Error = Error>,
+    ) {
+        let (command_tx, command_rx) = mpsc::unbounded();
+        let (status_tx, status_rx) = mpsc::unbounded();
+        let (closed_tx, closed_rx) = oneshot::channel();
+
+        let (trigger, valve) = stream_util::valve();
+
+        let connect = RwLock::new(ConnectState::default());
+        let metrics = Tracker::default();
+        let pending = Pending::new(metrics.clone());
+        let namespaces = Namespaces::default();
+        let app_id = AppId::default();
+        let counters = codec::Counters::default();
+        let playback = Playback::default();
+        let liveness = Liveness::default();
+        let shutdown = Shutdown::new(trigger);
+        let polling = self.polling.unwrap_or_default();
+        let connect_timeout = self.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+        let heartbeat = Heartbeat {
+            interval: self
+                .heartbeat_interval
+                .unwrap_or(DEFAULT_HEARTBEAT_INTERVAL),
+            max_missed: self
+                .heartbeat_max_missed
+                .unwrap_or(DEFAULT_HEARTBEAT_MAX_MISSED),
+        };
+        let cast = Client {
+            command: command_tx.clone(),
+            shutdown: shutdown.clone(),
+            status: status_tx.clone(),
+            connect: connect.clone(),
+            namespaces: namespaces.clone(),
+            app_id: app_id.clone(),
+            counters: counters.clone(),
+            metrics: metrics.clone(),
+            playback: playback.clone(),
+            closed: Some(closed_rx),
+            request_timeout: self.request_timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT),
+        };
+        let supervisor = Supervisor {
+            addr,
+            connect_timeout,
+            backoff: Backoff {
+                initial: self.reconnect_delay.unwrap_or(DEFAULT_RECONNECT_DELAY),
+                max: self
+                    .reconnect_max_delay
+                    .unwrap_or(DEFAULT_RECONNECT_MAX_DELAY),
+            },
+            auth: self.device_auth,
+            pinning: self.pinning,
+            recorder: self.recorder,
+            connect: connect.clone(),
+            command: command_tx.clone(),
+            status: status_tx.clone(),
+            pending,
+            counters,
+            metrics: metrics.clone(),
+            namespaces,
+            app_id,
+            playback: playback.clone(),
+            liveness: liveness.clone(),
+            shutdown: shutdown.clone(),
+            valve: valve.clone(),
+        };
+        let metrics_interval = self.metrics_interval;
+        let open = supervisor.clone();
+        let init = if heartbeat.is_valid() {
+            Ok(())
+        } else {
+            Err(Error::InvalidHeartbeat)
+        };
+        let init = init
+            .into_future()
+            .and_then(move |()| tls_connect(addr, connect_timeout))
+            .and_then(move |socket| open.open(socket));
+        let init = init.map(move |transport| {
+            info!("TLS connection established");
+            let run = supervisor.run(transport, command_rx.drain(valve.clone()));
+            tokio_executor::spawn(run.then(move |_| {
+                let _ = closed_tx.send(());
+                Ok(())
+            }));
+            if let Some(interval) = metrics_interval {
+                tokio_executor::spawn(task::report_metrics(
+                    valve.clone(),
+                    interval,
+                    metrics,
+                    status_tx.clone(),
+                ));
+            }
+            tokio_executor::spawn(task::keepalive(
+                valve.clone(),
+                heartbeat,
+                liveness,
+                command_tx.clone(),
+                status_tx,
+                shutdown,
+            ));
+            tokio_executor::spawn(task::poll_status(
+                valve, connect, playback, polling, command_tx,
+            ));
+        });
+        let init = init.map_err(|err| {
+            warn!("error during cast client init: {:?}", err);
+            err
+        });
+        (cast, status_rx, init)
+    }
+}
+
+/// Asynchronously establish a TLS connection. Errors if the connection is
+/// not established within `timeout`.
+fn tls_connect(
+    addr: SocketAddr,
+    timeout: Duration,
+) -> impl Future<Item = TlsStream<TcpStream>, Error = Error> {
     let connector = native_tls::TlsConnector::builder()
         .danger_accept_invalid_hostnames(true)
         .danger_accept_invalid_certs(true)
         .build()
         .map(TlsConnector::from)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+        .map_err(Error::Tls);
     let connector = match connector {
         Ok(connector) => connector,
         Err(err) => return future::Either::A(future::err(err)),
     };
     let connect = TcpStream::connect(&addr)
-        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+        .map_err(Error::Connect)
         .and_then(move |socket| {
             info!("Establishing TLS connection to {:?}", addr);
             connector
                 .connect(&addr.ip().to_string(), socket)
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+                .map_err(Error::Tls)
         });
+    let connect = Timeout::new(connect, timeout).map_err(move |err| {
+        if err.is_elapsed() {
+            Error::ConnectTimeout(timeout)
+        } else if err.is_timer() {
+            warn!("connect timer failed: {:?}", err);
+            Error::Timer
+        } else {
+            err.into_inner().unwrap_or(Error::Timer)
+        }
+    });
     future::Either::B(connect)
 }
 
@@ -116,32 +789,46 @@ pub fn connect(
 ) -> (
     Client,
     UnboundedReceiver<Status>,
-    impl Future<Item = (), Error = ()>,
+    impl Future<Item = (), Error = Error>,
 ) {
-    let (command_tx, command_rx) = mpsc::unbounded();
-    let (status_tx, status_rx) = mpsc::unbounded();
+    ClientBuilder::default().connect(addr)
+}
 
-    let (trigger, valve) = stream_util::valve();
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::time::Duration;
 
-    let connect = RwLock::new(ConnectState::default());
-    let cast = Client {
-        command: command_tx.clone(),
-        shutdown: Some(trigger),
-        status: status_tx.clone(),
-        connect: connect.clone(),
-    };
-    let init = tls_connect(addr).map(move |socket| {
-        info!("TLS connection established");
-        let (sink, source) = Framed::new(socket, codec::CastMessage::default()).split();
-        tokio_executor::spawn(task::respond(source, &connect, &command_tx, &status_tx));
-        tokio_executor::spawn(task::send(sink, command_rx.drain(valve.clone())));
-        tokio_executor::spawn(task::keepalive(valve.clone(), command_tx.clone()));
-        tokio_executor::spawn(task::poll_status(
-            valve.clone(),
-            connect.clone(),
-            command_tx.clone(),
-        ));
-    });
-    let init = init.map_err(|err| warn!("error during cast client init: {:?}", err));
-    (cast, status_rx, init)
+    use tokio::runtime::Runtime;
+
+    use super::{ClientBuilder, Error};
+
+    #[test]
+    fn rejects_heartbeat_without_missed_intervals() {
+        let mut runtime = Runtime::new().unwrap();
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap();
+        let (_client, _status, connect) = ClientBuilder::default()
+            .heartbeat(Duration::from_secs(5), 0)
+            .connect(addr);
+        match runtime.block_on(connect) {
+            Err(Error::InvalidHeartbeat) => {}
+            result => panic!("unexpected connect result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn reports_refused_connection() {
+        let mut runtime = Runtime::new().unwrap();
+        // Nothing listens on the port once the listener is dropped.
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap();
+        let connect = super::tls_connect(addr, Duration::from_secs(5));
+        match runtime.block_on(connect) {
+            Err(Error::Connect(_)) => {}
+            result => panic!("unexpected connect result: {:?}", result.map(|_| ())),
+        }
+    }
 }
@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::provider::{Command, Latency, Metrics};
+
+impl Latency {
+    fn record(&mut self, sample: Duration) {
+        self.last = sample;
+        self.max = self.max.max(sample);
+        self.total += sample;
+        self.samples += 1;
+    }
+
+    /// Mean of all samples.
+    pub fn mean(&self) -> Duration {
+        u32::try_from(self.samples)
+            .ok()
+            .and_then(|samples| self.total.checked_div(samples))
+            .unwrap_or_default()
+    }
+}
+
+/// `LOAD` that has not started playing.
+#[derive(Clone, Copy, Debug)]
+struct Load {
+    request_id: i64,
+    sent: Instant,
+    /// Set once the device answers the `LOAD`. Earlier media statuses
+    /// describe the previous media.
+    answered: bool,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Send time of the oldest `PING` that has not been answered.
+    ping: Option<Instant>,
+    load: Option<Load>,
+    heartbeat: Option<Latency>,
+    load_to_play: Option<Latency>,
+    commands: HashMap<&'static str, Latency>,
+}
+
+/// Connection latency measurements, shared by the codec, the channel
+/// handlers and the `Client`.
+#[derive(Clone, Debug, Default)]
+pub struct Tracker(Arc<Mutex<State>>);
+
+impl Tracker {
+    /// Record that a `PING` was written to the device.
+    ///
+    /// If an earlier `PING` is unanswered, the round trip is measured from
+    /// the earlier `PING`, so a device that skips a `PONG` reads as slow
+    /// rather than fast.
+    pub fn ping_sent(&self) {
+        if let Ok(mut state) = self.0.lock() {
+            state.ping.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// Record that the device answered a `PING`.
+    pub fn pong_received(&self) {
+        if let Ok(mut state) = self.0.lock() {
+            if let Some(sent) = state.ping.take() {
+                state
+                    .heartbeat
+                    .get_or_insert_with(Latency::default)
+                    .record(sent.elapsed());
+            }
+        }
+    }
+
+    /// Record that a `LOAD` with the given request id was written to the
+    /// device. Replaces a previous load that has not started playing.
+    pub fn load_sent(&self, request_id: i64) {
+        if let Ok(mut state) = self.0.lock() {
+            state.load = Some(Load {
+                request_id,
+                sent: Instant::now(),
+                answered: false,
+            });
+        }
+    }
+
+    /// Record a media status from the device in response to the request with
+    /// the given id. Only the first `PLAYING` status for the media of the last
+    /// load is measured.
+    pub fn media_status(&self, request_id: i64, playing: bool) {
+        if let Ok(mut state) = self.0.lock() {
+            let load = match state.load.as_mut() {
+                Some(load) => {
+                    load.answered |= load.request_id == request_id;
+                    *load
+                }
+                None => return,
+            };
+            if playing && load.answered {
+                state.load = None;
+                state
+                    .load_to_play
+                    .get_or_insert_with(Latency::default)
+                    .record(load.sent.elapsed());
+            }
+        }
+    }
+
+    /// Record that the device acknowledged a command of type `kind` that was
+    /// sent at `sent`.
+    pub fn acknowledged(&self, kind: &'static str, sent: Instant) {
+        if let Ok(mut state) = self.0.lock() {
+            state
+                .commands
+                .entry(kind)
+                .or_insert_with(Latency::default)
+                .record(sent.elapsed());
+        }
+    }
+
+    /// Forget the `PING` and `LOAD` in flight on a dropped connection. Their
+    /// responses will never arrive.
+    pub fn reset_in_flight(&self) {
+        if let Ok(mut state) = self.0.lock() {
+            state.ping = None;
+            state.load = None;
+        }
+    }
+
+    pub fn snapshot(&self) -> Metrics {
+        self.0
+            .lock()
+            .map(|state| Metrics {
+                heartbeat_rtt: state.heartbeat,
+                load_to_play: state.load_to_play,
+                command_latency: state.commands.clone(),
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Message type of `command` on the wire, used to group command latencies.
+pub fn kind(command: &Command) -> &'static str {
+    match *command {
+        Command::AuthChallenge { .. } => "AUTH_CHALLENGE",
+        Command::Close(_) | Command::Shutdown => "CLOSE",
+        Command::Connect(_) => "CONNECT",
+        Command::Custom(_) => "CUSTOM",
+        Command::GetAppAvailability { .. } => "GET_APP_AVAILABILITY",
+        Command::GetGroupStatus
+        | Command::GetMediaStatus(_)
+        | Command::MediaStatus(_)
+        | Command::ReceiverStatus => "GET_STATUS",
+        Command::Launch { .. } => "LAUNCH",
+        Command::Load { .. } => "LOAD",
+        Command::Pause(_) => "PAUSE",
+        Command::Ping => "PING",
+        Command::Play(_) => "PLAY",
+        Command::Pong => "PONG",
+        Command::QueueInsert { .. } => "QUEUE_INSERT",
+        Command::QueueLoad { .. } => "QUEUE_LOAD",
+        Command::QueueNext(_) | Command::QueuePrev(_) | Command::QueueUpdate(..) => "QUEUE_UPDATE",
+        Command::QueueRemove(..) => "QUEUE_REMOVE",
+        Command::Request { ref command, .. } => kind(command),
+        Command::Seek(..) => "SEEK",
+        Command::SetDeviceVolume { .. }
+        | Command::SetMemberVolume { .. }
+        | Command::VolumeLevel(..)
+        | Command::VolumeMute(..) => "SET_VOLUME",
+        Command::Stop(_) => "STOP",
+        Command::StopApp(_) => "STOP_APP",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use futures::future;
+    use futures::prelude::*;
+    use tokio::runtime::Runtime;
+    use url::Url;
+
+    use super::Tracker;
+    use crate::mock::MockReceiver;
+    use crate::{Media, Status};
+
+    #[test]
+    fn measures_from_oldest_unanswered_ping() {
+        let tracker = Tracker::default();
+        tracker.ping_sent();
+        thread::sleep(Duration::from_millis(10));
+        tracker.ping_sent();
+        tracker.pong_received();
+        // The second `PONG` has no `PING` in flight and is not measured.
+        tracker.pong_received();
+        let heartbeat = tracker.snapshot().heartbeat_rtt.unwrap();
+        assert_eq!(heartbeat.samples, 1);
+        assert!(heartbeat.last >= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn groups_command_latency_by_kind() {
+        let tracker = Tracker::default();
+        let sent = Instant::now();
+        tracker.acknowledged("PLAY", sent);
+        tracker.acknowledged("PLAY", sent);
+        tracker.acknowledged("LOAD", sent);
+        let metrics = tracker.snapshot();
+        assert_eq!(metrics.command_latency["PLAY"].samples, 2);
+        assert_eq!(metrics.command_latency["LOAD"].samples, 1);
+    }
+
+    #[test]
+    fn measures_load_once_answered() {
+        let tracker = Tracker::default();
+        tracker.load_sent(3);
+        // Status of the previous media, broadcast before the load is handled.
+        tracker.media_status(0, true);
+        assert!(tracker.snapshot().load_to_play.is_none());
+        tracker.media_status(3, false);
+        tracker.media_status(0, true);
+        tracker.media_status(0, true);
+        assert_eq!(tracker.snapshot().load_to_play.unwrap().samples, 1);
+    }
+
+    #[test]
+    fn measures_launch_and_load_against_receiver() {
+        let mut runtime = Runtime::new().unwrap();
+        let (receiver, serve) = MockReceiver::bind().unwrap();
+        runtime.spawn(serve);
+        let (client, status, init) = crate::connect(receiver.addr());
+        runtime.block_on(init).unwrap();
+        runtime.block_on(client.launch_app()).unwrap();
+        let connected = status.filter_map(|status| match status {
+            Status::Connected(connect) => Some(*connect),
+            _ => None,
+        });
+        let connect = match runtime.block_on(connected.into_future()) {
+            Ok((Some(connect), _)) => connect,
+            _ => panic!("client did not connect to the launched app"),
+        };
+        let media = Media {
+            title: None,
+            artist: None,
+            album: None,
+            url: Url::parse("http://127.0.0.1:8000/round-1.mp3").unwrap(),
+            cover: None,
+            content_type: "audio/mpeg".to_owned(),
+            duration: Some(60.0),
+        };
+        let load = future::lazy(move || {
+            let response = client.load(&connect, media);
+            response.map(move |_| client)
+        });
+        let mut client = runtime.block_on(load).unwrap();
+
+        let metrics = client.metrics();
+        assert_eq!(metrics.load_to_play.unwrap().samples, 1);
+        assert_eq!(metrics.command_latency["LAUNCH"].samples, 1);
+        assert_eq!(metrics.command_latency["LOAD"].samples, 1);
+        runtime.block_on(client.shutdown()).unwrap();
+        receiver.shutdown();
+    }
+}
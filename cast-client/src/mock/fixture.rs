@@ -0,0 +1,59 @@
+//! Setup shared by the tests that drive a client against a `MockReceiver`.
+use futures::sync::mpsc::UnboundedReceiver;
+use futures::Stream;
+use tokio::runtime::Runtime;
+use url::Url;
+
+use super::MockReceiver;
+use crate::{Client, ClientBuilder, Media, ReceiverConnection, Status};
+
+/// Bind a mock receiver and serve it on a new runtime.
+pub fn serve() -> (Runtime, MockReceiver) {
+    let mut runtime = Runtime::new().unwrap();
+    let (receiver, serve) = MockReceiver::bind().unwrap();
+    runtime.spawn(serve);
+    (runtime, receiver)
+}
+
+/// Connect a client configured by `builder` to `receiver`, launch the default
+/// media receiver app and wait for the client to connect to its session.
+pub fn launch(
+    runtime: &mut Runtime,
+    receiver: &MockReceiver,
+    builder: ClientBuilder,
+) -> (Client, UnboundedReceiver<Status>, ReceiverConnection) {
+    let (client, status, init) = builder.connect(receiver.addr());
+    runtime.block_on(init).unwrap();
+    runtime.block_on(client.launch_app()).unwrap();
+    let (connect, status) = connected(runtime, status);
+    (client, status, connect)
+}
+
+/// Wait for the client to connect to an app session. Returns the session and
+/// the remaining status updates.
+pub fn connected(
+    runtime: &mut Runtime,
+    mut status: UnboundedReceiver<Status>,
+) -> (ReceiverConnection, UnboundedReceiver<Status>) {
+    loop {
+        match runtime.block_on(status.into_future()) {
+            Ok((Some(Status::Connected(connect)), rest)) => return (*connect, rest),
+            Ok((Some(_), rest)) => status = rest,
+            _ => panic!("client did not connect to an app session"),
+        }
+    }
+}
+
+/// Track `index` of a game, served by a media server that is never
+/// requested.
+pub fn media(index: u32) -> Media {
+    Media {
+        title: Some(format!("Round {}", index)),
+        artist: None,
+        album: None,
+        url: Url::parse(&format!("http://127.0.0.1:8000/round-{}.mp3", index)).unwrap(),
+        cover: None,
+        content_type: "audio/mpeg".to_owned(),
+        duration: Some(60.0),
+    }
+}
@@ -0,0 +1,934 @@
+//! In-process mock Cast receiver for integration tests.
+//!
+//! `MockReceiver` speaks CASTV2 over a local TLS listener and implements
+//! enough of the connection, heartbeat, receiver and media channels to drive a
+//! `Client` through launching an app and playing media. Playback advances in
+//! real time and can be fast-forwarded with `MockReceiver::advance`.
+//!
+//! Faults are scripted with `fail_next_load`, `close`, `disconnect`, `inject`
+//! and `take_over`. State outlives individual connections, so a `Client` that
+//! reconnects after a `disconnect` rejoins the running app session.
+use std::convert::{TryFrom, TryInto};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use byteorder::{BigEndian, ByteOrder};
+use bytes::{BufMut, BytesMut};
+use floating_duration::TimeAsFloat;
+use futures::prelude::*;
+use futures::sync::mpsc::{self, UnboundedSender};
+use protobuf::Message;
+use serde_json::{json, Value};
+use stream_util::{self, Cancelable};
+use tokio_codec::{Decoder, Encoder, Framed};
+use tokio_tcp::{TcpListener, TcpStream};
+use tokio_tls::{TlsAcceptor, TlsStream};
+
+use crate::channel::{
+    connection, heartbeat, media, receiver, MessageBuilder, DEFAULT_DESTINATION_ID,
+    DEFAULT_SENDER_ID,
+};
+use crate::codec;
+use crate::proto::CastMessage;
+use crate::supervisor::Shutdown;
+
+#[cfg(test)]
+pub mod fixture;
+
+/// Self-signed certificate and key presented by the mock receiver. The client
+/// does not verify device certificates.
+const IDENTITY: &[u8] = include_bytes!("identity.p12");
+const IDENTITY_PASSWORD: &str = "cast-client";
+/// Bitmask of `PAUSE`, `SEEK`, `STREAM_VOLUME` and `STREAM_MUTE`.
+const SUPPORTED_MEDIA_COMMANDS: u32 = 15;
+
+/// Handle to a running mock receiver. Clones share the same receiver.
+#[derive(Clone, Debug)]
+pub struct MockReceiver {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    connection: Arc<Mutex<Option<UnboundedSender<Outbound>>>>,
+    shutdown: Shutdown,
+}
+
+impl MockReceiver {
+    /// Bind a mock receiver to an ephemeral port on localhost. Returns the
+    /// receiver and a future that accepts connections until the receiver is
+    /// shut down. The future must be spawned on a tokio runtime.
+    pub fn bind() -> io::Result<(Self, impl Future<Item = (), Error = ()>)> {
+        let identity = native_tls::Identity::from_pkcs12(IDENTITY, IDENTITY_PASSWORD)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        let acceptor = native_tls::TlsAcceptor::new(identity)
+            .map(TlsAcceptor::from)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        let listener = TcpListener::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))?;
+        let addr = listener.local_addr()?;
+        let (trigger, valve) = stream_util::valve();
+        let receiver = Self {
+            addr,
+            state: Arc::new(Mutex::new(State::default())),
+            connection: Arc::new(Mutex::new(None)),
+            shutdown: Shutdown::new(trigger),
+        };
+        let server = receiver.clone();
+        let serve = listener
+            .incoming()
+            .cancel(valve.clone())
+            .map_err(|err| warn!("mock receiver accept error: {:?}", err))
+            .for_each(move |socket| {
+                let server = server.clone();
+                let valve = valve.clone();
+                let connection = acceptor
+                    .accept(socket)
+                    .map_err(|err| warn!("mock receiver TLS error: {:?}", err))
+                    .and_then(move |socket| server.serve(socket))
+                    .select2(valve)
+                    .then(|_| Ok(()));
+                tokio_executor::spawn(connection);
+                Ok(())
+            });
+        Ok((receiver, serve))
+    }
+
+    /// Address to pass to `cast_client::connect`.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Respond to the next `LOAD` with `LOAD_FAILED`.
+    pub fn fail_next_load(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.fail_next_load = true;
+        }
+    }
+
+    /// Send a `CLOSE` on the connection channel to the connected sender, as a
+    /// device does when the launched app is stopped by another sender.
+    pub fn close(&self) {
+        let source = self
+            .state
+            .lock()
+            .ok()
+            .and_then(|state| state.app.as_ref().map(|app| app.transport_id.clone()))
+            .unwrap_or_else(|| DEFAULT_DESTINATION_ID.to_owned());
+        let message = MessageBuilder::default()
+            .namespace(connection::NAMESPACE)
+            .source(&source)
+            .destination(DEFAULT_SENDER_ID)
+            .payload(&json!({ "type": "CLOSE" }))
+            .into_message();
+        self.send(Outbound::Message(message));
+    }
+
+    /// Launch `app_id` as another sender would, replacing any running app,
+    /// and broadcast the new receiver status to the connected sender.
+    pub fn take_over(&self, app_id: &str, display_name: &str) {
+        let status = self.state.lock().ok().map(|mut state| {
+            state.launch(app_id);
+            if let Some(ref mut app) = state.app {
+                app.display_name = display_name.to_owned();
+            }
+            state.receiver_status(0)
+        });
+        if let Some(status) = status {
+            let message = MessageBuilder::default()
+                .namespace(receiver::NAMESPACE)
+                .source(DEFAULT_DESTINATION_ID)
+                .destination("*")
+                .payload(&status)
+                .into_message();
+            self.send(Outbound::Message(message));
+        }
+    }
+
+    /// Send `payload` on `namespace` from the receiver to the connected
+    /// sender, e.g. a message type the client does not know.
+    pub fn inject(&self, namespace: &str, payload: &Value) {
+        let message = MessageBuilder::default()
+            .namespace(namespace)
+            .source(DEFAULT_DESTINATION_ID)
+            .destination(DEFAULT_SENDER_ID)
+            .payload(payload)
+            .into_message();
+        self.send(Outbound::Message(message));
+    }
+
+    /// Drop the TCP connection to the connected sender without a `CLOSE`.
+    pub fn disconnect(&self) {
+        self.send(Outbound::Disconnect);
+    }
+
+    /// Fast-forward the simulated playback position of the loaded media.
+    pub fn advance(&self, by: Duration) {
+        if let Ok(mut state) = self.state.lock() {
+            if let Some(ref mut playback) = state.playback {
+                playback.position += by.as_fractional_secs();
+            }
+        }
+    }
+
+    /// Simulated playback position of the loaded media.
+    pub fn current_time(&self) -> Option<f64> {
+        self.state
+            .lock()
+            .ok()
+            .and_then(|mut state| state.playback.as_mut().map(Playback::current_time))
+    }
+
+    /// Types of all messages received from senders, in order.
+    pub fn received(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .map(|state| state.received.clone())
+            .unwrap_or_default()
+    }
+
+    /// Stop accepting connections and disconnect the connected sender.
+    pub fn shutdown(&self) {
+        self.shutdown.terminate();
+    }
+
+    fn send(&self, message: Outbound) {
+        let connection = self.connection.lock().ok().and_then(|tx| tx.clone());
+        if let Some(connection) = connection {
+            let _ = connection.unbounded_send(message);
+        }
+    }
+
+    fn serve(&self, socket: TlsStream<TcpStream>) -> impl Future<Item = (), Error = ()> {
+        let (sink, source) = Framed::new(socket, Frames::default()).split();
+        let (tx, rx) = mpsc::unbounded();
+        if let Ok(mut connection) = self.connection.lock() {
+            *connection = Some(tx.clone());
+        }
+        let state = self.state.clone();
+        let inbound = source
+            .map_err(|err| warn!("mock receiver decode error: {:?}", err))
+            .for_each(move |message| {
+                let replies = state
+                    .lock()
+                    .map(|mut state| state.handle(&message))
+                    .unwrap_or_default();
+                for reply in replies {
+                    let _ = tx.unbounded_send(Outbound::Message(reply));
+                }
+                Ok(())
+            });
+        let outbound = rx
+            .take_while(|message| match *message {
+                Outbound::Message(_) => Ok(true),
+                Outbound::Disconnect => Ok(false),
+            })
+            .filter_map(|message| match message {
+                Outbound::Message(message) => Some(message),
+                Outbound::Disconnect => None,
+            })
+            .forward(sink.sink_map_err(|err| warn!("mock receiver encode error: {:?}", err)))
+            .map(|_| ());
+        // Dropping both halves of the transport closes the socket.
+        inbound.select(outbound).then(|_| {
+            debug!("mock receiver connection closed");
+            Ok(())
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Outbound {
+    Message(CastMessage),
+    Disconnect,
+}
+
+#[derive(Debug)]
+struct App {
+    app_id: String,
+    display_name: String,
+    session_id: String,
+    transport_id: String,
+}
+
+#[derive(Debug)]
+struct Playback {
+    media_session_id: i64,
+    media: Value,
+    player_state: &'static str,
+    idle_reason: Option<&'static str>,
+    /// Position at the time playback was last resumed or paused.
+    position: f64,
+    resumed: Option<Instant>,
+    duration: Option<f64>,
+}
+
+impl Playback {
+    fn current_time(&mut self) -> f64 {
+        let elapsed = self
+            .resumed
+            .map(|resumed| resumed.elapsed().as_fractional_secs())
+            .unwrap_or_default();
+        let current_time = self.position + elapsed;
+        match self.duration {
+            Some(duration) if current_time >= duration => {
+                self.position = duration;
+                self.resumed = None;
+                self.player_state = "IDLE";
+                self.idle_reason = Some("FINISHED");
+                duration
+            }
+            _ => current_time,
+        }
+    }
+
+    fn pause(&mut self) {
+        self.position = self.current_time();
+        self.resumed = None;
+    }
+}
+
+#[derive(Debug)]
+struct QueueItem {
+    item_id: i64,
+    media: Value,
+}
+
+#[derive(Debug)]
+struct State {
+    launches: u32,
+    app: Option<App>,
+    playback: Option<Playback>,
+    queue: Vec<QueueItem>,
+    current_item_id: Option<i64>,
+    next_item_id: i64,
+    volume: (f64, bool),
+    fail_next_load: bool,
+    received: Vec<String>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            launches: 0,
+            app: None,
+            playback: None,
+            queue: Vec::new(),
+            current_item_id: None,
+            next_item_id: 1,
+            volume: (1.0, false),
+            fail_next_load: false,
+            received: Vec::new(),
+        }
+    }
+}
+
+impl State {
+    fn handle(&mut self, message: &CastMessage) -> Vec<CastMessage> {
+        let payload = serde_json::from_str::<Value>(message.get_payload_utf8()).unwrap_or_default();
+        let kind = payload["type"].as_str().unwrap_or_default().to_owned();
+        let request_id = payload["requestId"].as_i64().unwrap_or_default();
+        trace!("mock receiver got {} on {}", kind, message.get_namespace());
+        self.received.push(kind.clone());
+        let namespace = message.get_namespace();
+        // Broadcast after the response to a command that changed the queue.
+        let mut queue_change = None;
+        let response = match (namespace, kind.as_str()) {
+            (heartbeat::NAMESPACE, "PING") => json!({ "type": "PONG" }),
+            (receiver::NAMESPACE, "LAUNCH") => {
+                let app_id = payload["appId"].as_str().unwrap_or_default();
+                // Like a device, keep the session of an app that is running.
+                if self.app.as_ref().map_or(true, |app| app.app_id != app_id) {
+                    self.launch(app_id);
+                }
+                self.receiver_status(request_id)
+            }
+            (receiver::NAMESPACE, "GET_STATUS") => self.receiver_status(request_id),
+            (receiver::NAMESPACE, "STOP") => {
+                self.app = None;
+                self.playback = None;
+                self.queue.clear();
+                self.current_item_id = None;
+                self.receiver_status(request_id)
+            }
+            (receiver::NAMESPACE, "GET_APP_AVAILABILITY") => {
+                let availability = payload["appId"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .map(|app_id| (app_id.to_owned(), json!("APP_AVAILABLE")))
+                    .collect::<serde_json::Map<_, _>>();
+                json!({
+                    "requestId": request_id,
+                    "responseType": "GET_APP_AVAILABILITY",
+                    "availability": availability,
+                })
+            }
+            (receiver::NAMESPACE, "SET_VOLUME") => {
+                let volume = &payload["volume"];
+                self.volume.0 = volume["level"].as_f64().unwrap_or(self.volume.0);
+                self.volume.1 = volume["muted"].as_bool().unwrap_or(self.volume.1);
+                self.receiver_status(request_id)
+            }
+            (media::NAMESPACE, "LOAD") | (media::NAMESPACE, "QUEUE_LOAD")
+                if self.fail_next_load =>
+            {
+                self.fail_next_load = false;
+                json!({ "type": "LOAD_FAILED", "requestId": request_id })
+            }
+            (media::NAMESPACE, "LOAD") | (media::NAMESPACE, "QUEUE_LOAD") if self.app.is_none() => {
+                json!({
+                    "type": "INVALID_REQUEST",
+                    "requestId": request_id,
+                    "reason": "INVALID_COMMAND",
+                })
+            }
+            (media::NAMESPACE, "LOAD") => {
+                self.queue.clear();
+                self.current_item_id = None;
+                self.load(&payload);
+                self.media_status(request_id)
+            }
+            (media::NAMESPACE, "QUEUE_LOAD") => {
+                self.queue = self.queue_items(&payload["items"]);
+                let start_index = payload["startIndex"]
+                    .as_u64()
+                    .and_then(|index| usize::try_from(index).ok())
+                    .unwrap_or_default();
+                let start = self.queue.get(start_index).map(|item| item.item_id);
+                self.current_item_id = start;
+                match start {
+                    Some(item_id) => {
+                        let media = self.item_media(item_id);
+                        self.load(&json!({ "media": media }));
+                        self.media_status(request_id)
+                    }
+                    None => json!({
+                        "type": "INVALID_REQUEST",
+                        "requestId": request_id,
+                        "reason": "INVALID_PARAMS",
+                    }),
+                }
+            }
+            (media::NAMESPACE, "GET_STATUS") => self.media_status(request_id),
+            (media::NAMESPACE, _) if self.playback.is_none() => {
+                json!({ "type": "INVALID_PLAYER_STATE", "requestId": request_id })
+            }
+            (media::NAMESPACE, "QUEUE_INSERT") => {
+                let items = self.queue_items(&payload["items"]);
+                let item_ids = items.iter().map(|item| item.item_id).collect::<Vec<_>>();
+                let insert_before = payload["insertBefore"].as_i64();
+                let index = insert_before
+                    .and_then(|item_id| self.queue.iter().position(|item| item.item_id == item_id))
+                    .unwrap_or_else(|| self.queue.len());
+                self.queue.splice(index..index, items);
+                queue_change = Some(json!({
+                    "type": "QUEUE_CHANGE",
+                    "changeType": "INSERT",
+                    "itemIds": item_ids,
+                    "insertBefore": insert_before,
+                }));
+                self.media_status(request_id)
+            }
+            (media::NAMESPACE, "QUEUE_REMOVE") => {
+                let item_ids = payload["itemIds"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_i64)
+                    .collect::<Vec<_>>();
+                self.queue.retain(|item| !item_ids.contains(&item.item_id));
+                queue_change = Some(json!({
+                    "type": "QUEUE_CHANGE",
+                    "changeType": "REMOVE",
+                    "itemIds": item_ids,
+                }));
+                self.media_status(request_id)
+            }
+            (media::NAMESPACE, "QUEUE_UPDATE")
+            | (media::NAMESPACE, "QUEUE_NEXT")
+            | (media::NAMESPACE, "QUEUE_PREV") => {
+                let jump = match kind.as_str() {
+                    "QUEUE_NEXT" => Some(1),
+                    "QUEUE_PREV" => Some(-1),
+                    _ => payload["jump"].as_i64(),
+                };
+                let target = match jump {
+                    Some(jump) => self.jump(jump),
+                    None => payload["currentItemId"].as_i64(),
+                };
+                match target {
+                    // Neither a jump nor an item, e.g. only a repeat mode.
+                    None if jump.is_none() => self.media_status(request_id),
+                    Some(item_id) if self.queue.iter().any(|item| item.item_id == item_id) => {
+                        let media = self.item_media(item_id);
+                        if let Some(ref mut playback) = self.playback {
+                            playback.duration = media["duration"].as_f64();
+                            playback.media = media;
+                            playback.player_state = "PLAYING";
+                            playback.idle_reason = None;
+                            playback.position = 0.0;
+                            playback.resumed = Some(Instant::now());
+                        }
+                        self.current_item_id = Some(item_id);
+                        self.media_status(request_id)
+                    }
+                    _ => json!({
+                        "type": "ERROR",
+                        "requestId": request_id,
+                        "detailedErrorCode": 999,
+                        "reason": "INVALID_PARAMS",
+                    }),
+                }
+            }
+            (media::NAMESPACE, kind) => {
+                if let Some(ref mut playback) = self.playback {
+                    match kind {
+                        "PLAY" => {
+                            playback.resumed = Some(Instant::now());
+                            playback.player_state = "PLAYING";
+                        }
+                        "PAUSE" => {
+                            playback.pause();
+                            playback.player_state = "PAUSED";
+                        }
+                        "STOP" => {
+                            playback.pause();
+                            playback.player_state = "IDLE";
+                            playback.idle_reason = Some("CANCELLED");
+                        }
+                        "SEEK" => {
+                            let playing = playback.resumed.is_some();
+                            playback.position =
+                                payload["currentTime"].as_f64().unwrap_or(playback.position);
+                            playback.resumed = if playing { Some(Instant::now()) } else { None };
+                        }
+                        _ => {}
+                    }
+                }
+                self.media_status(request_id)
+            }
+            _ => return vec![],
+        };
+        let reply = |payload: &Value| {
+            MessageBuilder::default()
+                .namespace(namespace)
+                .source(message.get_destination_id())
+                .destination(message.get_source_id())
+                .payload(payload)
+                .into_message()
+        };
+        let mut replies = vec![reply(&response)];
+        replies.extend(queue_change.as_ref().map(reply));
+        replies
+    }
+
+    /// Assign item ids to the queue items of a `QUEUE_LOAD` or `QUEUE_INSERT`.
+    fn queue_items(&mut self, items: &Value) -> Vec<QueueItem> {
+        let items = items.as_array().map(Vec::as_slice).unwrap_or_default();
+        items
+            .iter()
+            .map(|item| {
+                let item_id = self.next_item_id;
+                self.next_item_id += 1;
+                QueueItem {
+                    item_id,
+                    media: item["media"].clone(),
+                }
+            })
+            .collect()
+    }
+
+    fn item_media(&self, item_id: i64) -> Value {
+        self.queue
+            .iter()
+            .find(|item| item.item_id == item_id)
+            .map(|item| item.media.clone())
+            .unwrap_or_default()
+    }
+
+    /// Id of the item `jump` places from the current item.
+    fn jump(&self, jump: i64) -> Option<i64> {
+        let current = self.current_item_id?;
+        let index = self.queue.iter().position(|item| item.item_id == current)?;
+        let index = i64::try_from(index).ok()? + jump;
+        let index = usize::try_from(index).ok()?;
+        self.queue.get(index).map(|item| item.item_id)
+    }
+
+    fn launch(&mut self, app_id: &str) {
+        self.launches += 1;
+        self.playback = None;
+        self.app = Some(App {
+            app_id: app_id.to_owned(),
+            display_name: "Mock Receiver".to_owned(),
+            session_id: format!("mock-session-{}", self.launches),
+            transport_id: format!("web-{}", self.launches),
+        });
+    }
+
+    fn load(&mut self, payload: &Value) {
+        let media_session_id = self
+            .playback
+            .as_ref()
+            .map_or(1, |playback| playback.media_session_id + 1);
+        let media = payload["media"].clone();
+        self.playback = Some(Playback {
+            media_session_id,
+            duration: media["duration"].as_f64(),
+            media,
+            player_state: "PLAYING",
+            idle_reason: None,
+            position: payload["currentTime"].as_f64().unwrap_or_default(),
+            resumed: Some(Instant::now()),
+        });
+    }
+
+    fn receiver_status(&self, request_id: i64) -> Value {
+        let applications = self
+            .app
+            .iter()
+            .map(|app| {
+                json!({
+                    "appId": app.app_id,
+                    "displayName": app.display_name,
+                    "namespaces": [{ "name": media::NAMESPACE }],
+                    "sessionId": app.session_id,
+                    "statusText": "",
+                    "transportId": app.transport_id,
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "type": "RECEIVER_STATUS",
+            "requestId": request_id,
+            "status": {
+                "applications": applications,
+                "isActiveInput": true,
+                "volume": { "level": self.volume.0, "muted": self.volume.1 },
+            },
+        })
+    }
+
+    fn media_status(&mut self, request_id: i64) -> Value {
+        let current_item_id = self.current_item_id;
+        let items = self
+            .queue
+            .iter()
+            .map(|item| json!({ "itemId": item.item_id }))
+            .collect::<Vec<_>>();
+        let status = self
+            .playback
+            .iter_mut()
+            .map(|playback| {
+                let current_time = playback.current_time();
+                json!({
+                    "currentItemId": current_item_id,
+                    "items": items,
+                    "mediaSessionId": playback.media_session_id,
+                    "media": playback.media,
+                    "playbackRate": 1,
+                    "playerState": playback.player_state,
+                    "idleReason": playback.idle_reason,
+                    "currentTime": current_time,
+                    "supportedMediaCommands": SUPPORTED_MEDIA_COMMANDS,
+                    "volume": { "level": 1.0, "muted": false },
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "type": "MEDIA_STATUS",
+            "requestId": request_id,
+            "status": status,
+        })
+    }
+}
+
+/// Server side of the CASTV2 framing. Decodes with the client codec and
+/// encodes raw `CastMessage` protobufs.
+#[derive(Debug, Default)]
+struct Frames(codec::CastMessage);
+
+impl Decoder for Frames {
+    type Item = CastMessage;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.0.decode(src)
+    }
+}
+
+impl Encoder for Frames {
+    type Item = CastMessage;
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let buf = item
+            .write_to_bytes()
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        let mut header = [0; 4];
+        let length = buf
+            .len()
+            .try_into()
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        BigEndian::write_u32(&mut header, length);
+        dst.reserve(header.len() + buf.len());
+        dst.put_slice(&header);
+        dst.put_slice(&buf);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use futures::future;
+    use futures::prelude::*;
+
+    use serde_json::json;
+
+    use super::fixture;
+    use crate::channel::media::PlayerState;
+    use crate::channel::receiver;
+    use crate::{ClientBuilder, CommandError, Error, MediaConnection, Response, Status};
+
+    #[test]
+    fn plays_media_and_injects_load_failure() {
+        let (mut runtime, receiver) = fixture::serve();
+        let (client, _, connect) =
+            fixture::launch(&mut runtime, &receiver, ClientBuilder::default());
+        let media = fixture::media(1);
+
+        let load = {
+            let (connect, media) = (connect.clone(), media.clone());
+            future::lazy(move || {
+                let response = client.load(&connect, media);
+                response.map(move |response| (client, response))
+            })
+        };
+        let (mut client, response) = runtime.block_on(load).unwrap();
+        match response {
+            Response::MediaStatus(ref status) => {
+                assert_eq!(status[0].player_state, PlayerState::Playing)
+            }
+            response => panic!("unexpected response: {:?}", response),
+        }
+        receiver.advance(Duration::from_secs(30));
+        assert!(receiver.current_time().unwrap() >= 30.0);
+
+        receiver.fail_next_load();
+        let load = future::lazy(|| client.load(&connect, media)).then(Ok::<_, ()>);
+        assert_eq!(
+            runtime.block_on(load).unwrap().unwrap_err(),
+            CommandError::LoadFailed
+        );
+        runtime.block_on(client.shutdown()).unwrap();
+        // The socket is closed once all frames are flushed, but the mock may
+        // not have handled them yet.
+        for _ in 0..100 {
+            if receiver
+                .received()
+                .iter()
+                .filter(|kind| *kind == "CLOSE")
+                .count()
+                == 2
+            {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let received = receiver.received();
+        assert!(received.iter().any(|kind| kind == "LAUNCH"));
+        // Media STOP, then receiver STOP, then CLOSE for the app transport and
+        // the receiver.
+        let tail = received
+            .iter()
+            .filter(|kind| *kind == "STOP" || *kind == "CLOSE")
+            .collect::<Vec<_>>();
+        assert_eq!(tail, vec!["STOP", "STOP", "CLOSE", "CLOSE"]);
+        receiver.shutdown();
+    }
+
+    #[test]
+    fn reports_session_taken_over_by_other_sender() {
+        let (mut runtime, receiver) = fixture::serve();
+        let (mut client, status, _) =
+            fixture::launch(&mut runtime, &receiver, ClientBuilder::default());
+
+        receiver.take_over("233637DE", "YouTube");
+        let lost = status.filter_map(|status| match status {
+            Status::SessionLost {
+                by_app,
+                display_name,
+            } => Some((by_app, display_name)),
+            _ => None,
+        });
+        match runtime.block_on(lost.into_future()) {
+            Ok((Some((by_app, display_name)), _)) => {
+                assert_eq!(by_app.as_ref().map(String::as_str), Some("233637DE"));
+                assert_eq!(display_name.as_ref().map(String::as_str), Some("YouTube"));
+            }
+            _ => panic!("client did not report the lost session"),
+        }
+        // The client no longer owns the app, so shutting down must not stop
+        // it.
+        runtime.block_on(client.shutdown()).unwrap();
+        assert!(!receiver.received().iter().any(|kind| kind == "STOP"));
+        receiver.shutdown();
+    }
+
+    #[test]
+    fn joined_app_is_not_stopped_on_shutdown() {
+        let (mut runtime, receiver) = fixture::serve();
+        receiver.take_over("CC1AD845", "Default Media Receiver");
+        let (mut client, status, init) = crate::connect(receiver.addr());
+        runtime.block_on(init).unwrap();
+        runtime.block_on(client.join("CC1AD845")).unwrap();
+        let _ = fixture::connected(&mut runtime, status);
+        runtime.block_on(client.shutdown()).unwrap();
+        assert!(!receiver.received().iter().any(|kind| kind == "STOP"));
+        receiver.shutdown();
+    }
+
+    #[test]
+    fn rejoins_session_after_reconnect() {
+        let (mut runtime, receiver) = fixture::serve();
+        let builder = ClientBuilder::default()
+            .reconnect_backoff(Duration::from_millis(10), Duration::from_millis(100));
+        let (mut client, status, connect) = fixture::launch(&mut runtime, &receiver, builder);
+        let session = connect.session;
+
+        // The app survives the dropped connection, so the client rejoins its
+        // session once it reconnects.
+        receiver.disconnect();
+        let rejoined = status
+            .skip_while(|status| match *status {
+                Status::Reconnected => Ok(false),
+                _ => Ok(true),
+            })
+            .filter_map(|status| match status {
+                Status::Connected(connect) => Some(connect.session),
+                _ => None,
+            });
+        match runtime.block_on(rejoined.into_future()) {
+            Ok((Some(rejoined), _)) => assert_eq!(rejoined, session),
+            _ => panic!("client did not rejoin the app session"),
+        }
+        runtime.block_on(client.shutdown()).unwrap();
+        receiver.shutdown();
+    }
+
+    #[test]
+    fn reports_session_taken_over_while_reconnecting() {
+        let (mut runtime, receiver) = fixture::serve();
+        let builder = ClientBuilder::default()
+            .reconnect_backoff(Duration::from_millis(100), Duration::from_millis(500));
+        let (mut client, status, _) = fixture::launch(&mut runtime, &receiver, builder);
+
+        // Another sender launches its app while the connection is down. The
+        // client reports it instead of relaunching its own app.
+        receiver.disconnect();
+        receiver.take_over("233637DE", "YouTube");
+        let lost = status.filter_map(|status| match status {
+            Status::SessionLost { by_app, .. } => Some(by_app),
+            _ => None,
+        });
+        match runtime.block_on(lost.into_future()) {
+            Ok((Some(by_app), _)) => {
+                assert_eq!(by_app.as_ref().map(String::as_str), Some("233637DE"))
+            }
+            _ => panic!("client did not report the lost session"),
+        }
+        runtime.block_on(client.shutdown()).unwrap();
+        let received = receiver.received();
+        assert_eq!(received.iter().filter(|kind| *kind == "LAUNCH").count(), 1);
+        assert!(!received.iter().any(|kind| kind == "STOP"));
+        receiver.shutdown();
+    }
+
+    #[test]
+    fn drops_malformed_message_and_stays_connected() {
+        let (mut runtime, receiver) = fixture::serve();
+        let (mut client, status, init) = crate::connect(receiver.addr());
+        runtime.block_on(init).unwrap();
+        // A receiver status without a status cannot be parsed.
+        receiver.inject(receiver::NAMESPACE, &json!({ "type": "RECEIVER_STATUS" }));
+        receiver.inject(
+            "urn:x-cast:com.example.unknown",
+            &json!({ "type": "HELLO" }),
+        );
+        // The device answers the launch on the same socket, after the
+        // injected frames.
+        runtime.block_on(client.launch_app()).unwrap();
+        assert_eq!(client.dropped_frames().inbound, 2);
+        let errors = status
+            .filter_map(|status| match status {
+                Status::Error(err) => Some(err),
+                _ => None,
+            })
+            .take(2)
+            .collect();
+        let errors = runtime.block_on(errors).unwrap();
+        for err in errors {
+            match *err {
+                Error::Protocol(_) => (),
+                ref err => panic!("unexpected error: {:?}", err),
+            }
+        }
+        runtime.block_on(client.shutdown()).unwrap();
+        receiver.shutdown();
+    }
+
+    #[test]
+    fn plays_through_queue() {
+        let (mut runtime, receiver) = fixture::serve();
+        let (mut client, _, connect) =
+            fixture::launch(&mut runtime, &receiver, ClientBuilder::default());
+        let status = |response: Response| match response {
+            Response::MediaStatus(mut status) => status.remove(0),
+            response => panic!("unexpected response: {:?}", response),
+        };
+
+        let load = future::lazy(|| {
+            client.queue_load(&connect, vec![fixture::media(1), fixture::media(2)], 0)
+        });
+        let loaded = status(runtime.block_on(load).unwrap());
+        let items = loaded
+            .items
+            .iter()
+            .filter_map(|item| item.item_id)
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![1, 2]);
+        assert_eq!(loaded.current_item_id, Some(1));
+        let media = MediaConnection {
+            receiver: connect,
+            session: loaded.media_session_id,
+        };
+
+        let insert = future::lazy(|| client.queue_insert(&media, vec![fixture::media(3)], Some(2)));
+        let inserted = status(runtime.block_on(insert).unwrap());
+        let items = inserted
+            .items
+            .iter()
+            .filter_map(|item| item.item_id)
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![1, 3, 2]);
+
+        let next = future::lazy(|| client.queue_next(&media));
+        let next = status(runtime.block_on(next).unwrap());
+        assert_eq!(next.current_item_id, Some(3));
+        assert_eq!(next.player_state, PlayerState::Playing);
+
+        let unknown = future::lazy(|| client.queue_update(&media, 9)).then(Ok::<_, ()>);
+        match runtime.block_on(unknown).unwrap() {
+            Err(CommandError::InvalidRequest(Some(ref reason))) => {
+                assert_eq!(reason, "INVALID_PARAMS")
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(receiver.received().iter().any(|kind| kind == "QUEUE_LOAD"));
+        runtime.block_on(client.shutdown()).unwrap();
+        receiver.shutdown();
+    }
+}
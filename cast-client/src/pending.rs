@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::sync::oneshot;
+use futures::Future;
+use tokio_timer::Timeout;
+
+use crate::metrics::Tracker;
+use crate::{Command, CommandError, Response};
+
+pub type Reply = oneshot::Sender<Result<Response, CommandError>>;
+
+/// Command waiting on a response from the device.
+#[derive(Debug)]
+struct InFlight {
+    reply: Reply,
+    kind: &'static str,
+    sent: Instant,
+}
+
+/// Table of in-flight commands keyed by request id.
+///
+/// Request ids are assigned by the codec when a command is encoded, so the
+/// codec registers a reply handle in the table and the channel handlers
+/// resolve it when a response echoing the same request id is received. The
+/// time to resolve each command is recorded in `metrics`.
+#[derive(Clone, Debug, Default)]
+pub struct Pending {
+    requests: Arc<Mutex<HashMap<i64, InFlight>>>,
+    metrics: Tracker,
+}
+
+impl Pending {
+    pub fn new(metrics: Tracker) -> Self {
+        Self {
+            requests: Arc::default(),
+            metrics,
+        }
+    }
+
+    /// Register a reply handle for the command of type `kind` with the given
+    /// request id.
+    pub fn register(&self, request_id: i64, kind: &'static str, reply: Reply) {
+        if let Ok(mut pending) = self.requests.lock() {
+            // Prune commands whose response futures have been dropped or have
+            // timed out.
+            pending.retain(|_, request| !request.reply.is_canceled());
+            let request = InFlight {
+                reply,
+                kind,
+                sent: Instant::now(),
+            };
+            pending.insert(request_id, request);
+        }
+    }
+
+    /// Fail all in-flight commands with `CommandError::Closed`.
+    pub fn close(&self) {
+        let pending = self
+            .requests
+            .lock()
+            .map(|mut pending| pending.drain().collect::<Vec<_>>())
+            .unwrap_or_default();
+        for (request_id, request) in pending {
+            trace!("closed pending request {}", request_id);
+            let _ = request.reply.send(Err(CommandError::Closed));
+        }
+    }
+
+    /// Resolve the command with the given request id. Returns `true` if a
+    /// command was waiting on the response.
+    ///
+    /// A `0` request id is reserved for spontaneous messages from the receiver
+    /// and never resolves a command.
+    pub fn resolve(&self, request_id: i64, result: Result<Response, CommandError>) -> bool {
+        if request_id == 0 {
+            return false;
+        }
+        let request = self
+            .requests
+            .lock()
+            .ok()
+            .and_then(|mut pending| pending.remove(&request_id));
+        if let Some(request) = request {
+            trace!("resolved pending request {}", request_id);
+            self.metrics.acknowledged(request.kind, request.sent);
+            let _ = request.reply.send(result);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Wrap `command` so the codec tracks its request id. Returns the wrapped
+/// command and a future that resolves with the matching response, or errors
+/// if no response is received within `timeout`.
+pub fn request(
+    command: Command,
+    timeout: Duration,
+) -> (Command, impl Future<Item = Response, Error = CommandError>) {
+    let (reply, response) = oneshot::channel();
+    let command = Command::Request {
+        command: Box::new(command),
+        reply,
+    };
+    let response = Timeout::new(response, timeout).then(|result| match result {
+        Ok(Ok(response)) => Ok(response),
+        Ok(Err(err)) => Err(err),
+        Err(ref err) if err.is_elapsed() => Err(CommandError::Timeout),
+        Err(ref err) if err.is_timer() => {
+            warn!("request timer failed: {:?}", err);
+            Err(CommandError::Timer)
+        }
+        Err(_) => Err(CommandError::Closed),
+    });
+    (command, response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::sync::oneshot;
+    use futures::Future;
+
+    use super::Pending;
+    use crate::metrics::Tracker;
+    use crate::{Command, CommandError, Response};
+
+    #[test]
+    fn resolve_completes_registered_request() {
+        let metrics = Tracker::default();
+        let pending = Pending::new(metrics.clone());
+        let (reply, response) = oneshot::channel();
+        pending.register(7, "GET_STATUS", reply);
+        assert!(pending.resolve(7, Ok(Response::MediaStatus(vec![]))));
+        assert!(!pending.resolve(7, Ok(Response::MediaStatus(vec![]))));
+        match response.wait() {
+            Ok(Ok(Response::MediaStatus(ref status))) if status.is_empty() => {}
+            result => panic!("unexpected response: {:?}", result),
+        }
+        let latency = metrics.snapshot().command_latency["GET_STATUS"];
+        assert_eq!(latency.samples, 1);
+    }
+
+    #[test]
+    fn resolve_ignores_spontaneous_messages() {
+        let pending = Pending::default();
+        let (reply, response) = oneshot::channel();
+        pending.register(0, "LOAD", reply);
+        assert!(!pending.resolve(0, Err(CommandError::LoadFailed)));
+        drop(pending);
+        assert!(response.wait().is_err());
+    }
+
+    #[test]
+    fn reports_timer_failure() {
+        // There is no timer outside of a runtime, so the timeout cannot be
+        // registered.
+        let (_command, response) = super::request(Command::Ping, Duration::from_secs(1));
+        assert_eq!(response.wait().unwrap_err(), CommandError::Timer);
+    }
+}
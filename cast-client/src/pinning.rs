@@ -0,0 +1,170 @@
+//! Trust-on-first-use pinning of device TLS certificates.
+//!
+//! Cast devices present self-signed certificates, so the TLS connection does
+//! not authenticate the device. Pinning records the SHA-256 fingerprint of the
+//! certificate a device presents the first time the client connects to it and
+//! checks later connections against it.
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use ring::digest;
+
+use crate::Error;
+
+/// Fingerprints of device certificates keyed by device id.
+///
+/// A store opened from a file persists fingerprints as they are recorded,
+/// one `<device id> <fingerprint>` pair per line.
+#[derive(Clone, Debug, Default)]
+pub struct PinStore {
+    path: Option<PathBuf>,
+    pins: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl PinStore {
+    /// Open the store persisted at `path`. The file is created when the first
+    /// fingerprint is recorded.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let pins = match fs::read_to_string(&path) {
+            Ok(contents) => parse(&contents),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            path: Some(path),
+            pins: Arc::new(Mutex::new(pins)),
+        })
+    }
+
+    /// Fingerprint pinned for `device_id`.
+    pub fn get(&self, device_id: &str) -> Option<String> {
+        self.pins
+            .lock()
+            .ok()
+            .and_then(|pins| pins.get(device_id).cloned())
+    }
+
+    /// Pin `fingerprint` for `device_id` and persist the store.
+    pub fn insert(&self, device_id: &str, fingerprint: &str) -> io::Result<()> {
+        let contents = match self.pins.lock() {
+            Ok(mut pins) => {
+                pins.insert(device_id.to_owned(), fingerprint.to_owned());
+                let mut pins = pins.iter().collect::<Vec<_>>();
+                pins.sort();
+                pins.into_iter()
+                    .map(|(device_id, fingerprint)| format!("{} {}\n", device_id, fingerprint))
+                    .collect::<String>()
+            }
+            Err(_) => return Err(io::Error::new(io::ErrorKind::Other, "poisoned pin store")),
+        };
+        match self.path {
+            Some(ref path) => fs::write(path, contents),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Configuration for pinning the certificate of a device.
+#[derive(Clone, Debug)]
+pub struct Pinning {
+    pub store: PinStore,
+    /// Stable id of the device, e.g. the `id` field of its mDNS TXT record.
+    pub device_id: String,
+    /// Fail the connection if the certificate does not match the pin.
+    pub strict: bool,
+}
+
+impl Pinning {
+    /// Check the DER-encoded certificate presented by the device against the
+    /// pinned fingerprint, pinning it if the device has not been seen before.
+    pub fn verify(&self, peer_cert: Option<&[u8]>) -> Result<(), Error> {
+        let actual = peer_cert.map(fingerprint);
+        let expected = match self.store.get(&self.device_id) {
+            Some(expected) => expected,
+            None => {
+                let actual = match actual {
+                    Some(actual) => actual,
+                    // There is nothing to pin.
+                    None => return Ok(()),
+                };
+                info!("pinning certificate {} for {}", actual, self.device_id);
+                if let Err(err) = self.store.insert(&self.device_id, &actual) {
+                    warn!("failed to persist certificate pin: {:?}", err);
+                }
+                return Ok(());
+            }
+        };
+        if actual.as_ref() == Some(&expected) {
+            return Ok(());
+        }
+        Err(Error::CertificateMismatch {
+            device_id: self.device_id.clone(),
+            expected,
+            actual,
+        })
+    }
+}
+
+/// Hex-encoded SHA-256 digest of a DER-encoded certificate.
+pub fn fingerprint(cert: &[u8]) -> String {
+    let digest = digest::digest(&digest::SHA256, cert);
+    let mut fingerprint = String::with_capacity(2 * digest.as_ref().len());
+    for byte in digest.as_ref() {
+        let _ = write!(fingerprint, "{:02x}", byte);
+    }
+    fingerprint
+}
+
+fn parse(contents: &str) -> HashMap<String, String> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next()) {
+                (Some(device_id), Some(fingerprint)) => {
+                    Some((device_id.to_owned(), fingerprint.to_owned()))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fingerprint, parse, PinStore, Pinning};
+    use crate::Error;
+
+    #[test]
+    fn pins_on_first_use_and_rejects_changed_certificate() {
+        let pinning = Pinning {
+            store: PinStore::default(),
+            device_id: "kitchen-speaker".to_owned(),
+            strict: true,
+        };
+        assert!(pinning.verify(Some(&b"first"[..])).is_ok());
+        assert_eq!(
+            pinning.store.get("kitchen-speaker"),
+            Some(fingerprint(b"first"))
+        );
+        assert!(pinning.verify(Some(&b"first"[..])).is_ok());
+        match pinning.verify(Some(&b"rogue"[..])) {
+            Err(Error::CertificateMismatch { actual, .. }) => {
+                assert_eq!(actual, Some(fingerprint(b"rogue")))
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn parses_store() {
+        let pins = parse("kitchen-speaker abc123\n\nmalformed\nliving-room def456\n");
+        assert_eq!(pins.len(), 2);
+        assert_eq!(pins["living-room"], "def456");
+    }
+}
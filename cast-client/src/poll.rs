@@ -0,0 +1,160 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use floating_duration::TimeAsFloat;
+
+use crate::channel::media::{MediaStatus, PlayerState};
+
+/// Cadence for polling the device for status.
+///
+/// The device broadcasts a spontaneous status whenever the player state
+/// changes, so polling only refreshes the playback position. Polls are
+/// frequent while playback approaches a boundary, i.e. the end of the media
+/// or the deadline set with `Client::set_deadline`, and sparse otherwise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Polling {
+    /// Interval between polls while media is playing.
+    pub playing: Duration,
+    /// Interval between polls while media is paused or idle, or no media
+    /// session is established.
+    pub paused: Duration,
+    /// Interval between polls while playback is within `window` of a
+    /// boundary.
+    pub near_boundary: Duration,
+    /// Distance from a boundary, in playback time, at which polls switch to
+    /// `near_boundary`.
+    pub window: Duration,
+}
+
+impl Default for Polling {
+    fn default() -> Self {
+        Self {
+            playing: Duration::from_secs(1),
+            paused: Duration::from_secs(5),
+            near_boundary: Duration::from_millis(150),
+            window: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Estimate {
+    player_state: PlayerState,
+    current_time: f64,
+    duration: Option<f64>,
+    playback_rate: f64,
+    received: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    estimate: Option<Estimate>,
+    deadline: Option<f64>,
+}
+
+/// Last known playback state of the media session, used to schedule polls.
+#[derive(Clone, Debug, Default)]
+pub struct Playback(Arc<Mutex<Inner>>);
+
+impl Playback {
+    /// Record a media status received from the device.
+    pub fn update(&self, status: Option<&MediaStatus>) {
+        if let Ok(mut inner) = self.0.lock() {
+            inner.estimate = status.map(|status| Estimate {
+                player_state: status.player_state.clone(),
+                current_time: status.current_time,
+                duration: status.media.as_ref().and_then(|media| media.duration),
+                playback_rate: status.playback_rate,
+                received: Instant::now(),
+            });
+        }
+    }
+
+    /// Set the playback position, in seconds, at which the caller needs an
+    /// up-to-date status.
+    pub fn set_deadline(&self, deadline: Option<f64>) {
+        if let Ok(mut inner) = self.0.lock() {
+            inner.deadline = deadline;
+        }
+    }
+
+    /// Delay until the next poll.
+    pub fn next_poll(&self, polling: &Polling) -> Duration {
+        let inner = match self.0.lock() {
+            Ok(inner) => inner,
+            Err(_) => return polling.paused,
+        };
+        let estimate = match inner.estimate {
+            Some(ref estimate) => estimate,
+            None => return polling.paused,
+        };
+        match estimate.player_state {
+            PlayerState::Playing | PlayerState::Buffering => {}
+            PlayerState::Idle | PlayerState::Paused => return polling.paused,
+        }
+        let rate = if estimate.playback_rate > 0.0 {
+            estimate.playback_rate
+        } else {
+            1.0
+        };
+        let position =
+            estimate.current_time + estimate.received.elapsed().as_fractional_secs() * rate;
+        // Switch to the fast cadence one `playing` interval early so the
+        // boundary is not overshot by a slow poll.
+        let window = polling.window.as_fractional_secs();
+        let lead = window + polling.playing.as_fractional_secs();
+        let near = inner
+            .deadline
+            .iter()
+            .chain(estimate.duration.iter())
+            .map(|boundary| (boundary - position) / rate)
+            .any(|remaining| remaining >= -window && remaining <= lead);
+        if near {
+            polling.near_boundary
+        } else {
+            polling.playing
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Playback, Polling};
+    use crate::channel::media::MediaStatus;
+
+    fn status(player_state: &str, current_time: f64) -> MediaStatus {
+        let payload = format!(
+            r#"{{
+                "mediaSessionId": 1,
+                "media": {{ "contentId": "http://127.0.0.1/round.mp3", "streamType": "BUFFERED", "contentType": "audio/mpeg", "duration": 60.0 }},
+                "playbackRate": 1,
+                "playerState": "{}",
+                "currentTime": {},
+                "supportedMediaCommands": 15
+            }}"#,
+            player_state, current_time
+        );
+        serde_json::from_str(&payload).unwrap()
+    }
+
+    #[test]
+    fn polls_faster_near_boundary() {
+        let polling = Polling::default();
+        let playback = Playback::default();
+        assert_eq!(playback.next_poll(&polling), polling.paused);
+
+        playback.update(Some(&status("PAUSED", 10.0)));
+        assert_eq!(playback.next_poll(&polling), polling.paused);
+
+        playback.update(Some(&status("PLAYING", 10.0)));
+        assert_eq!(playback.next_poll(&polling), polling.playing);
+
+        playback.set_deadline(Some(12.5));
+        assert_eq!(playback.next_poll(&polling), polling.near_boundary);
+        playback.set_deadline(Some(30.0));
+        assert_eq!(playback.next_poll(&polling), polling.playing);
+
+        playback.update(Some(&status("PLAYING", 59.0)));
+        assert_eq!(playback.next_poll(&polling), polling.near_boundary);
+    }
+}
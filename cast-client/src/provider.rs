@@ -1,9 +1,15 @@
+use std::collections::HashMap;
 use std::error;
 use std::fmt;
+use std::io;
+use std::time::Duration;
 
+use bytes::Bytes;
+use futures::sync::oneshot;
 use url::Url;
 
 use crate::channel::media::MediaStatus;
+use crate::channel::{multizone, receiver};
 
 #[derive(Clone, Debug)]
 pub struct Media {
@@ -38,26 +44,315 @@ pub struct Image {
     pub dimensions: (u32, u32),
 }
 
+/// Error establishing or maintaining the connection to a device.
 #[derive(Debug)]
 pub enum Error {
-    UnknownChannel(String),
+    /// The TCP connection and TLS handshake did not complete before the
+    /// connect timeout elapsed.
+    ConnectTimeout(Duration),
+    /// The timer backing the connect timeout failed, e.g. the runtime is
+    /// shutting down or the timer is at capacity.
+    Timer,
+    /// The heartbeat configured with `ClientBuilder::heartbeat` has a zero
+    /// interval or allows no missed intervals.
+    InvalidHeartbeat,
+    /// The TCP connection could not be established, e.g. it was refused.
+    Connect(io::Error),
+    /// The TLS handshake with the device failed.
+    Tls(native_tls::Error),
+    /// The device failed the authentication handshake in strict mode.
+    DeviceAuth(DeviceAuthError),
+    /// The device presented a different certificate than the one pinned for
+    /// its device id. `actual` is `None` if it presented no certificate.
+    /// Fingerprints are hex-encoded SHA-256 digests.
+    CertificateMismatch {
+        device_id: String,
+        expected: String,
+        actual: Option<String>,
+    },
+    /// I/O error on an established connection. The client reconnects.
+    Io(io::Error),
+    /// A command could not be encoded into a frame. Frames received from the
+    /// device that the codec cannot decode are skipped without a
+    /// `Status::Error`, since the connection stays usable; they are counted
+    /// by `Client::dropped_frames`.
+    Codec(CodecError),
+    /// The device sent a message that violates the protocol, e.g. a payload
+    /// that cannot be parsed or a message on a namespace no channel handles.
+    /// The message is dropped, counted by `Client::dropped_frames` and
+    /// reported as `Status::Error`.
+    Protocol(String),
+    /// The receiver app could not be launched.
+    LaunchFailed(Option<String>),
+    /// A command failed.
+    Command(CommandError),
+    /// The connection to the device was closed or the client shut down.
+    Closed,
 }
 
-impl error::Error for Error {}
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Connect(ref err) | Error::Io(ref err) => Some(err),
+            Error::Tls(ref err) => Some(err),
+            Error::DeviceAuth(ref err) => Some(err),
+            Error::Codec(ref err) => Some(err),
+            Error::Command(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Error::UnknownChannel(ref channel) => {
-                write!(f, "Message received on unknown channel {:?}", channel)
+            Error::ConnectTimeout(timeout) => {
+                write!(f, "Timed out connecting to device after {:?}", timeout)
+            }
+            Error::Timer => write!(f, "Connect timer failed"),
+            Error::InvalidHeartbeat => {
+                write!(f, "Heartbeat interval and max missed must be non-zero")
             }
+            Error::Connect(ref err) => write!(f, "Connection failed: {}", err),
+            Error::Tls(ref err) => write!(f, "TLS handshake failed: {}", err),
+            Error::DeviceAuth(ref err) => write!(f, "{}", err),
+            Error::CertificateMismatch {
+                ref device_id,
+                ref expected,
+                ref actual,
+            } => write!(
+                f,
+                "Certificate for {} does not match pin {}: {:?}",
+                device_id, expected, actual
+            ),
+            Error::Io(ref err) => write!(f, "I/O error: {}", err),
+            Error::Codec(ref err) => write!(f, "{}", err),
+            Error::Protocol(ref err) => write!(f, "Protocol error: {}", err),
+            Error::LaunchFailed(ref reason) => write!(f, "Launch failed: {:?}", reason),
+            Error::Command(ref err) => write!(f, "{}", err),
+            Error::Closed => write!(f, "Connection closed"),
         }
     }
 }
 
+impl From<DeviceAuthError> for Error {
+    fn from(err: DeviceAuthError) -> Self {
+        Error::DeviceAuth(err)
+    }
+}
+
+impl From<CodecError> for Error {
+    fn from(err: CodecError) -> Self {
+        Error::Codec(err)
+    }
+}
+
+impl From<CommandError> for Error {
+    fn from(err: CommandError) -> Self {
+        match err {
+            CommandError::Closed => Error::Closed,
+            CommandError::LaunchFailed(reason) => Error::LaunchFailed(reason),
+            CommandError::Codec(err) => Error::Codec(err),
+            err => Error::Command(err),
+        }
+    }
+}
+
+/// Error returned when the device authentication handshake fails.
+#[derive(Debug)]
+pub enum DeviceAuthError {
+    /// The connection was closed before the device responded.
+    Closed,
+    /// The device reported an error in response to the challenge.
+    Device(String),
+    /// The device certificate chain could not be verified.
+    InvalidCertificateChain(String),
+    /// The signature over the TLS certificate could not be verified.
+    InvalidSignature,
+    /// I/O error on the connection during the handshake.
+    Io(io::Error),
+    /// The device response could not be parsed.
+    Malformed,
+    /// The device signed a different nonce than the one it was sent.
+    NonceMismatch,
+    /// The device did not present a TLS certificate.
+    NoPeerCertificate,
+    /// The device did not respond before the timeout elapsed.
+    Timeout,
+}
+
+impl error::Error for DeviceAuthError {}
+
+impl fmt::Display for DeviceAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeviceAuthError::Closed => write!(f, "Connection closed during device auth"),
+            DeviceAuthError::Device(ref err) => write!(f, "Device auth error: {}", err),
+            DeviceAuthError::InvalidCertificateChain(ref err) => {
+                write!(f, "Invalid device certificate chain: {}", err)
+            }
+            DeviceAuthError::InvalidSignature => write!(f, "Invalid device signature"),
+            DeviceAuthError::Io(ref err) => write!(f, "I/O error during device auth: {}", err),
+            DeviceAuthError::Malformed => write!(f, "Malformed device auth response"),
+            DeviceAuthError::NonceMismatch => write!(f, "Device auth nonce mismatch"),
+            DeviceAuthError::NoPeerCertificate => write!(f, "Device presented no certificate"),
+            DeviceAuthError::Timeout => write!(f, "Timed out waiting for device auth"),
+        }
+    }
+}
+
+/// Error returned by the future associated with a `Client` command.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum CommandError {
+    /// The command channel or the connection to the device was closed before
+    /// a response was received.
+    Closed,
+    /// The receiver app could not be launched.
+    LaunchFailed(Option<String>),
+    /// Load was cancelled because a second load request was received.
+    LoadCancelled,
+    /// Media could not be loaded.
+    LoadFailed,
+    /// The command cannot be performed in the current player state.
+    InvalidPlayerState,
+    /// The request is invalid or cannot be completed.
+    InvalidRequest(Option<String>),
+    /// No response was received before the request timeout elapsed.
+    Timeout,
+    /// The timer backing the request timeout failed, e.g. it was shut down or
+    /// is at capacity. The command may still have been sent.
+    Timer,
+    /// The command could not be encoded and was not sent.
+    Codec(CodecError),
+}
+
+impl error::Error for CommandError {}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CommandError::Closed => write!(f, "Connection closed before response"),
+            CommandError::LaunchFailed(ref reason) => write!(f, "Launch failed: {:?}", reason),
+            CommandError::LoadCancelled => write!(f, "Load cancelled"),
+            CommandError::LoadFailed => write!(f, "Load failed"),
+            CommandError::InvalidPlayerState => write!(f, "Invalid player state"),
+            CommandError::InvalidRequest(ref reason) => {
+                write!(f, "Invalid request: {:?}", reason)
+            }
+            CommandError::Timeout => write!(f, "Timed out waiting for response"),
+            CommandError::Timer => write!(f, "Request timer failed"),
+            CommandError::Codec(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Error for a frame the codec dropped. The connection remains usable.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum CodecError {
+    /// The frame exceeds the 64KB message size limit.
+    FrameTooLarge(usize),
+    /// The frame is not a valid `CastMessage` protobuf.
+    Malformed(String),
+}
+
+impl error::Error for CodecError {}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CodecError::FrameTooLarge(length) => write!(
+                f,
+                "Frame of length {} exceeds the max message length",
+                length
+            ),
+            CodecError::Malformed(ref err) => write!(f, "Malformed frame: {}", err),
+        }
+    }
+}
+
+/// Count of frames dropped by the codec since the client connected.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DroppedFrames {
+    /// Frames received from the device that were oversized, malformed, could
+    /// not be parsed or were on a namespace no channel handles.
+    pub inbound: usize,
+    /// Commands that encoded to oversized frames and were not sent.
+    pub outbound: usize,
+}
+
+/// Summary of the latency samples of one measurement since the client
+/// connected.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Latency {
+    /// Most recent sample.
+    pub last: Duration,
+    /// Slowest sample.
+    pub max: Duration,
+    /// Sum of all samples. See [`Latency::mean`].
+    pub total: Duration,
+    pub samples: usize,
+}
+
+/// Snapshot of the latency of the connection to the device.
+///
+/// Measurements are `None` until the first sample is taken.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Metrics {
+    /// Round-trip time from a heartbeat `PING` to the `PONG` from the device.
+    pub heartbeat_rtt: Option<Latency>,
+    /// Time from sending a `LOAD` to the first media status reporting the
+    /// media as `PLAYING`.
+    pub load_to_play: Option<Latency>,
+    /// Time from sending a command to the response that acknowledges it,
+    /// keyed by message type, e.g. `"LOAD"` or `"PLAY"`.
+    pub command_latency: HashMap<&'static str, Latency>,
+}
+
+/// Response from the receiver that acknowledges a `Client` command.
+#[derive(Clone, Debug)]
+pub enum Response {
+    /// Availability of each requested app id on the device.
+    AppAvailability(HashMap<String, bool>),
+    /// Member devices of a Cast group.
+    GroupStatus(Vec<multizone::Member>),
+    MediaStatus(Vec<MediaStatus>),
+    ReceiverStatus(Box<receiver::Status>),
+}
+
+/// Message on an application-defined namespace.
+#[derive(Clone, Debug)]
+pub struct CustomMessage {
+    pub namespace: String,
+    pub source: String,
+    pub destination: String,
+    pub payload: Payload,
+}
+
+/// Payload of a message on an application-defined namespace.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Payload {
+    Json(serde_json::Value),
+    Binary(Bytes),
+}
+
 #[derive(Debug)]
 pub enum Command {
+    AuthChallenge {
+        nonce: Vec<u8>,
+    },
+    /// Close the virtual connection to the transport of the app session.
+    Close(ReceiverConnection),
     Connect(ReceiverConnection),
+    Custom(Box<CustomMessage>),
+    GetAppAvailability {
+        app_ids: Vec<String>,
+    },
+    GetGroupStatus,
+    /// Request the status of every media session of the app, e.g. to find
+    /// the media session of an app launched by another sender.
+    GetMediaStatus(ReceiverConnection),
     Launch {
         app_id: String,
     },
@@ -70,23 +365,118 @@ pub enum Command {
     Ping,
     Play(MediaConnection),
     Pong,
+    QueueInsert {
+        connect: MediaConnection,
+        media: Vec<Media>,
+        insert_before: Option<i64>,
+    },
+    QueueLoad {
+        connect: ReceiverConnection,
+        media: Vec<Media>,
+        start_index: u32,
+    },
+    QueueNext(MediaConnection),
+    QueuePrev(MediaConnection),
+    QueueRemove(MediaConnection, Vec<i64>),
+    QueueUpdate(MediaConnection, i64),
     ReceiverStatus,
-    Seek(MediaConnection, f32),
+    /// Track the wrapped command so its response resolves `reply`.
+    Request {
+        command: Box<Command>,
+        reply: oneshot::Sender<Result<Response, CommandError>>,
+    },
+    Seek(MediaConnection, f64),
+    SetDeviceVolume {
+        level: Option<f32>,
+        muted: Option<bool>,
+    },
+    SetMemberVolume {
+        device_id: String,
+        level: Option<f32>,
+        muted: Option<bool>,
+    },
+    /// Close the virtual connection to the receiver.
     Shutdown,
     Stop(MediaConnection),
+    /// Stop the app session.
+    StopApp(ReceiverConnection),
     VolumeLevel(MediaConnection, f32),
     VolumeMute(MediaConnection, bool),
 }
 
 #[derive(Debug)]
 pub enum Status {
+    /// The client connected to the session of the launched app. Also emitted
+    /// when the session is rejoined after the connection was reestablished.
     Connected(Box<ReceiverConnection>),
+    DeviceVolume(receiver::Volume),
+    /// Member devices of the Cast group, reported when the group status is
+    /// requested.
+    GroupStatus(Vec<multizone::Member>),
+    /// A device joined the Cast group or a member changed its volume.
+    GroupMemberUpdated(multizone::Member),
+    /// A device left the Cast group.
+    GroupMemberRemoved {
+        device_id: String,
+    },
+    /// The TV the device is attached to switched inputs or entered or left
+    /// standby. Fields are `None` if the device does not report them.
+    Input {
+        is_active_input: Option<bool>,
+        is_stand_by: Option<bool>,
+    },
     MediaConnected(Box<MediaConnection>),
     MediaState(Box<MediaStatus>),
-    LoadCancelled,
-    LoadFailed,
-    InvalidPlayerState,
-    InvalidRequest,
+    LoadCancelled {
+        request_id: i64,
+    },
+    LoadFailed {
+        request_id: i64,
+    },
+    InvalidPlayerState {
+        request_id: i64,
+    },
+    InvalidRequest {
+        request_id: i64,
+        reason: Option<String>,
+    },
+    /// Another sender stopped the launched app or replaced its session. The
+    /// client no longer controls playback until an app is launched again.
+    SessionLost {
+        /// Id of the app running on the receiver, if any.
+        by_app: Option<String>,
+        /// Display name of the app running on the receiver, if any.
+        display_name: Option<String>,
+    },
+    /// An error occurred on the connection to the device, e.g. the socket
+    /// failed, a reconnect attempt or app launch failed, or a message from the
+    /// device was dropped. The client keeps running unless
+    /// `Status::Disconnected` follows.
+    Error(Box<Error>),
+    /// The connection to the device dropped and the client is reconnecting.
+    Reconnecting,
+    /// The connection to the device was reestablished after dropping. The
+    /// launched app session is not usable until the client emits
+    /// [`Status::Connected`] again.
+    Reconnected,
+    /// The connection to the device is dead. The client has shut down.
+    Disconnected {
+        reason: DisconnectReason,
+    },
+    /// Periodic snapshot of the connection latency. Emitted if enabled with
+    /// `ClientBuilder::metrics_interval`.
+    Metrics(Box<Metrics>),
+}
+
+/// Reason the connection to the device was declared dead.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DisconnectReason {
+    /// No frames were received from the device for the given duration.
+    HeartbeatTimeout(Duration),
+    /// The device failed authentication or presented a certificate that does
+    /// not match the pin while reconnecting, in strict mode. The error is
+    /// reported as [`Status::Error`] first.
+    Rejected,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -107,7 +497,12 @@ pub struct ConnectState {
     session: Option<String>,
     transport: Option<String>,
     media_session: Option<i64>,
+    device_volume: Option<receiver::Volume>,
+    input: (Option<bool>, Option<bool>),
     pub lifecycle: SessionLifecycle,
+    /// Set when the TLS connection has been reestablished and the launched app
+    /// session has not yet been rejoined.
+    pub reconnect: bool,
 }
 
 impl ConnectState {
@@ -149,6 +544,24 @@ impl ConnectState {
         changed
     }
 
+    pub fn set_device_volume(&mut self, volume: Option<receiver::Volume>) -> bool {
+        let mut changed = false;
+        if self.device_volume != volume {
+            changed = true;
+            self.device_volume = volume;
+        }
+        changed
+    }
+
+    pub fn set_input(&mut self, input: (Option<bool>, Option<bool>)) -> bool {
+        let mut changed = false;
+        if self.input != input {
+            changed = true;
+            self.input = input;
+        }
+        changed
+    }
+
     pub fn set_media_session(&mut self, media_session: Option<i64>) -> bool {
         let mut changed = false;
         if self.media_session != media_session {
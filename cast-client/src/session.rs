@@ -10,26 +10,33 @@ pub fn register(
     state: &RwLock<ConnectState>,
     session: i64,
 ) -> impl Future<Item = Option<MediaConnection>, Error = ()> {
-    let register = state.with_write(move |mut state| {
+    state.write().map(move |mut state| {
         if state.set_media_session(Some(session)) {
             debug!("media session established: {}", session);
             state.lifecycle = SessionLifecycle::Established;
-            Ok(state.media_connection())
+            state.media_connection()
         } else {
-            Ok(None)
+            None
         }
-    });
-    register.expect("lock spawn")
+    })
 }
 
 /// Invalidate a media session id. This prevents the `status::task` from
 /// polling for media status when the session is no longer valid (e.g. if a new
 /// load has been schdeduled.
 pub fn invalidate(state: &RwLock<ConnectState>) -> impl Future<Item = (), Error = ()> {
-    let invalidate = state.with_write(|mut state| {
+    state.write().map(|mut state| {
         debug!("media session invalidated");
         state.lifecycle = SessionLifecycle::NoMediaSession;
-        Ok(())
-    });
-    invalidate.expect("lock spawn")
+    })
+}
+
+/// Mark the connection as reestablished after the TLS socket dropped. The
+/// receiver channel uses this to rejoin the launched app session instead of
+/// treating the next receiver status as a new connection.
+pub fn reconnect(state: &RwLock<ConnectState>) -> impl Future<Item = (), Error = ()> {
+    state.write().map(|mut state| {
+        debug!("connection reestablished");
+        state.reconnect = true;
+    })
 }
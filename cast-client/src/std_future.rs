@@ -0,0 +1,421 @@
+//! `std::future` interface to the client.
+//!
+//! The client is implemented with `futures` 0.1. This module exposes the
+//! connection and the client commands as async functions and status updates
+//! and custom messages as `futures` 0.3 [`Stream`]s.
+//!
+//! Like [`SyncClient`](crate::SyncClient), the [`Client`] runs the connection
+//! on a tokio 0.1 runtime it owns. The futures returned by this module may be
+//! polled from any executor, including a tokio 0.2 runtime.
+//!
+//! Only the public interface is exposed on `std::future`. The connection
+//! task, the channel handlers and the session state are still implemented
+//! with `futures` 0.1 and run on the owned runtime.
+//!
+//! Requires the `std-future` feature.
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::future::{self, Either};
+use futures::sync::mpsc::UnboundedReceiver;
+use futures::sync::oneshot::{self, SpawnHandle};
+use futures::{Future, IntoFuture};
+use futures03::compat::{Compat01As03, Future01CompatExt, Stream01CompatExt};
+use futures03::Stream;
+use tokio::runtime::{Runtime, TaskExecutor};
+
+use crate::{
+    media, multizone, receiver, ClientBuilder, CommandError, CustomMessage, DroppedFrames, Error,
+    Media, MediaConnection, Metrics, Payload, ReceiverConnection, Response, Status,
+};
+
+/// Connect to the device at `addr`. Resolves once the connection is
+/// established.
+pub async fn connect(addr: SocketAddr) -> Result<(Client, StatusStream), Error> {
+    connect_with(ClientBuilder::default(), addr).await
+}
+
+/// Connect to the device at `addr` with a client configured by `builder`.
+pub async fn connect_with(
+    builder: ClientBuilder,
+    addr: SocketAddr,
+) -> Result<(Client, StatusStream), Error> {
+    let runtime = Runtime::new().map_err(Error::Io)?;
+    let (client, status, init) = builder.connect(addr);
+    let client = Client {
+        runtime: Some(runtime),
+        client: Arc::new(Mutex::new(client)),
+    };
+    oneshot::spawn(init, &client.executor()).compat().await?;
+    Ok((client, Messages(status.compat())))
+}
+
+/// Client with async commands. See [`crate::Client`].
+///
+/// Commands are sent on the runtime that drives the connection when the
+/// returned future is first polled. Call [`Client::shutdown`] to close the
+/// connection. Dropping the client signals its runtime to stop without
+/// waiting for it, so the device is not told to stop the app or close the
+/// virtual connections.
+#[derive(Debug)]
+pub struct Client {
+    runtime: Option<Runtime>,
+    client: Arc<Mutex<crate::Client>>,
+}
+
+impl Client {
+    fn executor(&self) -> TaskExecutor {
+        self.runtime
+            .as_ref()
+            .expect("runtime is only taken on shutdown")
+            .executor()
+    }
+
+    /// Issue a command from within the runtime and resolve with its result.
+    fn command<F, R>(&self, command: F) -> Compat01As03<SpawnHandle<R::Item, CommandError>>
+    where
+        F: FnOnce(&crate::Client) -> R + Send + 'static,
+        R: IntoFuture<Error = CommandError>,
+        R::Future: Send + 'static,
+        R::Item: Send + 'static,
+    {
+        let client = Arc::clone(&self.client);
+        let command = future::lazy(move || match client.lock() {
+            Ok(client) => Either::A(command(&client).into_future()),
+            Err(_) => Either::B(future::err(CommandError::Closed)),
+        });
+        oneshot::spawn(command, &self.executor()).compat()
+    }
+
+    /// Call `f` with the client. Errors if a command panicked while holding
+    /// the client.
+    fn with_client<F, T>(&self, f: F) -> Result<T, CommandError>
+    where
+        F: FnOnce(&crate::Client) -> T,
+    {
+        self.client
+            .lock()
+            .map(|client| f(&client))
+            .map_err(|_| CommandError::Closed)
+    }
+
+    /// Launch the default media receiver app.
+    pub async fn launch_app(&self) -> Result<Response, CommandError> {
+        self.command(crate::Client::launch_app).await
+    }
+
+    /// Launch the receiver app with id `app_id`.
+    pub async fn launch(&self, app_id: &str) -> Result<Response, CommandError> {
+        let app_id = app_id.to_owned();
+        self.command(move |client| client.launch(&app_id)).await
+    }
+
+    /// Join the session of the running receiver app with id `app_id`.
+    pub async fn join(&self, app_id: &str) -> Result<Response, CommandError> {
+        let app_id = app_id.to_owned();
+        self.command(move |client| client.join(&app_id)).await
+    }
+
+    /// Status of the device, including the running apps and the device
+    /// volume.
+    pub async fn receiver_status(&self) -> Result<receiver::Status, CommandError> {
+        self.command(crate::Client::receiver_status).await
+    }
+
+    /// Status of every media session of the app session `connect`.
+    pub async fn media_status(
+        &self,
+        connect: &ReceiverConnection,
+    ) -> Result<Vec<media::MediaStatus>, CommandError> {
+        let connect = connect.clone();
+        self.command(move |client| client.media_status(&connect))
+            .await
+    }
+
+    pub async fn load(
+        &self,
+        connect: &ReceiverConnection,
+        media: Media,
+    ) -> Result<Response, CommandError> {
+        let connect = connect.clone();
+        self.command(move |client| client.load(&connect, media))
+            .await
+    }
+
+    pub async fn play(&self, connect: &MediaConnection) -> Result<Response, CommandError> {
+        let connect = connect.clone();
+        self.command(move |client| client.play(&connect)).await
+    }
+
+    pub async fn pause(&self, connect: &MediaConnection) -> Result<Response, CommandError> {
+        let connect = connect.clone();
+        self.command(move |client| client.pause(&connect)).await
+    }
+
+    pub async fn stop(&self, connect: &MediaConnection) -> Result<Response, CommandError> {
+        let connect = connect.clone();
+        self.command(move |client| client.stop(&connect)).await
+    }
+
+    pub async fn seek(
+        &self,
+        connect: &MediaConnection,
+        time: f64,
+    ) -> Result<Response, CommandError> {
+        let connect = connect.clone();
+        self.command(move |client| client.seek(&connect, time))
+            .await
+    }
+
+    /// Set the volume of the device. See [`crate::Client::set_device_volume`].
+    pub async fn set_device_volume(
+        &self,
+        level: Option<f32>,
+        muted: Option<bool>,
+    ) -> Result<Response, CommandError> {
+        self.command(move |client| client.set_device_volume(level, muted))
+            .await
+    }
+
+    /// Load `media` into the queue of the receiver app and start playing the
+    /// item at `start_index`. See [`crate::Client::queue_load`].
+    pub async fn queue_load(
+        &self,
+        connect: &ReceiverConnection,
+        media: Vec<Media>,
+        start_index: u32,
+    ) -> Result<Response, CommandError> {
+        let connect = connect.clone();
+        self.command(move |client| client.queue_load(&connect, media, start_index))
+            .await
+    }
+
+    /// Insert `media` into the queue before the item with id `insert_before`,
+    /// or at the end of the queue if `None`.
+    pub async fn queue_insert(
+        &self,
+        connect: &MediaConnection,
+        media: Vec<Media>,
+        insert_before: Option<i64>,
+    ) -> Result<Response, CommandError> {
+        let connect = connect.clone();
+        self.command(move |client| client.queue_insert(&connect, media, insert_before))
+            .await
+    }
+
+    /// Remove the items with the given ids from the queue.
+    pub async fn queue_remove(
+        &self,
+        connect: &MediaConnection,
+        item_ids: Vec<i64>,
+    ) -> Result<Response, CommandError> {
+        let connect = connect.clone();
+        self.command(move |client| client.queue_remove(&connect, item_ids))
+            .await
+    }
+
+    /// Jump to the queue item with id `item_id`.
+    pub async fn queue_update(
+        &self,
+        connect: &MediaConnection,
+        item_id: i64,
+    ) -> Result<Response, CommandError> {
+        let connect = connect.clone();
+        self.command(move |client| client.queue_update(&connect, item_id))
+            .await
+    }
+
+    pub async fn queue_next(&self, connect: &MediaConnection) -> Result<Response, CommandError> {
+        let connect = connect.clone();
+        self.command(move |client| client.queue_next(&connect))
+            .await
+    }
+
+    pub async fn queue_prev(&self, connect: &MediaConnection) -> Result<Response, CommandError> {
+        let connect = connect.clone();
+        self.command(move |client| client.queue_prev(&connect))
+            .await
+    }
+
+    /// Set the volume of the media stream. `level` is in the range `[0, 1]`.
+    pub async fn set_volume(
+        &self,
+        connect: &MediaConnection,
+        level: f32,
+    ) -> Result<Response, CommandError> {
+        let connect = connect.clone();
+        self.command(move |client| client.set_volume(&connect, level))
+            .await
+    }
+
+    pub async fn set_muted(
+        &self,
+        connect: &MediaConnection,
+        muted: bool,
+    ) -> Result<Response, CommandError> {
+        let connect = connect.clone();
+        self.command(move |client| client.set_muted(&connect, muted))
+            .await
+    }
+
+    /// Request the member devices of the Cast group. See
+    /// [`crate::Client::group_status`].
+    pub async fn group_status(&self) -> Result<Vec<multizone::Member>, CommandError> {
+        self.command(crate::Client::group_status).await
+    }
+
+    /// Set the volume of the member of the Cast group with id `device_id`.
+    /// See [`crate::Client::set_member_volume`].
+    pub async fn set_member_volume(
+        &self,
+        device_id: &str,
+        level: Option<f32>,
+        muted: Option<bool>,
+    ) -> Result<Response, CommandError> {
+        let device_id = device_id.to_owned();
+        self.command(move |client| client.set_member_volume(&device_id, level, muted))
+            .await
+    }
+
+    /// Subscribe to messages on an application-defined `namespace`. See
+    /// [`crate::Client::subscribe`].
+    pub fn subscribe(&self, namespace: &str) -> Result<CustomStream, CommandError> {
+        self.with_client(|client| Messages(client.subscribe(namespace).compat()))
+    }
+
+    /// Send `payload` on an application-defined `namespace` to `destination`.
+    /// See [`crate::Client::send_custom`].
+    pub fn send_custom(
+        &self,
+        namespace: &str,
+        destination: &str,
+        payload: Payload,
+    ) -> Result<(), CommandError> {
+        self.with_client(|client| client.send_custom(namespace, destination, payload))?
+    }
+
+    /// Count of frames dropped because they were oversized, malformed, could
+    /// not be parsed or were on a namespace no channel handles.
+    pub fn dropped_frames(&self) -> DroppedFrames {
+        self.with_client(crate::Client::dropped_frames)
+            .unwrap_or_default()
+    }
+
+    /// Snapshot of the latency of the connection to the device. See
+    /// [`crate::Client::metrics`].
+    pub fn metrics(&self) -> Metrics {
+        self.with_client(crate::Client::metrics).unwrap_or_default()
+    }
+
+    /// Playback position, in seconds, at which the caller needs an up-to-date
+    /// media status. See [`crate::Client::set_deadline`].
+    pub fn set_deadline(&self, position: Option<f64>) {
+        let _ = self.with_client(|client| client.set_deadline(position));
+    }
+
+    /// Gracefully shut down the connection to the device and the runtime.
+    /// See [`crate::Client::shutdown`].
+    ///
+    /// Errors with [`Error::Closed`] if a command panicked while holding the
+    /// client, in which case the runtime is stopped without telling the
+    /// device to stop the app.
+    pub async fn shutdown(self) -> Result<(), Error> {
+        self.disconnect(crate::Client::shutdown).await
+    }
+
+    /// Close the connection to the device and the runtime without stopping
+    /// the receiver app. See [`crate::Client::close`].
+    pub async fn close(self) -> Result<(), Error> {
+        self.disconnect(crate::Client::close).await
+    }
+
+    async fn disconnect<F, R>(mut self, disconnect: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut crate::Client) -> R + Send + 'static,
+        R: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        let runtime = self
+            .runtime
+            .take()
+            .expect("runtime is only taken on shutdown");
+        let client = Arc::clone(&self.client);
+        let disconnect = future::lazy(move || match client.lock() {
+            Ok(mut client) => Either::A(disconnect(&mut client).map_err(|()| Error::Closed)),
+            Err(_) => Either::B(future::err(Error::Closed)),
+        });
+        let result = oneshot::spawn(disconnect, &runtime.executor())
+            .compat()
+            .await;
+        let _ = runtime.shutdown_now().compat().await;
+        result
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // Dropping the runtime blocks until its threads have stopped, which
+        // would stall the executor polling the future that drops the client.
+        if let Some(runtime) = self.runtime.take() {
+            drop(runtime.shutdown_now());
+        }
+    }
+}
+
+/// Stream of status updates emitted by the client.
+pub type StatusStream = Messages<Status>;
+
+/// Stream of messages received on an application-defined namespace.
+pub type CustomStream = Messages<CustomMessage>;
+
+/// `futures` 0.3 [`Stream`] of the messages emitted by the client.
+#[derive(Debug)]
+pub struct Messages<T>(Compat01As03<UnboundedReceiver<T>>);
+
+impl<T> Stream for Messages<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0)
+            .poll_next(cx)
+            .map(|message| message.and_then(Result::ok))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use futures03::executor::block_on;
+    use futures03::StreamExt;
+
+    use crate::mock::fixture;
+    use crate::{Error, Response, Status};
+
+    #[test]
+    fn loads_media_without_tokio_runtime() {
+        let (_runtime, receiver) = fixture::serve();
+
+        // The session is driven by a `futures` 0.3 executor. The client runs
+        // the connection on a runtime it owns.
+        let addr = receiver.addr();
+        let session = async move {
+            let (client, mut status) = super::connect(addr).await?;
+            client.launch_app().await?;
+            let connect = loop {
+                match status.next().await {
+                    Some(Status::Connected(connect)) => break *connect,
+                    Some(_) => continue,
+                    None => return Err(Error::Closed),
+                }
+            };
+            let response = client.load(&connect, fixture::media(1)).await?;
+            client.shutdown().await?;
+            Ok(response)
+        };
+        let response = block_on(session).unwrap();
+        match response {
+            Response::MediaStatus(ref status) => assert_eq!(1, status.len()),
+            response => panic!("unexpected response: {:?}", response),
+        }
+        receiver.shutdown();
+    }
+}
@@ -0,0 +1,514 @@
+//! Supervise the TLS connection to the device and reconnect when it drops.
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::{self, Either, Loop};
+use futures::prelude::*;
+use futures::sync::mpsc::UnboundedSender;
+use futures::AsyncSink;
+use futures_locks::RwLock;
+use stream_util::{self, Cancelable, Trigger, Valve};
+use tokio_codec::Framed;
+use tokio_tcp::TcpStream;
+use tokio_timer::Delay;
+use tokio_tls::TlsStream;
+
+use crate::capture::Recorder;
+use crate::channel;
+use crate::channel::custom::Namespaces;
+use crate::channel::deviceauth::{self, DeviceAuth};
+use crate::channel::heartbeat::Liveness;
+use crate::channel::receiver::AppId;
+use crate::channel::Responder;
+use crate::codec;
+use crate::metrics::Tracker;
+use crate::pending::Pending;
+use crate::pinning::Pinning;
+use crate::poll::Playback;
+use crate::session;
+use crate::task;
+use crate::{
+    Command, ConnectState, DeviceAuthError, DisconnectReason, Error, ReceiverConnection, Status,
+};
+
+/// Exponential backoff parameters for reconnecting to the device.
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Backoff {
+    /// Delay before the reconnect attempt with the given zero-based index.
+    fn delay(self, attempt: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(attempt);
+        self.initial
+            .checked_mul(factor)
+            .map_or(self.max, |delay| cmp::min(delay, self.max))
+    }
+}
+
+/// Shared handle to the `Trigger` that shuts down the tasks backing a
+/// `Client`.
+#[derive(Clone, Debug)]
+pub struct Shutdown(Arc<Mutex<Option<Trigger>>>);
+
+impl Shutdown {
+    pub fn new(trigger: Trigger) -> Self {
+        Shutdown(Arc::new(Mutex::new(Some(trigger))))
+    }
+
+    /// Resolve the linked `Valve`. Subsequent calls are no-ops.
+    pub fn terminate(&self) {
+        let trigger = self.0.lock().ok().and_then(|mut trigger| trigger.take());
+        if let Some(trigger) = trigger {
+            trigger.terminate();
+        }
+    }
+}
+
+/// Reason a connection to the device stopped forwarding commands.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Disconnect {
+    /// The command stream was closed by `Client::shutdown`.
+    Shutdown,
+    /// The connection to the device was lost.
+    Dropped,
+}
+
+/// Framed connection to the device.
+pub type Transport = Framed<TlsStream<TcpStream>, codec::CastMessage>;
+
+/// Shared state for the tasks backing a supervised connection.
+#[derive(Clone, Debug)]
+pub struct Supervisor {
+    pub addr: SocketAddr,
+    pub backoff: Backoff,
+    pub connect_timeout: Duration,
+    pub auth: Option<DeviceAuth>,
+    pub pinning: Option<Pinning>,
+    pub recorder: Option<Recorder>,
+    pub connect: RwLock<ConnectState>,
+    pub command: UnboundedSender<Command>,
+    pub status: UnboundedSender<Status>,
+    pub pending: Pending,
+    pub counters: codec::Counters,
+    pub metrics: Tracker,
+    pub namespaces: Namespaces,
+    pub app_id: AppId,
+    pub playback: Playback,
+    pub liveness: Liveness,
+    pub shutdown: Shutdown,
+    pub valve: Valve,
+}
+
+impl Supervisor {
+    /// Frame a TLS connection to the device and, if configured, check its
+    /// certificate against the pinned fingerprint and run the device
+    /// authentication handshake on it.
+    ///
+    /// If either check fails in strict mode, the connection is rejected.
+    /// Otherwise the failure is reported and the connection is used anyway.
+    pub fn open(
+        &self,
+        socket: TlsStream<TcpStream>,
+    ) -> impl Future<Item = Transport, Error = Error> {
+        let peer_cert = socket
+            .get_ref()
+            .peer_certificate()
+            .ok()
+            .and_then(|cert| cert)
+            .and_then(|cert| cert.to_der().ok());
+        if let Some(ref pinning) = self.pinning {
+            match pinning.verify(peer_cert.as_ref().map(Vec::as_slice)) {
+                Ok(()) => {}
+                Err(err) if pinning.strict => return Either::A(future::err(err)),
+                Err(err) => {
+                    warn!("{}", err);
+                    let _ = self.status.unbounded_send(Status::Error(Box::new(err)));
+                }
+            }
+        }
+        let transport = Framed::new(
+            socket,
+            codec::CastMessage::new(
+                self.pending.clone(),
+                self.counters.clone(),
+                self.metrics.clone(),
+                self.recorder.clone(),
+            ),
+        );
+        let auth = match self.auth.clone() {
+            Some(auth) => auth,
+            None => return Either::A(future::ok(transport)),
+        };
+        let strict = auth.strict;
+        let peer_cert = match peer_cert {
+            Some(peer_cert) => peer_cert,
+            None if strict => {
+                let err = DeviceAuthError::NoPeerCertificate;
+                return Either::A(future::err(Error::DeviceAuth(err)));
+            }
+            None => {
+                warn!("device presented no certificate, skipping device auth");
+                return Either::A(future::ok(transport));
+            }
+        };
+        let authenticate = deviceauth::authenticate(transport, peer_cert, auth)
+            .map_err(Error::DeviceAuth)
+            .and_then(move |(transport, verified)| match verified {
+                Ok(()) => {
+                    info!("device authenticated");
+                    Ok(transport)
+                }
+                Err(err) if strict => Err(Error::DeviceAuth(err)),
+                Err(err) => {
+                    warn!("device auth failed: {}", err);
+                    Ok(transport)
+                }
+            });
+        Either::B(authenticate)
+    }
+
+    /// Drive `commands` to the device over `transport`, reconnecting with
+    /// exponential backoff whenever the connection drops. Resolves when the
+    /// command stream is closed by `Client::shutdown`.
+    pub fn run(
+        self,
+        transport: Transport,
+        commands: impl Stream<Item = Command, Error = ()>,
+    ) -> impl Future<Item = (), Error = ()> {
+        future::loop_fn(
+            (transport, commands, VecDeque::new()),
+            move |(transport, commands, preamble)| {
+                let supervisor = self.clone();
+                self.session(transport, commands, preamble).and_then(
+                    move |(commands, unsent, disconnect)| match disconnect {
+                        Disconnect::Shutdown => Either::A(future::ok(Loop::Break(()))),
+                        Disconnect::Dropped => {
+                            if !unsent.is_empty() {
+                                debug!(
+                                    "{} commands were not sent before the connection dropped, \
+                                     sending them after reconnecting",
+                                    unsent.len()
+                                );
+                            }
+                            Either::B(supervisor.reconnect().map(move |transport| {
+                                // Reestablish the virtual connection to the receiver
+                                // and request its status to rejoin the launched app
+                                // before sending the commands that were not sent on
+                                // the dropped connection.
+                                let connect = Command::Connect(ReceiverConnection {
+                                    session: channel::DEFAULT_DESTINATION_ID.to_owned(),
+                                    transport: channel::DEFAULT_DESTINATION_ID.to_owned(),
+                                });
+                                let mut preamble =
+                                    VecDeque::from(vec![connect, Command::ReceiverStatus]);
+                                preamble.extend(unsent);
+                                Loop::Continue((transport, commands, preamble))
+                            }))
+                        }
+                    },
+                )
+            },
+        )
+    }
+
+    /// Run a responder and forward commands on a single connection until it
+    /// drops or the client shuts down. `preamble` is sent before `commands`.
+    ///
+    /// Resolves with the command stream and the commands that were taken from
+    /// it but not yet written to the socket.
+    fn session<S>(
+        &self,
+        transport: Transport,
+        commands: S,
+        preamble: VecDeque<Command>,
+    ) -> impl Future<Item = (S, VecDeque<Command>, Disconnect), Error = ()>
+    where
+        S: Stream<Item = Command, Error = ()>,
+    {
+        // Start monitoring liveness of the new connection.
+        self.liveness.touch();
+        let (sink, source) = transport.split();
+        // The responder holds `read_trigger` and drops it when the socket
+        // closes. `Forward` holds `write_trigger` and drops it when it
+        // completes, which cancels the responder.
+        let (read_trigger, read_closed) = stream_util::valve();
+        let (write_trigger, write_closed) = stream_util::valve();
+        let responder = Responder::new(&channel::Context {
+            connect: self.connect.clone(),
+            command: self.command.clone(),
+            status: self.status.clone(),
+            pending: self.pending.clone(),
+            namespaces: self.namespaces.clone(),
+            app_id: self.app_id.clone(),
+            playback: self.playback.clone(),
+            metrics: self.metrics.clone(),
+            counters: self.counters.clone(),
+        });
+        let respond = task::respond(
+            source.cancel(write_closed),
+            responder,
+            &self.liveness,
+            &self.status,
+        );
+        tokio_executor::spawn(respond.then(move |_| {
+            drop(read_trigger);
+            Ok(())
+        }));
+        let pending = self.pending.clone();
+        let metrics = self.metrics.clone();
+        let liveness = self.liveness.clone();
+        Forward {
+            commands: Some(commands),
+            sink,
+            closed: read_closed,
+            buffered: preamble,
+            closing: false,
+            _shutdown: write_trigger,
+        }
+        .map(move |(commands, unsent, disconnect)| {
+            if disconnect == Disconnect::Dropped {
+                // Responses to in-flight commands will never arrive.
+                pending.close();
+                metrics.reset_in_flight();
+                // There is no connection to monitor while reconnecting.
+                liveness.suspend();
+            }
+            (commands, unsent, disconnect)
+        })
+    }
+
+    /// Reconnect to the device with exponential backoff. Errors if the client
+    /// is shut down before a connection is established.
+    fn reconnect(&self) -> impl Future<Item = Transport, Error = ()> {
+        warn!("connection to {:?} dropped, reconnecting", self.addr);
+        let _ = self.status.unbounded_send(Status::Reconnecting);
+        let supervisor = self.clone();
+        let connect = future::loop_fn(0_u32, move |attempt| {
+            let delay = supervisor.backoff.delay(attempt);
+            debug!("reconnect attempt {} in {:?}", attempt + 1, delay);
+            let addr = supervisor.addr;
+            let timeout = supervisor.connect_timeout;
+            let supervisor = supervisor.clone();
+            Delay::new(Instant::now() + delay)
+                .map_err(|err| warn!("Error on reconnect timer: {:?}", err))
+                .and_then(move |_| {
+                    let status = supervisor.status.clone();
+                    let shutdown = supervisor.shutdown.clone();
+                    crate::tls_connect(addr, timeout)
+                        .and_then(move |socket| supervisor.open(socket))
+                        .then(move |result| match result {
+                            Ok(transport) => Ok(Loop::Break(transport)),
+                            Err(err) if is_rejection(&err) => {
+                                // The device presents the same certificate on
+                                // every attempt, so retrying cannot succeed.
+                                warn!("device rejected on reconnect: {}", err);
+                                let _ = status.unbounded_send(Status::Error(Box::new(err)));
+                                let reason = DisconnectReason::Rejected;
+                                let _ = status.unbounded_send(Status::Disconnected { reason });
+                                shutdown.terminate();
+                                Err(())
+                            }
+                            Err(err) => {
+                                warn!("reconnect attempt {} failed: {:?}", attempt + 1, err);
+                                let _ = status.unbounded_send(Status::Error(Box::new(err)));
+                                Ok(Loop::Continue(attempt + 1))
+                            }
+                        })
+                })
+        });
+        let state = self.connect.clone();
+        let status = self.status.clone();
+        connect
+            .select2(self.valve.clone())
+            .then(|result| match result {
+                Ok(Either::A((transport, _))) => Ok(transport),
+                // The device was rejected and the client shut down.
+                Err(Either::A(_)) => Err(()),
+                _ => {
+                    debug!("client shut down while reconnecting");
+                    Err(())
+                }
+            })
+            .and_then(move |transport| {
+                info!("TLS connection reestablished");
+                session::reconnect(&state).map(move |_| {
+                    let _ = status.unbounded_send(Status::Reconnected);
+                    transport
+                })
+            })
+    }
+}
+
+/// Whether `err` rejects the device itself rather than a single connection
+/// attempt, i.e. the device failed authentication or presented a certificate
+/// that does not match the pin in strict mode.
+fn is_rejection(err: &Error) -> bool {
+    match *err {
+        Error::DeviceAuth(ref err) => match *err {
+            DeviceAuthError::Closed | DeviceAuthError::Io(_) | DeviceAuthError::Timeout => false,
+            _ => true,
+        },
+        Error::CertificateMismatch { .. } => true,
+        _ => false,
+    }
+}
+
+/// Forward commands to the sink of a single connection.
+///
+/// Unlike `Stream::forward`, `Forward` yields the command stream and the
+/// buffered commands back to the caller when the connection drops so they can
+/// be forwarded to a new connection.
+struct Forward<St, Si> {
+    commands: Option<St>,
+    sink: Si,
+    closed: Valve,
+    buffered: VecDeque<Command>,
+    closing: bool,
+    _shutdown: Trigger,
+}
+
+impl<St, Si> Forward<St, Si>
+where
+    St: Stream<Item = Command, Error = ()>,
+    Si: Sink<SinkItem = Command, SinkError = io::Error>,
+{
+    fn finish(&mut self, disconnect: Disconnect) -> Poll<(St, VecDeque<Command>, Disconnect), ()> {
+        let commands = self
+            .commands
+            .take()
+            .expect("Forward polled after completion");
+        let unsent = mem::replace(&mut self.buffered, VecDeque::new());
+        Ok(Async::Ready((commands, unsent, disconnect)))
+    }
+
+    /// Start sending buffered commands. Returns `false` if the sink is full.
+    fn start_send_buffered(&mut self) -> Result<bool, io::Error> {
+        while let Some(command) = self.buffered.pop_front() {
+            if let AsyncSink::NotReady(command) = self.sink.start_send(command)? {
+                self.buffered.push_front(command);
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<St, Si> Future for Forward<St, Si>
+where
+    St: Stream<Item = Command, Error = ()>,
+    Si: Sink<SinkItem = Command, SinkError = io::Error>,
+{
+    type Item = (St, VecDeque<Command>, Disconnect);
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.closed.poll() {
+            Ok(Async::NotReady) => {}
+            _ => return self.finish(Disconnect::Dropped),
+        }
+        if self.closing {
+            // The command stream is closed. Flush remaining frames to the
+            // socket before completing.
+            match self.sink.close() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => {}
+                Err(err) => warn!("Error on sink close: {:?}", err),
+            }
+            return self.finish(Disconnect::Shutdown);
+        }
+        loop {
+            match self.start_send_buffered() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => {
+                    warn!("Error on sink write: {:?}", err);
+                    return self.finish(Disconnect::Dropped);
+                }
+            }
+            let command = match self.commands.as_mut() {
+                Some(commands) => commands.poll(),
+                None => panic!("Forward polled after completion"),
+            };
+            match command {
+                Ok(Async::Ready(Some(command))) => self.buffered.push_back(command),
+                Ok(Async::Ready(None)) | Err(_) => {
+                    self.closing = true;
+                    return self.poll();
+                }
+                Ok(Async::NotReady) => break,
+            }
+        }
+        if let Err(err) = self.sink.poll_complete() {
+            warn!("Error on sink flush: {:?}", err);
+            return self.finish(Disconnect::Dropped);
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use futures::prelude::*;
+    use futures::stream;
+    use futures::sync::mpsc;
+
+    use super::{Backoff, Disconnect, Forward};
+    use crate::{Command, DeviceAuthError, Error};
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+        };
+        assert_eq!(Duration::from_millis(500), backoff.delay(0));
+        assert_eq!(Duration::from_secs(1), backoff.delay(1));
+        assert_eq!(Duration::from_secs(16), backoff.delay(5));
+        assert_eq!(Duration::from_secs(30), backoff.delay(6));
+        assert_eq!(Duration::from_secs(30), backoff.delay(u32::max_value()));
+    }
+
+    #[test]
+    fn yields_unsent_commands_when_dropped() {
+        let (read_trigger, closed) = stream_util::valve();
+        let (write_trigger, _) = stream_util::valve();
+        // The socket closed before any command was written.
+        read_trigger.terminate();
+        let (sink, _frames) = mpsc::unbounded();
+        let forward = Forward {
+            commands: Some(stream::empty::<Command, ()>()),
+            sink: sink.sink_map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe)),
+            closed,
+            buffered: vec![Command::Ping, Command::ReceiverStatus].into(),
+            closing: false,
+            _shutdown: write_trigger,
+        };
+        let (_, unsent, disconnect) = forward.wait().unwrap();
+        assert_eq!(disconnect, Disconnect::Dropped);
+        assert_eq!(unsent.len(), 2);
+    }
+
+    #[test]
+    fn rejections_are_not_retried() {
+        assert!(super::is_rejection(&Error::DeviceAuth(
+            DeviceAuthError::InvalidSignature
+        )));
+        assert!(!super::is_rejection(&Error::DeviceAuth(
+            DeviceAuthError::Timeout
+        )));
+        assert!(!super::is_rejection(&Error::ConnectTimeout(
+            Duration::from_secs(10)
+        )));
+    }
+}
@@ -1,29 +1,53 @@
 use std::io;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use futures::future::{self, Loop};
 use futures::prelude::*;
 use futures::sync::mpsc::UnboundedSender;
 use futures::Future;
 use futures_locks::RwLock;
 use stream_util::{Cancelable, Valve};
-use tokio_timer::Interval;
+use tokio_timer::{Delay, Interval};
 
+use crate::channel::heartbeat::{Heartbeat, Liveness};
 use crate::channel::Responder;
+use crate::metrics::Tracker;
+use crate::poll::{Playback, Polling};
 use crate::proto::CastMessage;
-use crate::{Command, ConnectState, Status};
+use crate::supervisor::Shutdown;
+use crate::{Command, ConnectState, DisconnectReason, Error, Status};
 
+/// Send a `PING` to the device every `heartbeat.interval` and declare the
+/// connection dead if no frame has been received for `heartbeat.max_missed`
+/// intervals.
 pub fn keepalive(
     valve: Valve,
+    heartbeat: Heartbeat,
+    liveness: Liveness,
     command: UnboundedSender<Command>,
+    status: UnboundedSender<Status>,
+    shutdown: Shutdown,
 ) -> impl Future<Item = (), Error = ()> {
-    Interval::new_interval(Duration::new(5, 0))
+    // A deadline too large to represent is never reached.
+    let deadline = heartbeat.interval.checked_mul(heartbeat.max_missed);
+    Interval::new_interval(heartbeat.interval)
         .cancel(valve)
-        .map(|_| Command::Ping)
-        .or_else(|err| -> Result<Command, ()> {
+        .or_else(|err| -> Result<Instant, ()> {
             warn!("Error on heartbeat interval: {:?}", err);
             // Attempt to recover from errors on the heartbeat channel
-            Ok(Command::Ping)
+            Ok(Instant::now())
+        })
+        .take_while(move |_| match liveness.elapsed() {
+            Some(elapsed) if deadline.map_or(false, |deadline| elapsed > deadline) => {
+                warn!("No frames received from device in {:?}", elapsed);
+                let reason = DisconnectReason::HeartbeatTimeout(elapsed);
+                let _ = status.unbounded_send(Status::Disconnected { reason });
+                shutdown.terminate();
+                Ok(false)
+            }
+            _ => Ok(true),
         })
+        .map(|_| Command::Ping)
         .forward(command.sink_map_err(|err| warn!("Error on sink heartbeat: {:?}", err)))
         .map(|_| ())
         .or_else(|err| {
@@ -33,57 +57,75 @@ pub fn keepalive(
         })
 }
 
+/// Emit a snapshot of the connection latency in `metrics` every `interval`.
+pub fn report_metrics(
+    valve: Valve,
+    interval: Duration,
+    metrics: Tracker,
+    status: UnboundedSender<Status>,
+) -> impl Future<Item = (), Error = ()> {
+    Interval::new_interval(interval)
+        .cancel(valve)
+        .map_err(|err| warn!("Error on metrics interval: {:?}", err))
+        .for_each(move |_| {
+            let snapshot = Box::new(metrics.snapshot());
+            status
+                .unbounded_send(Status::Metrics(snapshot))
+                .map_err(|_| ())
+        })
+}
+
+/// Poll the device for status on the cadence given by `polling` and the last
+/// known playback state.
+///
+/// Media status is polled while a media session is established. Otherwise
+/// receiver status is polled to pick up app sessions the device did not
+/// broadcast.
 pub fn poll_status(
     valve: Valve,
     state: RwLock<ConnectState>,
+    playback: Playback,
+    polling: Polling,
     tx: UnboundedSender<Command>,
 ) -> impl Future<Item = (), Error = ()> {
-    Interval::new_interval(Duration::from_millis(150))
-        .cancel(valve)
-        .map_err(|err| warn!("Error on status interval: {:?}", err))
-        .and_then(move |_| {
-            let tx = tx.clone();
-            let status = state.clone().with_read(move |state| {
-                tx.unbounded_send(Command::ReceiverStatus).map_err(|_| ())?;
-                if let Some(connect) = state.media_connection() {
-                    tx.unbounded_send(Command::MediaStatus(connect.clone()))
-                        .map_err(|_| ())?;
+    let poll = future::loop_fn((), move |_| {
+        let delay = playback.next_poll(&polling);
+        let state = state.clone();
+        let tx = tx.clone();
+        Delay::new(Instant::now() + delay)
+            .map_err(|err| warn!("Error on status timer: {:?}", err))
+            .and_then(move |_| state.read())
+            .and_then(move |state| {
+                match state.media_connection() {
+                    Some(connect) => tx.unbounded_send(Command::MediaStatus(connect)),
+                    None => tx.unbounded_send(Command::ReceiverStatus),
                 }
-                Ok(())
-            });
-            status.expect("lock spawn")
-        })
-        .for_each(|_| Ok(()))
+                .map_err(|_| ())
+            })
+            .map(|_| Loop::<(), ()>::Continue(()))
+    });
+    poll.select2(valve).then(|_| Ok(()))
 }
 
 pub fn respond(
     source: impl Stream<Item = CastMessage, Error = io::Error>,
-    connect: &RwLock<ConnectState>,
-    command: &UnboundedSender<Command>,
+    responder: Responder,
+    liveness: &Liveness,
     status: &UnboundedSender<Status>,
 ) -> impl Future<Item = (), Error = ()> {
-    let responder = Responder::new(connect, command, status);
+    let liveness = liveness.clone();
+    let status = status.clone();
     source
-        .for_each(move |message| {
-            if let Err(err) = responder.handle(&message) {
-                warn!("responder handler error: {:?}", err);
-                return Err(io::Error::new(io::ErrorKind::Other, err));
-            }
-            Ok(())
+        .map_err(move |err| {
+            warn!("Error on responder: {:?}", err);
+            let _ = status.unbounded_send(Status::Error(Box::new(Error::Io(err))));
         })
-        .map_err(|err| warn!("Error on responder: {:?}", err))
-}
-
-pub fn send(
-    sink: impl Sink<SinkItem = Command, SinkError = io::Error>,
-    command: impl Stream<Item = Command, Error = ()>,
-) -> impl Future<Item = (), Error = ()> {
-    command
-        .forward(sink.sink_map_err(|err| warn!("Error on sink write: {:?}", err)))
-        .map(|_| ())
-        .or_else(|err| {
-            warn!("Error on write: {:?}", err);
-            // Attempt to recover from errors on the write channel
-            Ok(())
+        .for_each(move |message| {
+            liveness.touch();
+            // The responder drops bad frames itself. Remaining errors mean
+            // the client dropped its command or status channel.
+            responder
+                .handle(&message)
+                .map_err(|err| debug!("responder handler error: {:?}", err))
         })
 }
@@ -0,0 +1,171 @@
+//! Command-line argument parsing.
+use std::error;
+use std::fmt;
+
+pub const USAGE: &str = "\
+Usage: castctl <command> [<args>]
+
+Commands:
+    discover                            List Cast devices and groups
+    status <device>                     Show running apps, volume and media state
+    load <device> <url|file> [<type>]   Play a URL or a local file
+    play <device>                       Resume playback
+    pause <device>                      Pause playback
+    stop <device>                       Stop playback
+    seek <device> <seconds>             Seek to a position in the current media
+    volume <device> <level|mute|unmute> Set the device volume, level in [0, 1]
+    watch <device>                      Print status updates until interrupted
+
+<device> is the friendly name of a device or its address, e.g.
+192.168.1.20:8009. <type> is the content type of the media, which is guessed
+from the file extension if omitted.
+
+Set CASTCTL_LOG to configure logging, e.g. CASTCTL_LOG=cast_client=debug.";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Discover,
+    Status {
+        device: String,
+    },
+    Load {
+        device: String,
+        media: String,
+        content_type: Option<String>,
+    },
+    Play {
+        device: String,
+    },
+    Pause {
+        device: String,
+    },
+    Stop {
+        device: String,
+    },
+    Seek {
+        device: String,
+        position: f64,
+    },
+    Volume {
+        device: String,
+        volume: Volume,
+    },
+    Watch {
+        device: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Volume {
+    Level(f32),
+    Mute,
+    Unmute,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct UsageError(String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for UsageError {}
+
+/// Parse the arguments following the program name.
+pub fn parse(args: &[String]) -> Result<Command, UsageError> {
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let command = match args.as_slice() {
+        ["discover"] => Command::Discover,
+        ["status", device] => Command::Status {
+            device: (*device).to_owned(),
+        },
+        ["load", device, media] => Command::Load {
+            device: (*device).to_owned(),
+            media: (*media).to_owned(),
+            content_type: None,
+        },
+        ["load", device, media, content_type] => Command::Load {
+            device: (*device).to_owned(),
+            media: (*media).to_owned(),
+            content_type: Some((*content_type).to_owned()),
+        },
+        ["play", device] => Command::Play {
+            device: (*device).to_owned(),
+        },
+        ["pause", device] => Command::Pause {
+            device: (*device).to_owned(),
+        },
+        ["stop", device] => Command::Stop {
+            device: (*device).to_owned(),
+        },
+        ["seek", device, position] => Command::Seek {
+            device: (*device).to_owned(),
+            position: position
+                .parse()
+                .ok()
+                .filter(|position: &f64| *position >= 0.0)
+                .ok_or_else(|| UsageError(format!("invalid position: {}", position)))?,
+        },
+        ["volume", device, "mute"] => Command::Volume {
+            device: (*device).to_owned(),
+            volume: Volume::Mute,
+        },
+        ["volume", device, "unmute"] => Command::Volume {
+            device: (*device).to_owned(),
+            volume: Volume::Unmute,
+        },
+        ["volume", device, level] => Command::Volume {
+            device: (*device).to_owned(),
+            volume: level
+                .parse()
+                .ok()
+                .filter(|level: &f32| *level >= 0.0 && *level <= 1.0)
+                .map(Volume::Level)
+                .ok_or_else(|| UsageError(format!("invalid volume level: {}", level)))?,
+        },
+        ["watch", device] => Command::Watch {
+            device: (*device).to_owned(),
+        },
+        _ => {
+            let err = match args.first() {
+                None => "missing command".to_owned(),
+                Some(command) => format!("invalid arguments for command: {}", command),
+            };
+            return Err(UsageError(err));
+        }
+    };
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Command, Volume};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| arg.to_owned()).collect()
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse(&args(&["discover"])), Ok(Command::Discover));
+        assert_eq!(
+            parse(&args(&["seek", "Kitchen Speaker", "42.5"])),
+            Ok(Command::Seek {
+                device: "Kitchen Speaker".to_owned(),
+                position: 42.5,
+            })
+        );
+        assert_eq!(
+            parse(&args(&["volume", "192.168.1.20:8009", "mute"])),
+            Ok(Command::Volume {
+                device: "192.168.1.20:8009".to_owned(),
+                volume: Volume::Mute,
+            })
+        );
+        assert!(parse(&args(&["volume", "Kitchen Speaker", "1.5"])).is_err());
+        assert!(parse(&args(&["play"])).is_err());
+        assert!(parse(&[]).is_err());
+    }
+}
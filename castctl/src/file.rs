@@ -0,0 +1,73 @@
+//! Local media files served to the device by the embedded media server.
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use punchtop_audio::{Image, Tags, Track};
+
+/// Id of the file in the media server registry. Only one file is served.
+pub const ID: &str = "castctl";
+
+/// Content type of media with the given file extension, if it is a
+/// [format supported by Cast devices](https://developers.google.com/cast/docs/media).
+pub fn content_type(path: &str) -> Option<&'static str> {
+    let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+    let content_type = match extension.as_str() {
+        "mp3" => "audio/mpeg",
+        "m4a" | "mp4" | "aac" => "audio/mp4",
+        "flac" => "audio/flac",
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "wav" => "audio/wav",
+        "webm" => "audio/webm",
+        _ => return None,
+    };
+    Some(content_type)
+}
+
+#[derive(Clone, Debug)]
+pub struct LocalFile {
+    path: PathBuf,
+    content_type: String,
+}
+
+impl LocalFile {
+    pub fn new(path: PathBuf, content_type: String) -> Self {
+        Self { path, content_type }
+    }
+}
+
+impl Track for LocalFile {
+    fn id(&self) -> &str {
+        ID
+    }
+
+    /// The duration is unknown. The device determines it from the stream.
+    fn duration(&self) -> Duration {
+        Duration::default()
+    }
+
+    fn tags(&self) -> Option<Tags> {
+        let title = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        Some(Tags {
+            title,
+            ..Tags::default()
+        })
+    }
+
+    fn cover(&self) -> Option<Image> {
+        None
+    }
+
+    fn stream(&self) -> Option<Box<dyn Read>> {
+        let file = File::open(&self.path).ok()?;
+        Some(Box::new(file))
+    }
+
+    fn content_type(&self) -> String {
+        self.content_type.clone()
+    }
+}
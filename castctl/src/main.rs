@@ -0,0 +1,334 @@
+#![deny(clippy::all, clippy::pedantic)]
+#![deny(warnings)]
+
+#[macro_use]
+extern crate log;
+
+use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+
+use cast_client::media::{MediaStatus, PlayerState};
+use cast_client::{receiver, CommandError, Media, Response, Status, SyncClient};
+use futures::future;
+use punchtop_audio::chromecast::{devices, CastAddr, CastAddrBuilder, Device};
+use punchtop_audio::Track;
+use tokio::runtime::Runtime;
+use url::Url;
+
+mod cli;
+mod file;
+mod session;
+
+use crate::cli::{Command, Volume};
+use crate::file::LocalFile;
+use crate::session::Error;
+
+fn main() {
+    env_logger::Builder::from_env("CASTCTL_LOG").init();
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let command = match cli::parse(&args) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("castctl: {}\n\n{}", err, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(err) = run(command) {
+        eprintln!("castctl: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run(command: Command) -> Result<(), Error> {
+    match command {
+        Command::Discover => {
+            discover();
+            Ok(())
+        }
+        Command::Status { device } => status(&device),
+        Command::Load {
+            device,
+            media,
+            content_type,
+        } => load(&device, &media, content_type),
+        Command::Play { device } => with_client(&device, |client, _| {
+            let media = session::media_connection(client)?;
+            print_response(&client.play(&media)?);
+            Ok(())
+        }),
+        Command::Pause { device } => with_client(&device, |client, _| {
+            let media = session::media_connection(client)?;
+            print_response(&client.pause(&media)?);
+            Ok(())
+        }),
+        Command::Stop { device } => with_client(&device, |client, _| {
+            let media = session::media_connection(client)?;
+            print_response(&client.stop(&media)?);
+            Ok(())
+        }),
+        Command::Seek { device, position } => with_client(&device, |client, _| {
+            let media = session::media_connection(client)?;
+            print_response(&client.seek(&media, position)?);
+            Ok(())
+        }),
+        Command::Volume { device, volume } => {
+            let (level, muted) = match volume {
+                Volume::Level(level) => (Some(level), None),
+                Volume::Mute => (None, Some(true)),
+                Volume::Unmute => (None, Some(false)),
+            };
+            with_client(&device, |client, _| {
+                print_response(&client.set_device_volume(level, muted)?);
+                Ok(())
+            })
+        }
+        Command::Watch { device } => watch(&device),
+    }
+}
+
+/// Find the device with the friendly name `device`, or parse `device` as
+/// the address of a device.
+fn resolve(device: &str) -> Result<CastAddr, Error> {
+    let cast = match device.parse::<SocketAddr>() {
+        Ok(addr) => CastAddrBuilder::default()
+            .name(device.to_owned())
+            .addr(addr.ip())
+            .port(addr.port())
+            .into_castaddr(),
+        Err(_) => devices().find(|cast| cast.name == device),
+    };
+    let cast = cast.ok_or_else(|| Error::DeviceNotFound(device.to_owned()))?;
+    debug!("resolved device {} to {}", device, cast.addr());
+    Ok(cast)
+}
+
+/// Connect to the device `device`, run `command` and close the connection.
+fn with_client<T>(
+    device: &str,
+    command: impl FnOnce(&SyncClient, &Receiver<Status>) -> Result<T, Error>,
+) -> Result<T, Error> {
+    resolve(device).and_then(|cast| session::with_client(cast.addr(), command))
+}
+
+fn discover() {
+    let mut found = false;
+    for cast in devices() {
+        found = true;
+        let kind = if cast.is_group { "group" } else { "device" };
+        println!("{}\t{}\t{}", cast.name, cast.addr(), kind);
+    }
+    if !found {
+        eprintln!("castctl: No devices found");
+    }
+}
+
+fn status(device: &str) -> Result<(), Error> {
+    with_client(device, |client, _| print_status(client))
+}
+
+fn print_status(client: &SyncClient) -> Result<(), Error> {
+    let receiver = client.status()?;
+    print_receiver(&receiver);
+    let app = match session::media_app(&receiver) {
+        Some(app) => app,
+        None => return Ok(()),
+    };
+    let connect = client.join(&app.app_id)?;
+    let media = client.media_status(&connect)?;
+    if media.is_empty() {
+        println!("Media: none");
+    }
+    for media in &media {
+        println!("{}", describe_media(media));
+    }
+    Ok(())
+}
+
+fn load(device: &str, media: &str, content_type: Option<String>) -> Result<(), Error> {
+    let url = Url::parse(media)
+        .ok()
+        .filter(|url| url.scheme() == "http" || url.scheme() == "https");
+    let path = url.as_ref().map_or(media, Url::path);
+    let content_type = content_type
+        .or_else(|| file::content_type(path).map(str::to_owned))
+        .ok_or_else(|| Error::InvalidMedia(format!("unknown content type of {}", media)))?;
+    let cast = resolve(device)?;
+    match url {
+        Some(url) => load_url(&cast, url, content_type),
+        None => load_file(&cast, Path::new(media), content_type),
+    }
+}
+
+/// Load media the device can fetch itself.
+fn load_url(cast: &CastAddr, url: Url, content_type: String) -> Result<(), Error> {
+    session::with_client(cast.addr(), |client, _| {
+        load_media(client, url, content_type)
+    })
+}
+
+fn load_media(client: &SyncClient, url: Url, content_type: String) -> Result<(), Error> {
+    let connect = client.launch_app()?;
+    let title = url
+        .path_segments()
+        .and_then(Iterator::last)
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_owned);
+    let media = Media {
+        title,
+        artist: None,
+        album: None,
+        url,
+        cover: None,
+        content_type,
+        duration: None,
+    };
+    let media = client.load(&connect, media)?;
+    for media in &client.media_status(&media.receiver)? {
+        println!("{}", describe_media(media));
+    }
+    Ok(())
+}
+
+/// Serve a local file to the device until playback finishes.
+fn load_file(cast: &CastAddr, path: &Path, content_type: String) -> Result<(), Error> {
+    if !path.is_file() {
+        return Err(Error::InvalidMedia(format!(
+            "no such file {}",
+            path.display()
+        )));
+    }
+    let mut runtime = Runtime::new().map_err(Error::Runtime)?;
+    let file = LocalFile::new(path.to_path_buf(), content_type);
+    let mut registry = HashMap::new();
+    let track: Box<dyn Track + Send + Sync> = Box::new(file.clone());
+    registry.insert(file::ID.to_owned(), track);
+    let (device, status, connect) = Device::connect(cast, registry).map_err(Error::Backend)?;
+    runtime.block_on(connect).map_err(Error::Connect)?;
+    let status = session::forward(&mut runtime, status);
+    let connect = session::wait_for(&status, "app session", session::connected)?;
+    // The connection to the device closes when `device` is dropped.
+    let device = Arc::new(device);
+    let load = {
+        let device = Arc::clone(&device);
+        future::lazy(move || device.load(&connect, &file))
+    };
+    runtime.block_on(load).map_err(Error::Backend)?;
+    println!(
+        "Serving {} to {}. Press Ctrl-C to stop.",
+        path.display(),
+        cast.name
+    );
+    let mut started = false;
+    for status in status.iter() {
+        match status {
+            Status::MediaState(ref media) => {
+                println!("{}", describe_media(media));
+                match media.player_state {
+                    PlayerState::Playing | PlayerState::Buffering => started = true,
+                    PlayerState::Idle if started => return Ok(()),
+                    PlayerState::Idle | PlayerState::Paused => {}
+                }
+            }
+            Status::LoadFailed { .. } => return Err(CommandError::LoadFailed.into()),
+            Status::Disconnected { .. } => return Err(Error::Closed),
+            _ => {}
+        }
+    }
+    Err(Error::Closed)
+}
+
+/// Print status updates until the connection to the device is lost.
+fn watch(device: &str) -> Result<(), Error> {
+    with_client(device, watch_status)
+}
+
+fn watch_status(client: &SyncClient, status: &Receiver<Status>) -> Result<(), Error> {
+    let receiver = client.status()?;
+    print_receiver(&receiver);
+    if let Some(app) = session::media_app(&receiver) {
+        // Track the media session so its status is polled.
+        let connect = client.join(&app.app_id)?;
+        client.media_status(&connect)?;
+    }
+    for status in status.iter() {
+        println!("{}", describe(&status));
+        if let Status::Disconnected { .. } = status {
+            return Err(Error::Closed);
+        }
+    }
+    Err(Error::Closed)
+}
+
+fn print_response(response: &Response) {
+    match *response {
+        Response::MediaStatus(ref media) => {
+            for media in media {
+                println!("{}", describe_media(media));
+            }
+        }
+        Response::ReceiverStatus(ref receiver) => print_receiver(receiver),
+        ref response => println!("{:?}", response),
+    }
+}
+
+fn print_receiver(receiver: &receiver::Status) {
+    println!("Volume: {}", describe_volume(&receiver.volume));
+    if let Some(is_active_input) = receiver.is_active_input {
+        println!("Active input: {}", is_active_input);
+    }
+    if let Some(is_stand_by) = receiver.is_stand_by {
+        println!("Standby: {}", is_stand_by);
+    }
+    if receiver.applications.is_empty() {
+        println!("App: none");
+    }
+    for app in &receiver.applications {
+        println!(
+            "App: {} ({}) {}",
+            app.display_name, app.app_id, app.status_text
+        );
+    }
+}
+
+fn describe(status: &Status) -> String {
+    match *status {
+        Status::Connected(ref connect) => format!("Connected to session {}", connect.session),
+        Status::DeviceVolume(ref volume) => format!("Volume: {}", describe_volume(volume)),
+        Status::MediaState(ref media) => describe_media(media),
+        Status::Error(ref err) => format!("Error: {}", err),
+        ref status => format!("{:?}", status),
+    }
+}
+
+fn describe_volume(volume: &receiver::Volume) -> String {
+    let level = volume
+        .level
+        .map_or_else(|| "unknown".to_owned(), |level| format!("{:.2}", level));
+    if volume.muted == Some(true) {
+        format!("{} (muted)", level)
+    } else {
+        level
+    }
+}
+
+fn describe_media(media: &MediaStatus) -> String {
+    let info = media.media.as_ref();
+    let title = info
+        .and_then(|info| info.metadata.as_ref())
+        .and_then(|metadata| metadata.title.clone())
+        .or_else(|| info.map(|info| info.content_id.clone()))
+        .unwrap_or_default();
+    let duration = info
+        .and_then(|info| info.duration)
+        .map(|duration| format!(" / {:.1}s", duration))
+        .unwrap_or_default();
+    format!(
+        "Media: {:?} {} at {:.1}s{}",
+        media.player_state, title, media.current_time, duration
+    )
+}
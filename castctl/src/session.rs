@@ -0,0 +1,149 @@
+//! Connection to a device for the duration of a command.
+use std::error;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use cast_client::{self, receiver, MediaConnection, ReceiverConnection, Status, SyncClient};
+use futures::prelude::*;
+use futures::sync::mpsc::UnboundedReceiver;
+use tokio::runtime::Runtime;
+
+/// Namespace of the media channel. Apps that support it can be controlled
+/// with media commands.
+const MEDIA_NAMESPACE: &str = "urn:x-cast:com.google.cast.media";
+/// Timeout for a status update the command is waiting for.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum Error {
+    DeviceNotFound(String),
+    Runtime(io::Error),
+    Connect(cast_client::Error),
+    Command(cast_client::Error),
+    Backend(punchtop_audio::Error),
+    InvalidMedia(String),
+    /// No app that supports media commands is running on the device.
+    NoMediaApp,
+    /// The media app has no media session.
+    NoMediaSession,
+    /// The device did not send the awaited status update in time.
+    Timeout(&'static str),
+    Closed,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::DeviceNotFound(ref name) => write!(f, "No device named {} found", name),
+            Error::Runtime(ref err) => write!(f, "Failed to start runtime: {}", err),
+            Error::Connect(ref err) => write!(f, "Failed to connect: {}", err),
+            Error::Command(ref err) => write!(f, "Command failed: {}", err),
+            Error::Backend(ref err) => write!(f, "Failed to serve media: {:?}", err),
+            Error::InvalidMedia(ref media) => write!(f, "Invalid media: {}", media),
+            Error::NoMediaApp => write!(f, "No media app is running"),
+            Error::NoMediaSession => write!(f, "No media is loaded"),
+            Error::Timeout(what) => write!(f, "Timed out waiting for {}", what),
+            Error::Closed => write!(f, "Connection closed"),
+        }
+    }
+}
+
+impl From<cast_client::Error> for Error {
+    fn from(err: cast_client::Error) -> Self {
+        Error::Command(err)
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Runtime(ref err) => Some(err),
+            Error::Connect(ref err) => Some(err),
+            Error::Command(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Connect to the device at `addr`.
+pub fn connect(addr: SocketAddr) -> Result<(SyncClient, Receiver<Status>), Error> {
+    SyncClient::connect(addr).map_err(Error::Connect)
+}
+
+/// Connect to the device at `addr`, run `command` and close the connection.
+/// Apps launched by `command` keep running.
+pub fn with_client<T>(
+    addr: SocketAddr,
+    command: impl FnOnce(&SyncClient, &Receiver<Status>) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let (client, status) = connect(addr)?;
+    let result = command(&client, &status);
+    client.close();
+    result
+}
+
+/// Join the running media app and find its media session.
+pub fn media_connection(client: &SyncClient) -> Result<MediaConnection, Error> {
+    let status = client.status()?;
+    let app = media_app(&status).ok_or(Error::NoMediaApp)?;
+    let receiver = client.join(&app.app_id)?;
+    let media = client.media_status(&receiver)?;
+    let session = media
+        .first()
+        .map(|media| media.media_session_id)
+        .ok_or(Error::NoMediaSession)?;
+    Ok(MediaConnection { receiver, session })
+}
+
+/// The first running app that supports media commands.
+pub fn media_app(status: &receiver::Status) -> Option<&receiver::Applications> {
+    status.applications.iter().find(|app| {
+        app.namespaces
+            .iter()
+            .any(|namespace| namespace.name == MEDIA_NAMESPACE)
+    })
+}
+
+/// Forward status updates from the runtime to a blocking receiver.
+pub fn forward(runtime: &mut Runtime, status: UnboundedReceiver<Status>) -> Receiver<Status> {
+    let (tx, rx) = mpsc::channel();
+    runtime.spawn(status.for_each(move |status| tx.send(status).map_err(|_| ())));
+    rx
+}
+
+/// Block until `f` maps a status update to `Some`. Errors if no such update
+/// is received within a timeout.
+pub fn wait_for<T>(
+    status: &Receiver<Status>,
+    what: &'static str,
+    mut f: impl FnMut(Status) -> Option<T>,
+) -> Result<T, Error> {
+    let deadline = Instant::now() + STATUS_TIMEOUT;
+    loop {
+        let now = Instant::now();
+        let timeout = if now < deadline {
+            deadline - now
+        } else {
+            Duration::default()
+        };
+        match status.recv_timeout(timeout) {
+            Ok(status) => {
+                if let Some(item) = f(status) {
+                    return Ok(item);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => return Err(Error::Timeout(what)),
+            Err(RecvTimeoutError::Disconnected) => return Err(Error::Closed),
+        }
+    }
+}
+
+pub fn connected(status: Status) -> Option<ReceiverConnection> {
+    match status {
+        Status::Connected(connect) => Some(*connect),
+        _ => None,
+    }
+}
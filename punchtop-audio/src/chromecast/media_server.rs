@@ -31,6 +31,11 @@ pub struct Route {
 }
 
 impl Route {
+    /// Routes to the media server at `base`.
+    pub fn new(base: Url) -> Self {
+        Self { base }
+    }
+
     pub fn media(&self, track: &impl Track) -> Url {
         self.base
             .join(&uri!(media: track.id()).to_string())
@@ -73,7 +78,7 @@ pub fn spawn(
 ) -> Result<Route, Error> {
     let addr = default_interface_addr(cast).and_then(get_available_port)?;
     let base = Url::parse(&format!("http://{}/", addr)).map_err(|_| Error::BaseUrl)?;
-    let router = Route { base };
+    let router = Route::new(base);
     debug!("bind to {:?}", addr);
     let config = Config::build(Environment::Production)
         .address(addr.ip().to_string())
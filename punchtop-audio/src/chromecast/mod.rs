@@ -3,7 +3,9 @@ use std::hash::{Hash, Hasher};
 use std::net::{IpAddr, SocketAddr};
 use std::time::Duration;
 
-use cast_client::{self, Client, Image, Media, MediaConnection, ReceiverConnection, Status};
+use cast_client::{
+    self, multizone, Client, Image, Media, MediaConnection, ReceiverConnection, Status,
+};
 use floating_duration::TimeAsFloat;
 use futures::sync::mpsc::UnboundedReceiver;
 use futures::Future;
@@ -11,6 +13,9 @@ use mdns::RecordKind;
 
 mod media_server;
 mod parser;
+mod sync;
+
+pub use self::sync::{SyncGroup, SyncStatus};
 
 use crate::chromecast::media_server::Route;
 use crate::{Error, Result, Track};
@@ -19,6 +24,14 @@ use crate::{Error, Result, Track};
 const SERVICE_NAME: &str = "_googlecast._tcp.local";
 /// Key in DNS TXT record for Chromecast "friendly name".
 const CHROMECAST_NAME_KEY: &str = "fn";
+/// Key in DNS TXT record for Chromecast model name.
+const CHROMECAST_MODEL_KEY: &str = "md";
+/// Key in DNS TXT record for Chromecast capabilities bitmask.
+const CHROMECAST_CAPABILITIES_KEY: &str = "ca";
+/// Model name advertised by Cast groups.
+const GROUP_MODEL: &str = "Google Cast Group";
+/// Capability bit set by Cast groups.
+const GROUP_CAPABILITY: u32 = 1 << 5;
 /// Timeout for discovering Chromecast devices with mdns.
 const DISCOVER_TIMEOUT: Duration = Duration::from_millis(3000);
 
@@ -29,6 +42,15 @@ pub struct CastAddr {
     pub name: String,
     /// Address of Chromecast as discovered by mdns.
     addr: SocketAddr,
+    /// Whether the endpoint is a Cast group of several devices.
+    pub is_group: bool,
+}
+
+impl CastAddr {
+    /// Address of the Chromecast.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
 }
 
 impl PartialEq for CastAddr {
@@ -50,6 +72,7 @@ pub struct CastAddrBuilder {
     name: Option<String>,
     addr: Option<IpAddr>,
     port: Option<u16>,
+    is_group: bool,
 }
 
 impl CastAddrBuilder {
@@ -68,10 +91,19 @@ impl CastAddrBuilder {
         self
     }
 
+    pub fn group(mut self, is_group: bool) -> Self {
+        self.is_group = is_group;
+        self
+    }
+
     pub fn into_castaddr(self) -> Option<CastAddr> {
         let name = self.name?;
         let addr = SocketAddr::new(self.addr?, self.port?);
-        Some(CastAddr { name, addr })
+        Some(CastAddr {
+            name,
+            addr,
+            is_group: self.is_group,
+        })
     }
 }
 
@@ -89,64 +121,95 @@ impl Device {
         (
             Self,
             UnboundedReceiver<Status>,
-            impl Future<Item = (), Error = ()>,
+            impl Future<Item = (), Error = cast_client::Error>,
         ),
         Error,
     > {
         let router =
             media_server::spawn(registry, config.addr).map_err(|_| Error::BackendNotInitialized)?;
         let (cast, status, connect) = cast_client::connect(config.addr);
-        cast.launch_app();
+        let _ = cast.launch_app();
         let backend = Self { router, cast };
         Ok((backend, status, connect))
     }
 
     pub fn stop(&self, connect: &MediaConnection) -> Result {
-        self.cast.stop(connect);
+        let _ = self.cast.stop(connect);
         Ok(())
     }
 
-    pub fn shutdown(&mut self) -> Result {
-        self.cast.shutdown();
-        Ok(())
+    /// Stop playback and the receiver app and close the connection to the
+    /// device. The returned future resolves once the connection is closed.
+    pub fn shutdown(&mut self) -> impl Future<Item = (), Error = ()> {
+        self.cast.shutdown()
     }
 
     pub fn load(&self, connect: &ReceiverConnection, track: &impl Track) -> Result {
-        let media = self.metadata(track).ok_or_else(|| Error::CannotLoadMedia)?;
-        self.cast.load(connect, media);
+        let media = metadata(&self.router, track).ok_or_else(|| Error::CannotLoadMedia)?;
+        let _ = self.cast.load(connect, media);
         Ok(())
     }
 
+    /// Poll the device more often as playback approaches `position`, e.g.
+    /// the end of a round.
+    pub fn set_deadline(&self, position: Option<f64>) {
+        self.cast.set_deadline(position);
+    }
+
     pub fn pause(&self, connect: &MediaConnection) -> Result {
-        self.cast.pause(connect);
+        let _ = self.cast.pause(connect);
         Ok(())
     }
 
     pub fn play(&self, connect: &MediaConnection) -> Result {
-        self.cast.play(connect);
+        let _ = self.cast.play(connect);
         Ok(())
     }
 
-    fn metadata(&self, track: &impl Track) -> Option<Media> {
-        let url = self.router.cover(track);
-        let cover = track
-            .cover()
-            .map(|img| (img.width, img.height))
-            .map(|dimensions| Image { url, dimensions });
-        let tags = track.tags();
-        let url = self.router.media(track);
-        tags.map(|tags| Media {
-            title: tags.title,
-            artist: tags.artist,
-            album: tags.album,
-            url,
-            cover,
-            content_type: track.content_type(),
-            duration: Some(track.duration().as_fractional_secs()),
-        })
+    /// Request the member devices of a Cast group.
+    pub fn group_status(
+        &self,
+    ) -> impl Future<Item = Vec<multizone::Member>, Error = cast_client::CommandError> {
+        self.cast.group_status()
+    }
+
+    /// Set the volume of the member of a Cast group with id `device_id`.
+    /// Resolves once the group acknowledges the change.
+    pub fn set_member_volume(
+        &self,
+        device_id: &str,
+        level: Option<f32>,
+        muted: Option<bool>,
+    ) -> impl Future<Item = (), Error = cast_client::CommandError> {
+        self.cast
+            .set_member_volume(device_id, level, muted)
+            .map(|_| ())
     }
 }
 
+/// Media metadata for `track` served by the media server at `router`.
+fn metadata(router: &Route, track: &impl Track) -> Option<Media> {
+    let url = router.cover(track);
+    let cover = track
+        .cover()
+        .map(|img| (img.width, img.height))
+        .map(|dimensions| Image { url, dimensions });
+    let tags = track.tags();
+    let url = router.media(track);
+    tags.map(|tags| Media {
+        title: tags.title,
+        artist: tags.artist,
+        album: tags.album,
+        url,
+        cover,
+        content_type: track.content_type(),
+        // Tracks of unknown duration report a zero duration.
+        duration: Some(track.duration())
+            .filter(|duration| *duration > Duration::default())
+            .map(|duration| duration.as_fractional_secs()),
+    })
+}
+
 /// An iterator yielding Chromecast `Device`s available for audio playback.
 ///
 /// See [`devices()`](fn.devices.html).
@@ -177,7 +240,9 @@ pub fn devices() -> Devices {
                                 RecordKind::AAAA(v6) => builder.addr(v6.into()),
                                 RecordKind::SRV { port: p, .. } => builder.port(p),
                                 RecordKind::TXT(ref text) => {
-                                    match parser::dns_txt(text).get(CHROMECAST_NAME_KEY) {
+                                    let txt = parser::dns_txt(text);
+                                    let builder = builder.group(is_group(&txt));
+                                    match txt.get(CHROMECAST_NAME_KEY) {
                                         Some(name) => builder.name(name.to_owned()),
                                         None => builder,
                                     }
@@ -186,7 +251,10 @@ pub fn devices() -> Devices {
                             }
                         });
                 if let Some(cast) = builder.into_castaddr() {
-                    debug!("found device: name={} addr={}", cast.name, cast.addr);
+                    debug!(
+                        "found device: name={} addr={} group={}",
+                        cast.name, cast.addr, cast.is_group
+                    );
                     devices.insert(cast);
                 }
             }
@@ -196,3 +264,13 @@ pub fn devices() -> Devices {
         connect: devices.into_iter(),
     }
 }
+
+/// Whether a DNS TXT record advertises a Cast group.
+fn is_group(txt: &HashMap<String, String>) -> bool {
+    let model = txt.get(CHROMECAST_MODEL_KEY).map(String::as_str);
+    let capabilities = txt
+        .get(CHROMECAST_CAPABILITIES_KEY)
+        .and_then(|ca| ca.parse::<u32>().ok())
+        .unwrap_or_default();
+    model == Some(GROUP_MODEL) || capabilities & GROUP_CAPABILITY != 0
+}
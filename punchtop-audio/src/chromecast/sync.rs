@@ -0,0 +1,686 @@
+//! Synchronized playback of the same track on several Chromecasts.
+//!
+//! Each member of a [`SyncGroup`] is driven by its own cast client. The group
+//! loads a track on every connected member and, once each of them has a media
+//! session or failed to load the track, seeks them to a common position and
+//! starts them together. The first member
+//! with a media session is the leader: its reported `current_time` is the game
+//! clock and the other members are seeked back to it when they drift.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use cast_client::media::{MediaStatus, PlayerState};
+use cast_client::{self, Client, MediaConnection, ReceiverConnection, Status};
+use floating_duration::TimeAsFloat;
+use futures::future;
+use futures::prelude::*;
+use futures::sync::mpsc::{self, UnboundedReceiver};
+
+use crate::chromecast::media_server::{self, Route};
+use crate::chromecast::{metadata, CastAddr};
+use crate::{Error, Result, Track};
+
+/// Members whose position differs from the leader by more than this many
+/// seconds are seeked to the position of the leader.
+const DRIFT_TOLERANCE: f64 = 0.25;
+/// Minimum interval between drift corrections. A seek takes effect only once
+/// the member reports its next status.
+const CORRECTION_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Status of a [`SyncGroup`], aggregated from the status of its members.
+#[derive(Debug)]
+pub enum SyncStatus {
+    /// Every member is connected to an app session and media can be loaded.
+    Connected,
+    /// Every member loaded the current track and playback started at the same
+    /// position on each of them.
+    Started,
+    /// Position of the group in the current track in seconds.
+    Elapsed(f64),
+    /// The member with the given index is connected to an app session.
+    MemberConnected(usize),
+    /// A status of the member with the given index that the group does not
+    /// aggregate.
+    Member(usize, Status),
+    /// The connection to every member is closed.
+    Disconnected,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Phase {
+    Connecting,
+    Connected,
+    Loading,
+    Playing,
+    Paused,
+}
+
+/// Playback position reported by a member.
+#[derive(Clone, Copy, Debug)]
+struct Position {
+    current_time: f64,
+    playback_rate: f64,
+    reported: Instant,
+}
+
+impl Position {
+    fn new(status: &MediaStatus) -> Self {
+        let playback_rate = if status.player_state == PlayerState::Playing {
+            status.playback_rate
+        } else {
+            0.0
+        };
+        Self {
+            current_time: status.current_time,
+            playback_rate,
+            reported: Instant::now(),
+        }
+    }
+
+    /// Position of the member at `now`, extrapolated from the last report.
+    fn extrapolate(&self, now: Instant) -> f64 {
+        if now > self.reported {
+            let elapsed = (now - self.reported).as_fractional_secs();
+            self.current_time + elapsed * self.playback_rate
+        } else {
+            self.current_time
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Member {
+    name: String,
+    /// Whether the member is a Cast group of several speakers.
+    is_group: bool,
+    cast: Client,
+    connect: Option<ReceiverConnection>,
+    /// Set while the member was sent the current track and has neither
+    /// loaded it nor failed to.
+    loading: bool,
+    session: Option<MediaConnection>,
+    position: Option<Position>,
+    closed: bool,
+}
+
+impl Member {
+    /// Seek to `position` and resume playback if `play` is set. The member is
+    /// paused first so it does not play ahead while the seek is in flight.
+    fn start(&self, position: f64, play: bool) {
+        if let Some(ref session) = self.session {
+            let _ = self.cast.pause(session);
+            let _ = self.cast.seek(session, position);
+            if play {
+                let _ = self.cast.play(session);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.connect = None;
+        self.loading = false;
+        self.session = None;
+        self.position = None;
+    }
+}
+
+/// Several Chromecasts that play the same track in sync.
+#[derive(Debug)]
+pub struct SyncGroup {
+    router: Route,
+    members: Vec<Member>,
+    phase: Phase,
+    corrected: Option<Instant>,
+}
+
+impl SyncGroup {
+    /// Connect to every device in `configs` and launch the media receiver app
+    /// on each of them.
+    ///
+    /// Returns the group, the status updates of all members tagged with the
+    /// index of the member in `configs`, and a future that resolves once every
+    /// member is connected. The future must be spawned on a tokio runtime and
+    /// fails if any member fails to connect.
+    pub fn connect(
+        configs: &[CastAddr],
+        registry: HashMap<String, Box<dyn Track + Send + Sync>>,
+    ) -> std::result::Result<
+        (
+            Self,
+            UnboundedReceiver<(usize, Status)>,
+            impl Future<Item = (), Error = cast_client::Error>,
+        ),
+        Error,
+    > {
+        let first = configs.first().ok_or(Error::BackendNotInitialized)?;
+        // Members share a network, so one media server serves all of them.
+        let router =
+            media_server::spawn(registry, first.addr).map_err(|_| Error::BackendNotInitialized)?;
+        Ok(Self::with_router(router, configs))
+    }
+
+    /// Connect to every device in `configs`. Media is served by the media
+    /// server behind `router`.
+    fn with_router(
+        router: Route,
+        configs: &[CastAddr],
+    ) -> (
+        Self,
+        UnboundedReceiver<(usize, Status)>,
+        impl Future<Item = (), Error = cast_client::Error>,
+    ) {
+        let (tx, rx) = mpsc::unbounded();
+        let mut members = Vec::with_capacity(configs.len());
+        let mut forwards = Vec::with_capacity(configs.len());
+        let mut connects = Vec::with_capacity(configs.len());
+        for (index, config) in configs.iter().enumerate() {
+            let (cast, status, connect) = cast_client::connect(config.addr);
+            let _ = cast.launch_app();
+            if config.is_group {
+                // The speakers of the group are reported as
+                // `Status::GroupStatus`.
+                let _ = cast.group_status();
+            }
+            let tx = tx.clone().sink_map_err(|_| ());
+            forwards.push(status.map(move |status| (index, status)).forward(tx));
+            connects.push(connect);
+            members.push(Member {
+                name: config.name.clone(),
+                is_group: config.is_group,
+                cast,
+                connect: None,
+                loading: false,
+                session: None,
+                position: None,
+                closed: false,
+            });
+        }
+        let connect = future::lazy(move || {
+            for forward in forwards {
+                tokio_executor::spawn(forward.map(|_| ()));
+            }
+            future::join_all(connects).map(|_| ())
+        });
+        let group = Self {
+            router,
+            members,
+            phase: Phase::Connecting,
+            corrected: None,
+        };
+        (group, rx, connect)
+    }
+
+    /// Name of the member with index `index`.
+    pub fn name(&self, index: usize) -> Option<&str> {
+        self.members.get(index).map(|member| member.name.as_str())
+    }
+
+    /// Whether the member with index `index` is a Cast group of several
+    /// speakers.
+    pub fn is_group(&self, index: usize) -> bool {
+        self.members
+            .get(index)
+            .map_or(false, |member| member.is_group)
+    }
+
+    /// Set the volume of the speaker with id `device_id` in the Cast group
+    /// with index `index`. Resolves once the group acknowledges the change.
+    pub fn set_member_volume(
+        &self,
+        index: usize,
+        device_id: &str,
+        level: Option<f32>,
+        muted: Option<bool>,
+    ) -> impl Future<Item = (), Error = cast_client::CommandError> {
+        match self.members.get(index).filter(|member| member.is_group) {
+            Some(member) => future::Either::A(
+                member
+                    .cast
+                    .set_member_volume(device_id, level, muted)
+                    .map(|_| ()),
+            ),
+            None => future::Either::B(future::err(cast_client::CommandError::InvalidRequest(None))),
+        }
+    }
+
+    /// Whether any member with an open connection is connected to an app
+    /// session.
+    pub fn has_session(&self) -> bool {
+        self.live().any(|member| member.connect.is_some())
+    }
+
+    /// Load `track` on every connected member. Playback starts once each of
+    /// them has loaded it, failed to load it or lost its app session.
+    ///
+    /// Errors with [`Error::CannotLoadMedia`] if no member is connected to an
+    /// app session.
+    pub fn load(&mut self, track: &impl Track) -> Result {
+        if !self.has_session() {
+            return Err(Error::CannotLoadMedia);
+        }
+        let media = metadata(&self.router, track).ok_or_else(|| Error::CannotLoadMedia)?;
+        for member in &mut self.members {
+            member.loading = match member.connect {
+                Some(ref connect) => {
+                    let _ = member.cast.load(connect, media.clone());
+                    true
+                }
+                None => false,
+            };
+            member.session = None;
+            member.position = None;
+        }
+        self.phase = Phase::Loading;
+        Ok(())
+    }
+
+    pub fn play(&mut self) {
+        if self.phase == Phase::Paused {
+            self.phase = Phase::Playing;
+        }
+        for member in &self.members {
+            if let Some(ref session) = member.session {
+                let _ = member.cast.play(session);
+            }
+        }
+    }
+
+    pub fn pause(&mut self) {
+        if self.phase == Phase::Playing {
+            self.phase = Phase::Paused;
+        }
+        for member in &self.members {
+            if let Some(ref session) = member.session {
+                let _ = member.cast.pause(session);
+            }
+        }
+    }
+
+    /// Poll every member more often as playback approaches `position`.
+    pub fn set_deadline(&self, position: Option<f64>) {
+        for member in &self.members {
+            member.cast.set_deadline(position);
+        }
+    }
+
+    /// Stop playback and the receiver app on every member and close the
+    /// connections. The returned future resolves once every connection is
+    /// closed.
+    pub fn shutdown(&mut self) -> impl Future<Item = (), Error = ()> {
+        let shutdown = self
+            .members
+            .iter_mut()
+            .map(|member| member.cast.shutdown())
+            .collect::<Vec<_>>();
+        future::join_all(shutdown).map(|_| ())
+    }
+
+    /// Apply a status update of the member with index `index` and return the
+    /// resulting group status updates.
+    pub fn handle(&mut self, index: usize, status: Status) -> Vec<SyncStatus> {
+        let mut events = vec![];
+        if index >= self.members.len() {
+            return events;
+        }
+        match status {
+            Status::Connected(connect) => {
+                let member = &mut self.members[index];
+                let rejoined = member
+                    .connect
+                    .as_ref()
+                    .map_or(false, |old| old.session == connect.session);
+                if rejoined {
+                    // The transport of a rejoined session may have changed.
+                    if let Some(ref mut session) = member.session {
+                        session.receiver = (*connect).clone();
+                    }
+                } else {
+                    // A new app session has no media loaded.
+                    member.session = None;
+                    member.position = None;
+                }
+                member.connect = Some(*connect);
+                events.push(SyncStatus::MemberConnected(index));
+                let connected = self.live().all(|member| member.connect.is_some());
+                match self.phase {
+                    Phase::Connecting if connected => {
+                        self.phase = Phase::Connected;
+                        events.push(SyncStatus::Connected);
+                    }
+                    // A member that rejoined its session after its connection
+                    // dropped resumes at the position of the leader.
+                    Phase::Playing | Phase::Paused if rejoined => self.align(index),
+                    _ => {}
+                }
+            }
+            Status::MediaConnected(session) => {
+                self.members[index].session = Some(*session);
+                match self.phase {
+                    Phase::Loading => self.start_loaded(&mut events),
+                    // A member that reconnected or loaded late joins at the
+                    // position of the leader.
+                    Phase::Playing | Phase::Paused => self.align(index),
+                    _ => {}
+                }
+            }
+            Status::MediaState(state) => {
+                let member = &mut self.members[index];
+                match member.session {
+                    Some(ref session) if session.session == state.media_session_id => {
+                        member.position = Some(Position::new(&state));
+                    }
+                    // Status of a previous media session.
+                    _ => return events,
+                }
+                if self.phase == Phase::Playing && self.leader() == Some(index) {
+                    events.push(SyncStatus::Elapsed(state.current_time));
+                    self.correct_drift();
+                }
+            }
+            status @ Status::LoadFailed { .. } => {
+                // The other members start without this one.
+                self.members[index].loading = false;
+                events.push(SyncStatus::Member(index, status));
+                self.start_loaded(&mut events);
+            }
+            status @ Status::SessionLost { .. } => {
+                self.members[index].reset();
+                if !self.has_session() {
+                    self.phase = Phase::Connecting;
+                }
+                events.push(SyncStatus::Member(index, status));
+                self.start_loaded(&mut events);
+            }
+            status @ Status::Disconnected { .. } => {
+                self.members[index].reset();
+                self.members[index].closed = true;
+                events.push(SyncStatus::Member(index, status));
+                self.start_loaded(&mut events);
+                if self.live().next().is_none() {
+                    events.push(SyncStatus::Disconnected);
+                }
+            }
+            status => events.push(SyncStatus::Member(index, status)),
+        }
+        events
+    }
+
+    /// Members whose connection is open.
+    fn live(&self) -> impl Iterator<Item = &Member> {
+        self.members.iter().filter(|member| !member.closed)
+    }
+
+    /// Index of the member whose position is the game clock.
+    fn leader(&self) -> Option<usize> {
+        self.members
+            .iter()
+            .position(|member| !member.closed && member.session.is_some())
+    }
+
+    /// Start the group once every member that was sent the current track has
+    /// loaded it. Members that failed to load it or lost their app session
+    /// are not waited for.
+    fn start_loaded(&mut self, events: &mut Vec<SyncStatus>) {
+        if self.phase != Phase::Loading {
+            return;
+        }
+        let mut loading = self.live().filter(|member| member.loading).peekable();
+        let loaded = loading.peek().is_some() && loading.all(|member| member.session.is_some());
+        if loaded {
+            self.start();
+            events.push(SyncStatus::Started);
+        }
+    }
+
+    /// Start every member at the position of the member furthest into the
+    /// track. Members autoplay once loaded, so this avoids rewinding any of
+    /// them.
+    fn start(&mut self) {
+        let now = Instant::now();
+        let position = self
+            .members
+            .iter()
+            .filter_map(|member| member.position)
+            .map(|position| position.extrapolate(now))
+            .fold(0.0, f64::max);
+        debug!("starting sync group at {:.2}s", position);
+        for member in &mut self.members {
+            member.start(position, true);
+            member.position = None;
+        }
+        self.phase = Phase::Playing;
+        self.corrected = Some(now);
+    }
+
+    /// Seek the member with index `index` to the position of the leader.
+    fn align(&mut self, index: usize) {
+        let now = Instant::now();
+        let leader = self
+            .leader()
+            .filter(|&leader| leader != index)
+            .and_then(|leader| self.members[leader].position);
+        if let Some(leader) = leader {
+            let member = &mut self.members[index];
+            debug!("aligning {} with sync group", member.name);
+            member.start(leader.extrapolate(now), self.phase == Phase::Playing);
+            member.position = None;
+        }
+    }
+
+    /// Seek members that drifted from the leader back to its position.
+    fn correct_drift(&mut self) {
+        let now = Instant::now();
+        match self.corrected {
+            Some(corrected) if now.duration_since(corrected) < CORRECTION_INTERVAL => return,
+            _ => {}
+        }
+        let leader = match self
+            .leader()
+            .and_then(|leader| self.members[leader].position)
+        {
+            Some(leader) => leader.extrapolate(now),
+            None => return,
+        };
+        let positions = self
+            .members
+            .iter()
+            .map(|member| {
+                member
+                    .position
+                    .filter(|_| !member.closed && member.session.is_some())
+                    .map(|position| position.extrapolate(now))
+            })
+            .collect::<Vec<_>>();
+        for index in drifted(leader, &positions) {
+            let member = &mut self.members[index];
+            warn!(
+                "{} drifted {:.2}s from sync group",
+                member.name,
+                positions[index].unwrap_or(leader) - leader
+            );
+            member.start(leader, true);
+            member.position = None;
+        }
+        self.corrected = Some(now);
+    }
+}
+
+/// Indexes of the members whose position is further than `DRIFT_TOLERANCE`
+/// from `leader`. Members with an unknown position are skipped.
+fn drifted(leader: f64, positions: &[Option<f64>]) -> Vec<usize> {
+    positions
+        .iter()
+        .enumerate()
+        .filter_map(|(index, position)| {
+            position
+                .filter(|position| (position - leader).abs() > DRIFT_TOLERANCE)
+                .map(|_| index)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use cast_client::mock::MockReceiver;
+    use cast_client::Status;
+    use futures::prelude::*;
+    use futures::stream::Wait;
+    use futures::sync::mpsc::UnboundedReceiver;
+    use tokio::runtime::Runtime;
+    use url::Url;
+
+    use super::{drifted, SyncGroup, SyncStatus};
+    use crate::chromecast::media_server::Route;
+    use crate::chromecast::{CastAddr, CastAddrBuilder};
+    use crate::{Image, Tags, Track};
+
+    struct Round;
+
+    impl Track for Round {
+        fn id(&self) -> &str {
+            "round-1"
+        }
+
+        fn duration(&self) -> Duration {
+            Duration::from_secs(60)
+        }
+
+        fn tags(&self) -> Option<Tags> {
+            Some(Tags::default())
+        }
+
+        fn cover(&self) -> Option<Image> {
+            None
+        }
+
+        fn stream(&self) -> Option<Box<dyn Read>> {
+            None
+        }
+
+        fn content_type(&self) -> String {
+            "audio/mpeg".to_owned()
+        }
+    }
+
+    fn cast_addr(name: &str, addr: SocketAddr) -> CastAddr {
+        CastAddrBuilder::default()
+            .name(name.to_owned())
+            .addr(addr.ip())
+            .port(addr.port())
+            .into_castaddr()
+            .unwrap()
+    }
+
+    /// Feed member statuses to `group` until it emits a status matching
+    /// `done`.
+    fn handle_until<F>(
+        group: &mut SyncGroup,
+        status: &mut Wait<UnboundedReceiver<(usize, Status)>>,
+        mut done: F,
+    ) where
+        F: FnMut(&SyncStatus) -> bool,
+    {
+        loop {
+            let (index, status) = status.next().unwrap().unwrap();
+            if group.handle(index, status).iter().any(&mut done) {
+                return;
+            }
+        }
+    }
+
+    fn connected_group(
+        runtime: &mut Runtime,
+        receivers: &[&MockReceiver],
+    ) -> (SyncGroup, Wait<UnboundedReceiver<(usize, Status)>>) {
+        let configs = receivers
+            .iter()
+            .enumerate()
+            .map(|(index, receiver)| cast_addr(&format!("cast-{}", index), receiver.addr()))
+            .collect::<Vec<_>>();
+        let router = Route::new(Url::parse("http://127.0.0.1:8000/").unwrap());
+        let (mut group, status, connect) = SyncGroup::with_router(router, &configs);
+        runtime.block_on(connect).unwrap();
+        let mut status = status.wait();
+        handle_until(&mut group, &mut status, |status| match *status {
+            SyncStatus::Connected => true,
+            _ => false,
+        });
+        (group, status)
+    }
+
+    #[test]
+    fn finds_drifted_members() {
+        let positions = [Some(12.0), Some(12.1), None, Some(11.5), Some(12.6)];
+        assert_eq!(drifted(12.0, &positions), vec![3, 4]);
+    }
+
+    #[test]
+    fn starts_without_member_that_failed_to_load() {
+        let mut runtime = Runtime::new().unwrap();
+        let (first, serve) = MockReceiver::bind().unwrap();
+        runtime.spawn(serve);
+        let (second, serve) = MockReceiver::bind().unwrap();
+        runtime.spawn(serve);
+        let (mut group, mut status) = connected_group(&mut runtime, &[&first, &second]);
+
+        second.fail_next_load();
+        group.load(&Round).unwrap();
+        let mut failed = false;
+        handle_until(&mut group, &mut status, |status| match *status {
+            SyncStatus::Member(1, Status::LoadFailed { .. }) => {
+                failed = true;
+                false
+            }
+            SyncStatus::Started => true,
+            _ => false,
+        });
+        assert!(failed);
+        first.shutdown();
+        second.shutdown();
+    }
+
+    #[test]
+    fn starts_without_member_that_lost_its_session() {
+        let mut runtime = Runtime::new().unwrap();
+        let (first, serve) = MockReceiver::bind().unwrap();
+        runtime.spawn(serve);
+        let (second, serve) = MockReceiver::bind().unwrap();
+        runtime.spawn(serve);
+        let (mut group, mut status) = connected_group(&mut runtime, &[&first, &second]);
+
+        second.take_over("233637DE", "YouTube");
+        handle_until(&mut group, &mut status, |status| match *status {
+            SyncStatus::Member(1, Status::SessionLost { .. }) => true,
+            _ => false,
+        });
+        // The track is only sent to the member that still has a session.
+        group.load(&Round).unwrap();
+        handle_until(&mut group, &mut status, |status| match *status {
+            SyncStatus::Started => true,
+            _ => false,
+        });
+        first.shutdown();
+        second.shutdown();
+    }
+
+    #[test]
+    fn load_fails_without_session() {
+        let mut runtime = Runtime::new().unwrap();
+        let (receiver, serve) = MockReceiver::bind().unwrap();
+        runtime.spawn(serve);
+        let (mut group, mut status) = connected_group(&mut runtime, &[&receiver]);
+
+        receiver.take_over("233637DE", "YouTube");
+        handle_until(&mut group, &mut status, |status| match *status {
+            SyncStatus::Member(0, Status::SessionLost { .. }) => true,
+            _ => false,
+        });
+        assert!(!group.has_session());
+        assert!(group.load(&Round).is_err());
+        receiver.shutdown();
+    }
+}
@@ -1,12 +1,26 @@
 export const CLEAR_MEDIA = "CLEAR_MEDIA";
+export const REMOVE_GROUP_MEMBER = "REMOVE_GROUP_MEMBER";
 export const SET_ACTIVE_DEVICE = "SET_ACTIVE_DEVICE";
 export const SET_CONFIG = "SET_CONFIG";
 export const SET_ELAPSED = "SET_ELAPSED";
+export const SET_GROUP_MEMBER = "SET_GROUP_MEMBER";
+export const SET_GROUP_MEMBERS = "SET_GROUP_MEMBERS";
 export const SET_MEDIA = "SET_MEDIA";
+export const SET_MEMBER_VOLUME = "SET_MEMBER_VOLUME";
 export const SET_PLAYBACK = "SET_PLAYBACK";
+export const SESSION_LOST = "SESSION_LOST";
+export const SESSION_RESTORED = "SESSION_RESTORED";
 export const SET_PLAYLIST = "SET_PLAYLIST";
 export const TOGGLE_PLAYBACK = "TOGGLE_PLAYBACK";
 
+export function sessionLost(device, name, takenOverBy) {
+  return { type: SESSION_LOST, device, name, takenOverBy };
+}
+
+export function sessionRestored(device) {
+  return { type: SESSION_RESTORED, device };
+}
+
 export function setActiveDevice(kind, name) {
   return { type: SET_ACTIVE_DEVICE, kind, name };
 }
@@ -19,6 +33,10 @@ export function setElapsed(elapsed) {
   return { type: SET_ELAPSED, elapsed };
 }
 
+export function setMemberVolume(device, deviceId, { level, muted }) {
+  return { type: SET_MEMBER_VOLUME, device, deviceId, level, muted };
+}
+
 export function setMedia(media, elapsed) {
   return { type: SET_MEDIA, media, elapsed };
 }
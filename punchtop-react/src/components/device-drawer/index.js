@@ -12,6 +12,7 @@ import Cast from "@material-ui/icons/Cast";
 import CastConnected from "@material-ui/icons/CastConnected";
 import Computer from "@material-ui/icons/Computer";
 
+import GroupMembers from "../group-members";
 import { setActiveDevice } from "../../actions";
 
 const DEVICE_KIND_CAST = "cast";
@@ -79,6 +80,7 @@ class DeviceDrawer extends React.Component {
               </ListItem>
             ))}
         </List>
+        <GroupMembers />
         <Divider />
         <List>
           {devices
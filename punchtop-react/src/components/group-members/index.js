@@ -0,0 +1,69 @@
+import React from "react";
+import { connect } from "react-redux";
+import List from "@material-ui/core/List";
+import ListItem from "@material-ui/core/ListItem";
+import ListItemSecondaryAction from "@material-ui/core/ListItemSecondaryAction";
+import ListItemText from "@material-ui/core/ListItemText";
+import ListSubheader from "@material-ui/core/ListSubheader";
+import IconButton from "@material-ui/core/IconButton";
+import VolumeOff from "@material-ui/icons/VolumeOff";
+import VolumeUp from "@material-ui/icons/VolumeUp";
+
+import style from "./style.css";
+import { setMemberVolume } from "../../actions";
+
+export const Member = ({ member, setVolume }) => (
+  <ListItem>
+    <ListItemText primary={member.name} />
+    <input
+      className={style.volume}
+      type="range"
+      min={0}
+      max={1}
+      step={0.05}
+      value={member.level || 0}
+      aria-label={`${member.name} volume`}
+      onChange={event => setVolume({ level: Number(event.target.value) })}
+    />
+    <ListItemSecondaryAction>
+      <IconButton
+        aria-label={member.muted ? "Unmute" : "Mute"}
+        onClick={() => setVolume({ muted: !member.muted })}
+      >
+        {member.muted ? <VolumeOff /> : <VolumeUp />}
+      </IconButton>
+    </ListItemSecondaryAction>
+  </ListItem>
+);
+
+const GroupMembers = ({ groups, setVolume }) =>
+  Object.keys(groups).map(device => (
+    <List
+      key={device}
+      subheader={<ListSubheader>{groups[device].name}</ListSubheader>}
+    >
+      {groups[device].members.map(member => (
+        <Member
+          key={member.deviceId}
+          member={member}
+          setVolume={volume =>
+            setVolume(Number(device), member.deviceId, volume)
+          }
+        />
+      ))}
+    </List>
+  ));
+
+const mapStateToProps = state => ({
+  groups: state.punchtop.device.groups
+});
+
+const mapDispatchToProps = dispatch => ({
+  setVolume: (device, deviceId, volume) =>
+    dispatch(setMemberVolume(device, deviceId, volume))
+});
+
+export default connect(
+  mapStateToProps,
+  mapDispatchToProps
+)(GroupMembers);
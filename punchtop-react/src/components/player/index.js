@@ -34,7 +34,22 @@ export const ElapsedBar = ({ elapsed, duration }) => (
   </div>
 );
 
-const Player = ({ media, isPlaying, elapsed, duration, toggle }) => (
+export const SessionLost = ({ name, takenOverBy }) => (
+  <div className={style.sessionLost}>
+    {takenOverBy
+      ? `${takenOverBy} took over ${name}`
+      : `The game was stopped on ${name}`}
+  </div>
+);
+
+const Player = ({
+  media,
+  isPlaying,
+  elapsed,
+  duration,
+  lost,
+  toggle
+}) => (
   <div>
     <ReactCSSTransitionReplace
       transitionName="cross-fade"
@@ -67,6 +82,13 @@ const Player = ({ media, isPlaying, elapsed, duration, toggle }) => (
             <Spacer height="0.4em" />
             <div className={style.artist}>{media?.artist}</div>
           </div>
+          {Object.keys(lost).map(device => (
+            <SessionLost
+              key={device}
+              name={lost[device].name}
+              takenOverBy={lost[device].takenOverBy}
+            />
+          ))}
           <Spacer height="0.75em" />
           <div className={style.player}>
             <ElapsedBar
@@ -98,7 +120,8 @@ const mapStateToProps = state => ({
   media: state.punchtop.media.current,
   isPlaying: state.punchtop.player.isPlaying,
   elapsed: state.punchtop.player.elapsed,
-  duration: state.punchtop.config.duration
+  duration: state.punchtop.config.duration,
+  lost: state.punchtop.device.lost
 });
 
 const mapDispatchToProps = dispatch => ({
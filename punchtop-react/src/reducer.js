@@ -3,10 +3,16 @@ import { combineReducers } from "redux";
 
 import {
   CLEAR_MEDIA,
+  REMOVE_GROUP_MEMBER,
+  SESSION_LOST,
+  SESSION_RESTORED,
   SET_ACTIVE_DEVICE,
   SET_CONFIG,
   SET_ELAPSED,
+  SET_GROUP_MEMBER,
+  SET_GROUP_MEMBERS,
   SET_MEDIA,
+  SET_MEMBER_VOLUME,
   SET_PLAYBACK,
   SET_PLAYLIST,
   TOGGLE_PLAYBACK
@@ -26,6 +32,12 @@ const initialState = {
   },
   device: {
     active: {},
+    // Speakers of each Cast group, keyed by the index of the group in the
+    // sync group.
+    groups: {},
+    // Devices taken over by another sender, keyed by the index of the device
+    // in the sync group.
+    lost: {},
     all: [
       { kind: "cast", name: "TV" },
       { kind: "local", name: "quadbox" },
@@ -35,6 +47,18 @@ const initialState = {
   }
 };
 
+const updateGroup = (state, index, update) => {
+  const group = state.device.groups[index];
+  if (!group) {
+    return state;
+  }
+  const groups = Object.assign({}, state.device.groups, {
+    [index]: Object.assign({}, group, { members: update(group.members) })
+  });
+  const device = Object.assign({}, state.device, { groups });
+  return Object.assign({}, state, { device });
+};
+
 const reducer = (state = initialState, action) => {
   switch (action.type) {
     case CLEAR_MEDIA: {
@@ -46,6 +70,28 @@ const reducer = (state = initialState, action) => {
       });
       return Object.assign({}, state, { media, player });
     }
+    case REMOVE_GROUP_MEMBER:
+      return updateGroup(state, action.device, members =>
+        members.filter(member => member.deviceId !== action.deviceId)
+      );
+    case SESSION_LOST: {
+      // Another sender controls the device. The backend pauses the game if
+      // no other device is left, so there is nothing to invoke.
+      const lost = Object.assign({}, state.device.lost, {
+        [action.device]: {
+          name: action.name,
+          takenOverBy: action.takenOverBy || null
+        }
+      });
+      const device = Object.assign({}, state.device, { lost });
+      return Object.assign({}, state, { device });
+    }
+    case SESSION_RESTORED: {
+      const lost = Object.assign({}, state.device.lost);
+      delete lost[action.device];
+      const device = Object.assign({}, state.device, { lost });
+      return Object.assign({}, state, { device });
+    }
     case SET_ACTIVE_DEVICE: {
       const config = Object.assign({}, state.device, {
         kind: action.kind,
@@ -59,6 +105,46 @@ const reducer = (state = initialState, action) => {
       });
       return Object.assign({}, state, { config });
     }
+    case SET_GROUP_MEMBER:
+      return updateGroup(state, action.device, members =>
+        members.some(member => member.deviceId === action.member.deviceId)
+          ? members.map(member =>
+              member.deviceId === action.member.deviceId
+                ? action.member
+                : member
+            )
+          : [...members, action.member]
+      );
+    case SET_GROUP_MEMBERS: {
+      const groups = Object.assign({}, state.device.groups, {
+        [action.device]: { name: action.name, members: action.members }
+      });
+      const device = Object.assign({}, state.device, { groups });
+      return Object.assign({}, state, { device });
+    }
+    case SET_MEMBER_VOLUME: {
+      const { device, deviceId, level, muted } = action;
+      global.external.invoke(
+        JSON.stringify({
+          type: SET_MEMBER_VOLUME,
+          device,
+          deviceId,
+          level,
+          muted
+        })
+      );
+      // The group confirms the change with a `SET_GROUP_MEMBER`.
+      return updateGroup(state, device, members =>
+        members.map(member =>
+          member.deviceId === deviceId
+            ? Object.assign({}, member, {
+                level: level === undefined ? member.level : level,
+                muted: muted === undefined ? member.muted : muted
+              })
+            : member
+        )
+      );
+    }
     case SET_MEDIA: {
       const player = Object.assign({}, state.player, {
         elapsed: clamp(action.elapsed || 0, 0, state.config.duration)
@@ -1,19 +1,19 @@
 use std::time::Duration;
 
 use base64;
-use cast_client::{MediaConnection, ReceiverConnection, Status};
+use cast_client::{multizone, CommandError, Status};
 use floating_duration::TimeAsFloat;
-use punchtop_audio::chromecast::Device as CastDevice;
+use futures::future::{self, Either};
+use futures::Future;
+use punchtop_audio::chromecast::{SyncGroup, SyncStatus};
 use punchtop_audio::Track;
 use punchtop_playlist::fs::{self, Playlist};
-use serde_derive::Serialize;
+use serde_derive::{Deserialize, Serialize};
 use stream_util::{self, Trigger, Valve};
 
 pub struct State {
     playlist: Playlist,
-    client: Option<CastDevice>,
-    connect: Option<ReceiverConnection>,
-    session: Option<MediaConnection>,
+    client: Option<SyncGroup>,
     shutdown: Option<Trigger>,
 }
 
@@ -42,8 +42,6 @@ impl Controller {
         let state = State {
             playlist,
             client: None,
-            connect: None,
-            session: None,
             shutdown: Some(trigger),
         };
         let events = vec![];
@@ -60,9 +58,9 @@ impl Controller {
 }
 
 impl Controller {
-    pub fn set_client(&mut self, client: CastDevice) {
+    pub fn set_client(&mut self, client: SyncGroup) {
         if let Some(mut old) = std::mem::replace(&mut self.state.client, Some(client)) {
-            let _ = old.shutdown();
+            tokio::spawn(old.shutdown());
         }
     }
 
@@ -81,36 +79,59 @@ impl Controller {
 // Playback controls
 impl Controller {
     fn load_next(&mut self) -> Option<(u64, fs::Track)> {
-        let client = self.state.client.as_ref()?;
-        let connect = self.state.connect.as_ref()?;
+        let client = self.state.client.as_mut()?;
+        let deadline = self.config.duration.as_fractional_secs();
         self.state.playlist.next().map(|(cursor, track)| {
-            let _ = client.load(&connect, &track);
+            let _ = client.load(&track);
+            // Status is polled faster near the time limit so rounds advance
+            // on time.
+            client.set_deadline(Some(deadline));
             (cursor, track)
         })
     }
 
-    pub fn pause(&self) {
-        if let Some(ref client) = self.state.client {
-            if let Some(ref session) = self.state.session {
-                let _ = client.pause(session);
-            }
+    pub fn pause(&mut self) {
+        if let Some(ref mut client) = self.state.client {
+            client.pause();
         }
     }
 
-    pub fn play(&self) {
-        if let Some(ref client) = self.state.client {
-            if let Some(ref session) = self.state.session {
-                let _ = client.play(session);
-            }
+    pub fn play(&mut self) {
+        if let Some(ref mut client) = self.state.client {
+            client.play();
         }
     }
 
+    /// Set the volume of the speaker with id `device_id` in the Cast group
+    /// with index `device`. The returned future must be spawned; it logs
+    /// whether the group acknowledged the change.
+    pub fn set_member_volume(
+        &self,
+        device: usize,
+        device_id: &str,
+        level: Option<f32>,
+        muted: Option<bool>,
+    ) -> impl Future<Item = (), Error = ()> {
+        let update = match self.state.client {
+            Some(ref client) => {
+                Either::A(client.set_member_volume(device, device_id, level, muted))
+            }
+            None => Either::B(future::err(CommandError::Closed)),
+        };
+        let device_id = device_id.to_owned();
+        update.map_err(move |err| {
+            warn!(
+                "Could not set volume of cast group member {}: {}",
+                device_id, err
+            )
+        })
+    }
+
     fn shutdown(&mut self) {
         if let Some(ref mut client) = self.state.client {
-            if let Some(ref session) = self.state.session {
-                let _ = client.stop(session);
-            }
-            let _ = client.shutdown();
+            // The stop commands are queued before the valve is triggered,
+            // so the game stream ending does not race them.
+            tokio::spawn(client.shutdown());
         }
         if let Some(shutdown) = self.state.shutdown.take() {
             shutdown.terminate();
@@ -120,14 +141,26 @@ impl Controller {
 }
 
 impl Controller {
-    pub fn handle(&mut self, event: Status) -> Vec<Event> {
-        use cast_client::Status::*;
+    pub fn handle(&mut self, member: usize, event: Status) -> Vec<Event> {
         if !self.events.is_empty() {
             debug!("app backlog of {} events", self.events.len());
         }
-        match event {
-            Connected(connect) => {
-                self.state.connect = Some(*connect);
+        let statuses = match self.state.client {
+            Some(ref mut client) => client.handle(member, event),
+            None => vec![],
+        };
+        for status in statuses {
+            self.handle_sync(status);
+        }
+        if self.lifecycle == Lifecycle::Uninitialized {
+            return vec![];
+        }
+        std::mem::replace(&mut self.events, vec![])
+    }
+
+    fn handle_sync(&mut self, status: SyncStatus) {
+        match status {
+            SyncStatus::Connected => {
                 if let Some((cursor, track)) = self.load_next() {
                     self.events.push(Event::SetMedia {
                         media: media(&track, cursor),
@@ -135,21 +168,16 @@ impl Controller {
                     self.events.push(Event::SetPlayback { is_playing: true });
                 }
             }
-            MediaConnected(session) => {
-                self.state.session = Some(*session);
-                self.play();
+            SyncStatus::MemberConnected(member) => {
+                self.events.push(Event::SessionRestored { device: member });
             }
-            MediaState(ref state)
-                if state.current_time < self.config.duration.as_fractional_secs() =>
-            {
-                self.events.push(Event::SetElapsed {
-                    elapsed: state.current_time,
-                });
+            SyncStatus::Started => info!("Playback started on all cast devices"),
+            SyncStatus::Elapsed(elapsed) if elapsed < self.config.duration.as_fractional_secs() => {
+                self.events.push(Event::SetElapsed { elapsed });
             }
-            MediaState(_) if self.state.session.is_some() => {
+            SyncStatus::Elapsed(_) => {
                 info!("Time limit reached. Advancing game");
                 if let Some((cursor, track)) = self.load_next() {
-                    self.state.session = None;
                     info!("Advancing to track {}", cursor);
                     self.events.push(Event::SetMedia {
                         media: media(&track, cursor),
@@ -161,13 +189,98 @@ impl Controller {
                     self.shutdown();
                 }
             }
-            MediaState(_) => {}
-            event => warn!("Got unknown app event: {:?}", event),
+            SyncStatus::Member(member, status) => self.handle_member(member, status),
+            SyncStatus::Disconnected => {
+                warn!("All cast devices disconnected. Shutting down");
+                self.events.push(Event::ClearMedia);
+                self.events.push(Event::Shutdown);
+                self.shutdown();
+            }
         }
-        if self.lifecycle == Lifecycle::Uninitialized {
-            return vec![];
+    }
+
+    fn handle_member(&mut self, member: usize, event: Status) {
+        use cast_client::Status::*;
+        let name = self
+            .state
+            .client
+            .as_ref()
+            .and_then(|client| client.name(member))
+            .unwrap_or_default()
+            .to_owned();
+        match event {
+            SessionLost {
+                by_app,
+                display_name,
+            } => {
+                warn!(
+                    "Cast session on {} taken over by {:?} ({:?})",
+                    name, display_name, by_app
+                );
+                // The receiver no longer plays our media. The other devices
+                // keep playing, so only this device is flagged.
+                self.events.push(Event::SessionLost {
+                    device: member,
+                    name,
+                    taken_over_by: display_name.or(by_app),
+                });
+                let has_session = self
+                    .state
+                    .client
+                    .as_ref()
+                    .map_or(false, SyncGroup::has_session);
+                if !has_session {
+                    info!("No cast device has a session. Pausing game");
+                    self.pause();
+                    self.events.push(Event::SetPlayback { is_playing: false });
+                }
+            }
+            Error(err) => warn!("Cast device {} error: {}", name, err),
+            Input {
+                is_active_input,
+                is_stand_by,
+            } if is_active_input == Some(false) || is_stand_by == Some(true) => {
+                // The TV switched to another input or was turned off. Pause
+                // the game until the user resumes it.
+                info!(
+                    "Cast device {} is no longer the active input. Pausing game",
+                    name
+                );
+                self.pause();
+                self.events.push(Event::SetPlayback { is_playing: false });
+            }
+            Input { .. } => {}
+            GroupStatus(members) => {
+                info!("Cast group {} has {} speakers", name, members.len());
+                self.events.push(Event::SetGroupMembers {
+                    device: member,
+                    name,
+                    members: members.into_iter().map(group_member).collect(),
+                });
+            }
+            GroupMemberUpdated(update) => {
+                debug!(
+                    "Cast group member {} volume={:?}",
+                    update.name, update.volume.level
+                );
+                self.events.push(Event::SetGroupMember {
+                    device: member,
+                    member: group_member(update),
+                });
+            }
+            GroupMemberRemoved { device_id } => {
+                info!("Cast group member {} left {}", device_id, name);
+                self.events.push(Event::RemoveGroupMember {
+                    device: member,
+                    device_id,
+                });
+            }
+            Reconnecting => warn!("Lost connection to cast device {}. Reconnecting", name),
+            Disconnected { reason } => warn!("Cast device {} disconnected: {:?}", name, reason),
+            // The group resumes the device once it rejoins the app session.
+            Reconnected => info!("Reconnected to cast device {}", name),
+            event => warn!("Got unknown event from {}: {:?}", name, event),
         }
-        std::mem::replace(&mut self.events, vec![])
     }
 }
 
@@ -190,17 +303,55 @@ fn media(track: &fs::Track, cursor: u64) -> Media {
     }
 }
 
+/// Speaker of a Cast group as shown in the device drawer.
+fn group_member(member: multizone::Member) -> GroupMember {
+    GroupMember {
+        device_id: member.device_id,
+        name: member.name,
+        level: member.volume.level,
+        muted: member.volume.muted,
+    }
+}
+
+/// Commands from the webview that carry arguments, encoded as JSON.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Invoke {
+    #[serde(rename_all = "camelCase")]
+    SetMemberVolume {
+        device: usize,
+        device_id: String,
+        level: Option<f32>,
+        muted: Option<bool>,
+    },
+}
+
 #[derive(Serialize, Debug, PartialEq)]
 #[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
 #[allow(dead_code)]
 pub enum Event {
     ClearMedia,
+    #[serde(rename_all = "camelCase")]
+    RemoveGroupMember {
+        device: usize,
+        device_id: String,
+    },
     SetConfig {
         duration: f64,
     },
     SetElapsed {
         elapsed: f64,
     },
+    /// Speakers of the Cast group with index `device`.
+    SetGroupMembers {
+        device: usize,
+        name: String,
+        members: Vec<GroupMember>,
+    },
+    SetGroupMember {
+        device: usize,
+        member: GroupMember,
+    },
     SetMedia {
         media: Media,
     },
@@ -211,6 +362,17 @@ pub enum Event {
     SetPlaylist {
         name: String,
     },
+    /// Another sender took over the device with index `device`.
+    #[serde(rename_all = "camelCase")]
+    SessionLost {
+        device: usize,
+        name: String,
+        taken_over_by: Option<String>,
+    },
+    /// The device with index `device` is connected to an app session again.
+    SessionRestored {
+        device: usize,
+    },
     Shutdown,
     TogglePlayback,
 }
@@ -224,6 +386,15 @@ pub struct Media {
     cover: Option<Image>,
 }
 
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupMember {
+    device_id: String,
+    name: String,
+    level: Option<f64>,
+    muted: Option<bool>,
+}
+
 #[derive(Serialize, Debug, PartialEq)]
 pub struct Image {
     url: String,
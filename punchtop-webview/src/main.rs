@@ -13,7 +13,7 @@ use std::time::Duration;
 use floating_duration::TimeAsFloat;
 use futures::prelude::*;
 use futures::Stream;
-use punchtop_audio::chromecast::{devices, Device};
+use punchtop_audio::chromecast::{devices, SyncGroup};
 use punchtop_playlist::fs;
 use stream_util::Drainable;
 use tokio::runtime::Runtime;
@@ -21,9 +21,10 @@ use web_view::*;
 
 mod app;
 
-use crate::app::{Config, Controller, Event};
+use crate::app::{Config, Controller, Event, Invoke};
 
-const CAST: &str = "Kitchen Speaker";
+/// Chromecasts that play the game in sync.
+const CASTS: &[&str] = &["Kitchen Speaker"];
 const WEBVIEW_HTML: &str = include_str!(concat!(env!("OUT_DIR"), "/index.html"));
 
 fn main() {
@@ -33,13 +34,17 @@ fn main() {
         duration: Duration::new(60, 0),
         iterations: 60,
     };
-    let player = devices().find(|p| p.name == CAST);
-    let player = if let Some(player) = player {
-        player
-    } else {
-        eprintln!("Could not find chromecast named {}", CAST);
-        ::std::process::exit(1);
-    };
+    let mut found = devices().collect::<Vec<_>>();
+    let mut players = Vec::with_capacity(CASTS.len());
+    for name in CASTS {
+        match found.iter().position(|p| p.name == *name) {
+            Some(index) => players.push(found.swap_remove(index)),
+            None => {
+                eprintln!("Could not find chromecast named {}", name);
+                ::std::process::exit(1);
+            }
+        }
+    }
     let playlist = fs::dir::new(
         Path::new("/Users/lopopolo/Downloads/Party Mix"),
         config.duration,
@@ -47,19 +52,25 @@ fn main() {
     )
     .or_else(|| fs::music::new(config.duration, config.iterations))
     .expect("playlist");
-    let (client, chan, connect) = match Device::connect(&player, playlist.registry()) {
+    let casts = CASTS.join(", ");
+    let (client, chan, connect) = match SyncGroup::connect(&players, playlist.registry()) {
         Ok(connect) => connect,
         Err(err) => {
             warn!("chromecast connect error: {:?}", err);
-            eprintln!("Could not connect to chromecast named {}", CAST);
+            eprintln!("Could not connect to chromecasts named {}", casts);
             ::std::process::exit(1);
         }
     };
-    rt.spawn(connect);
+    rt.spawn(connect.map_err(move |err| {
+        warn!("chromecast connect error: {}", err);
+        eprintln!("Could not connect to chromecasts named {}: {}", casts, err);
+        ::std::process::exit(1);
+    }));
     let (mut controller, valve) = Controller::new(config, playlist);
     controller.set_client(client);
     let controller = Arc::new(Mutex::new(controller));
     let handler_controller = Arc::clone(&controller);
+    let executor = rt.executor();
     let io_controller = Arc::clone(&controller);
     let mut webview = web_view::builder()
         .title("Punchtop")
@@ -89,7 +100,18 @@ fn main() {
                 }
                 "play" => controller.play(),
                 "pause" => controller.pause(),
-                _ => unimplemented!(),
+                arg => match serde_json::from_str(arg) {
+                    Ok(Invoke::SetMemberVolume {
+                        device,
+                        device_id,
+                        level,
+                        muted,
+                    }) => {
+                        let update = controller.set_member_volume(device, &device_id, level, muted);
+                        executor.spawn(update);
+                    }
+                    Err(err) => warn!("unknown webview invoke {}: {}", arg, err),
+                },
             };
             Ok(())
         })
@@ -97,9 +119,9 @@ fn main() {
         .expect("build webview");
     webview.set_color((15, 55, 55));
     let ui_handle = webview.handle();
-    let play_loop = chan.drain(valve).for_each(move |event| {
+    let play_loop = chan.drain(valve).for_each(move |(member, event)| {
         let mut controller = io_controller.lock().map_err(|_| ())?;
-        for event in controller.handle(event) {
+        for event in controller.handle(member, event) {
             let _ = ui_handle.dispatch(move |webview| {
                 dispatch_in_webview(webview, &event);
                 if event == Event::Shutdown {
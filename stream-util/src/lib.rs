@@ -107,12 +107,20 @@
 //! convenience for generating a [`Future`] for the [`drain`](Drainable::drain)
 //! and [`cancel`](Cancelable::cancel) combinators that resolves when
 //! triggered.
+//!
+//! # `std::future`
+//!
+//! With the `std-future` feature, the `std_future` module provides the same
+//! combinators for [`std::future::Future`] and `futures` 0.3 streams.
 
 use futures::future::Shared;
 use futures::prelude::*;
 use futures::sync::mpsc::{Receiver, UnboundedReceiver};
 use futures::sync::oneshot;
 
+#[cfg(feature = "std-future")]
+pub mod std_future;
+
 /// A remote trigger for draining or canceling a [`Stream`] with a [`Valve`].
 ///
 /// `Trigger` implements [`Drop`] and will trigger when it goes out of scope.
@@ -0,0 +1,255 @@
+//! [`Drain`], [`Cancel`] and [`Valve`] for [`std::future::Future`] and
+//! `futures` 0.3 [`Stream`]s.
+//!
+//! The combinators behave like their `futures` 0.1 counterparts in the crate
+//! root. Streams in `futures` 0.3 have no error type, so [`Drain`] and
+//! [`Cancel`] yield the items of the underlying stream directly.
+//!
+//! The rest of the workspace still uses the `futures` 0.1 combinators. This
+//! module is provided for callers driving their streams with `futures` 0.3.
+//!
+//! This module requires the `std-future` feature.
+//!
+//! ```rust
+//! use futures03::channel::mpsc;
+//! use futures03::executor::block_on;
+//! use futures03::StreamExt;
+//! use stream_util::std_future::{self, Drainable};
+//!
+//! let (trigger, valve) = std_future::valve();
+//! let (sender, receiver) = mpsc::unbounded::<()>();
+//!
+//! sender.unbounded_send(()).unwrap();
+//! sender.unbounded_send(()).unwrap();
+//!
+//! trigger.terminate();
+//! let drained = block_on(receiver.drain(valve).collect::<Vec<_>>());
+//! assert_eq!(drained.len(), 2);
+//! ```
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures03::channel::mpsc::{Receiver, UnboundedReceiver};
+use futures03::channel::oneshot;
+use futures03::future::{FutureExt, Shared};
+use futures03::Stream;
+
+/// A remote trigger for draining or canceling a [`Stream`] with a [`Valve`].
+///
+/// `Trigger` implements [`Drop`] and will trigger when it goes out of scope.
+#[derive(Debug)]
+pub struct Trigger(Option<oneshot::Sender<()>>);
+
+impl Trigger {
+    /// Consume the `Trigger` and resolve the linked [`Valve`].
+    pub fn terminate(self) {
+        drop(self);
+    }
+}
+
+impl Drop for Trigger {
+    fn drop(&mut self) {
+        if let Some(trigger) = self.0.take() {
+            let _ = trigger.send(());
+        }
+    }
+}
+
+/// Drain or cancel a [`Stream`] when resolved by a [`Trigger`].
+///
+/// `Valve` is cloneable and may be used with multiple [`Stream`]s. Once
+/// resolved, the valve stays resolved and may be polled again.
+#[derive(Clone, Debug)]
+pub struct Valve {
+    until: Shared<oneshot::Receiver<()>>,
+    resolved: bool,
+}
+
+impl Future for Valve {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if self.resolved {
+            // `Shared` panics if polled after it has completed.
+            return Poll::Ready(());
+        }
+        // A dropped `Trigger` cancels the receiver, which also resolves the
+        // valve.
+        match Pin::new(&mut self.until).poll(cx) {
+            Poll::Ready(_) => {
+                self.resolved = true;
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Create a matching [`Trigger`] and [`Valve`] for resolving the [`Drain`] or
+/// [`Cancel`] combinators.
+pub fn valve() -> (Trigger, Valve) {
+    let (trigger, valve) = oneshot::channel();
+    let valve = Valve {
+        until: valve.shared(),
+        resolved: false,
+    };
+    (Trigger(Some(trigger)), valve)
+}
+
+/// Channel receivers that can be closed to stop accepting new messages.
+pub trait Close {
+    /// Close the receiver. Messages already in the channel can still be
+    /// received.
+    fn close(&mut self);
+}
+
+impl<T> Close for Receiver<T> {
+    fn close(&mut self) {
+        Receiver::close(self);
+    }
+}
+
+impl<T> Close for UnboundedReceiver<T> {
+    fn close(&mut self) {
+        UnboundedReceiver::close(self);
+    }
+}
+
+/// Wrapper around [`Receiver`] and [`UnboundedReceiver`] that enables the
+/// receiver to be canceled and fully drained by closing it safely.
+#[derive(Debug)]
+pub struct Drain<S, F> {
+    receiver: S,
+    until: F,
+    draining: bool,
+}
+
+impl<S, F> Stream for Drain<S, F>
+where
+    S: Stream + Close + Unpin,
+    F: Future<Output = ()> + Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if !this.draining {
+            if let Poll::Ready(()) = Pin::new(&mut this.until).poll(cx) {
+                // Drain trigger has resolved, close the underlying stream to
+                // start a graceful drain.
+                this.receiver.close();
+                this.draining = true;
+            }
+        }
+        Pin::new(&mut this.receiver).poll_next(cx)
+    }
+}
+
+/// Extension trait that exposes the [`drain`](Drainable::drain) method for
+/// [`Receiver`] and [`UnboundedReceiver`].
+pub trait Drainable: Stream + Close {
+    /// Create a new [`Stream`] that closes and drains when `trigger` resolves.
+    fn drain<F>(self, trigger: F) -> Drain<Self, F>
+    where
+        F: Future<Output = ()>,
+        Self: Sized,
+    {
+        Drain {
+            receiver: self,
+            until: trigger,
+            draining: false,
+        }
+    }
+}
+
+impl<T> Drainable for Receiver<T> {}
+impl<T> Drainable for UnboundedReceiver<T> {}
+
+/// Wrapper around [`Stream`] that enables the stream to be canceled and
+/// terminated.
+#[derive(Debug)]
+pub struct Cancel<S, F> {
+    stream: S,
+    until: F,
+}
+
+impl<S, F> Stream for Cancel<S, F>
+where
+    S: Stream + Unpin,
+    F: Future<Output = ()> + Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Poll::Ready(()) = Pin::new(&mut this.until).poll(cx) {
+            // Cancel trigger has resolved, short-circuit the underlying
+            // stream.
+            return Poll::Ready(None);
+        }
+        Pin::new(&mut this.stream).poll_next(cx)
+    }
+}
+
+/// Extension trait that exposes the [`cancel`](Cancelable::cancel) method for
+/// [`Stream`].
+pub trait Cancelable: Stream {
+    /// Create a new [`Stream`] that yields the items from the stream until
+    /// `trigger` resolves.
+    fn cancel<F>(self, trigger: F) -> Cancel<Self, F>
+    where
+        F: Future<Output = ()>,
+        Self: Sized,
+    {
+        Cancel {
+            stream: self,
+            until: trigger,
+        }
+    }
+}
+
+impl<S> Cancelable for S where S: Stream {}
+
+#[cfg(test)]
+mod tests {
+    use futures03::channel::mpsc;
+    use futures03::executor::block_on;
+    use futures03::{stream, StreamExt};
+
+    use super::{Cancelable, Drainable};
+
+    #[test]
+    fn terminate_drains_receiver() {
+        let (trigger, valve) = super::valve();
+        let (sender, receiver) = mpsc::unbounded::<()>();
+        sender.unbounded_send(()).unwrap();
+        sender.unbounded_send(()).unwrap();
+
+        trigger.terminate();
+        let drained = block_on(receiver.drain(valve).collect::<Vec<_>>());
+        assert_eq!(2_usize, drained.len());
+    }
+
+    #[test]
+    fn drop_cancels_stream() {
+        let valve = {
+            // Drop the trigger by letting it fall out of scope.
+            let (_trigger, valve) = super::valve();
+            valve
+        };
+        let items = block_on(stream::repeat(()).cancel(valve).collect::<Vec<_>>());
+        assert!(items.is_empty());
+    }
+
+    #[test]
+    fn poll_after_cancel() {
+        let (trigger, valve) = super::valve();
+        let mut items = stream::repeat(()).cancel(valve);
+
+        trigger.terminate();
+        assert_eq!(None, block_on(items.next()));
+        assert_eq!(None, block_on(items.next()));
+    }
+}